@@ -1,39 +1,107 @@
-use rodio::{
-    DeviceSinkBuilder, MixerDeviceSink,
-    buffer::SamplesBuffer,
-    queue::{SourcesQueueInput, queue},
+use rodio::{ChannelCount, DeviceSinkBuilder, MixerDeviceSink, SampleRate, Source};
+use std::{
+    collections::VecDeque,
+    num::NonZero,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use std::{num::NonZero, sync::Arc};
 
-/// Convenience wrapper around an audio queue
+use crate::resampler::Resampler;
+
+/// Rate the DSP generates samples at
+const APU_SAMPLE_RATE: u32 = 32_000;
+/// Amount of audio to try to keep buffered
+const TARGET_LATENCY: Duration = Duration::from_millis(60);
+/// Maximum amount the resampling ratio is nudged by to keep the buffer at its target size
+const MAX_RATE_ADJUST: f64 = 0.005;
+/// Number of samples the audio thread takes out of the buffer at once, so it doesn't lock it for every sample
+const BLOCK_LEN: usize = 256;
+
+/// Convenience wrapper around an audio output
 pub struct Audio {
-    /// The sample queue
-    queue: Arc<SourcesQueueInput>,
-    // stream: Stream,
+    /// Resampled samples waiting to be played
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    resampler: Resampler,
+    /// Number of samples to keep in `buffer`
+    target_len: usize,
+    // Kept so the stream stays open
     sink: MixerDeviceSink,
 }
 
+/// Source which plays the samples in the buffer, and silence when it runs out
+struct BufferSource {
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+    /// Samples taken out of the buffer which haven't been played yet
+    block: VecDeque<f32>,
+    sample_rate: SampleRate,
+}
+
+impl Iterator for BufferSource {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        if self.block.is_empty() {
+            let mut buffer = self.buffer.lock().unwrap();
+            let len = buffer.len().min(BLOCK_LEN);
+            self.block.extend(buffer.drain(..len));
+            drop(buffer);
+            if self.block.is_empty() {
+                self.block.extend(std::iter::repeat_n(0.0, BLOCK_LEN));
+            }
+        }
+        self.block.pop_front()
+    }
+}
+
+impl Source for BufferSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+    fn channels(&self) -> ChannelCount {
+        NonZero::new(1).unwrap()
+    }
+    fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
 impl Audio {
     pub fn new() -> Audio {
+        // Use the device's own sample rate, and resample to it ourselves
         let sink = DeviceSinkBuilder::from_default_device()
             .unwrap()
-            .with_sample_rate(NonZero::new(32_000).unwrap())
-            .with_channels(NonZero::new(1).unwrap())
             .open_stream()
             .expect("Unable to open default sink");
-        let (input, output) = queue(true);
-        sink.mixer().add(output);
-        Audio { queue: input, sink }
+        let sample_rate = sink.config().sample_rate();
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+        sink.mixer().add(BufferSource {
+            buffer: buffer.clone(),
+            block: VecDeque::with_capacity(BLOCK_LEN),
+            sample_rate,
+        });
+        Audio {
+            buffer,
+            resampler: Resampler::new(APU_SAMPLE_RATE, sample_rate.get()),
+            target_len: (TARGET_LATENCY.as_secs_f64() * sample_rate.get() as f64) as usize,
+            sink,
+        }
     }
     /// Append a bunch of samples to the audio queue
     pub fn push_samples(&mut self, samples: &[f32], volume: f32) {
         if samples.len() == 0 {
             return;
         }
-        self.queue.append(SamplesBuffer::new(
-            NonZero::new(1).unwrap(),
-            NonZero::new(32_000).unwrap(),
-            samples.iter().map(|s| s * volume).collect::<Vec<f32>>(),
-        ));
+        // Nudge the rate so that the buffer stays around its target size,
+        // consuming samples faster when it is too full and slower when it is running out
+        let len = self.buffer.lock().unwrap().len();
+        let fill = (len as f64 - self.target_len as f64) / self.target_len as f64;
+        let adjust = 1.0 + (fill * MAX_RATE_ADJUST).clamp(-MAX_RATE_ADJUST, MAX_RATE_ADJUST);
+        let resampled = self.resampler.process(samples, adjust);
+        self.buffer
+            .lock()
+            .unwrap()
+            .extend(resampled.into_iter().map(|s| s * volume));
     }
 }
@@ -1,6 +1,7 @@
 use crate::{
     AppWindow, DisassemblyLine, OamData,
-    utils::{bytes_to_rgb, get_binary_data, get_oam_data},
+    utils::{bytes_to_rgb, get_binary_data, get_oam_data, get_rom_info},
+    voice_history::{SCOPE_LEN, VoiceHistory, scope_image},
 };
 use closure::closure;
 use derive_new::new;
@@ -10,6 +11,7 @@ use std::{
     collections::{BTreeMap, VecDeque},
     fmt::Display,
     ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
     rc::Rc,
     sync::{
         Arc, Mutex, MutexGuard,
@@ -18,9 +20,25 @@ use std::{
     thread::{self},
     time::{Duration, Instant},
 };
-use super_yane::{Console, Cpu, InputPort, MASTER_CLOCK_SPEED_HZ, Ppu, ppu::SCREEN_RESOLUTION};
+use super_yane::{
+    APU_CLOCK_SPEED_HZ, Console, Cpu, InputPort, MASTER_CLOCK_SPEED_HZ, Ppu,
+    apu::{ApuBreakpoints, SpcFile, brr::BrrSample},
+    cartridge::{CartridgeError, MemoryMap},
+    port_log::PortLogEntry,
+    ppu::SCREEN_RESOLUTION,
+};
 
 const SLEEP_TIME: Duration = Duration::from_millis(5);
+/// How often to update the UI when only the APU is running
+const APU_ONLY_UI_TIME: Duration = Duration::from_millis(16);
+/// Number of port accesses to keep in the port log
+const PORT_LOG_CAPACITY: usize = 0x10000;
+/// Maximum number of port accesses to show in the UI at once
+const PORT_LOG_DISPLAY_LEN: usize = 0x1000;
+/// Minimum number of samples to play when auditioning a looping BRR sample
+const AUDITION_LENGTH: usize = 32_000;
+/// Volume auditioned BRR samples are played at, regardless of the volume setting
+const AUDITION_GAIN: f32 = 0.5;
 
 use crate::{
     ConsoleData, Settings,
@@ -36,6 +54,8 @@ pub enum AdvanceAmount {
     MasterCycles(u32),
     Scanlines(u32),
     Instructions(u32),
+    /// SPC700 instructions, running the CPU as needed to keep it in sync
+    ApuInstructions(u32),
     Frames(u32),
     StartVBlank,
     EndVBlank,
@@ -45,9 +65,16 @@ pub enum AdvanceAmount {
 pub enum Command {
     Advance(AdvanceAmount),
     UpdateInputPorts([InputPort; 2]),
-    LoadRom(Vec<u8>),
+    /// Load a console that was just created from a ROM, along with where to save its SRAM
+    LoadRom(Console, Option<PathBuf>),
+    /// Load an SPC file and only run the APU
+    LoadSpc(SpcFile),
     LoadSavestate(Console),
     Reset,
+    /// Play a buffer of samples through the audio output, at `AUDITION_GAIN` rather than the volume setting
+    PlaySamples(Vec<f32>),
+    /// Write a byte to ARAM, as if it had been edited in the debugger
+    WriteAram(u16, u8),
 }
 /// The payload send to the emulation thread telling it to update the emulator
 #[derive(new)]
@@ -56,6 +83,45 @@ pub struct UpdateEmuPayload {
     command: Command,
 }
 
+/// Path of the .srm file that a ROM's SRAM is saved to, which is next to the ROM
+/// A patched ROM has its own file, named after the patch, so it doesn't share saves with the unpatched ROM
+pub fn sram_path(rom_path: &Path, patch: Option<&Path>) -> PathBuf {
+    match patch.and_then(Path::file_stem) {
+        Some(patch_name) => {
+            let mut name = rom_path.file_stem().unwrap_or_default().to_os_string();
+            name.push(".");
+            name.push(patch_name);
+            name.push(".srm");
+            rom_path.with_file_name(name)
+        }
+        None => rom_path.with_extension("srm"),
+    }
+}
+
+/// Save the console's SRAM to a .srm file, if the game has written to it since it was last saved
+/// Only cartridges with a battery keep their SRAM, so nothing is saved for the others
+fn save_sram_file(console: &mut Console, path: &Path) {
+    if console.cartridge().header().chipset.has_battery && console.take_sram_written() {
+        info!("Saving SRAM to {:?}", path);
+        if let Err(e) = std::fs::write(path, console.sram()) {
+            error!("Unable to write to file {:?}: {:?}", path, e);
+        }
+    }
+}
+
+/// Load a .srm file into the console's SRAM, if it exists
+pub fn load_sram_file(console: &mut Console, path: &Path) {
+    if path.exists() {
+        match std::fs::read(path) {
+            Ok(sram) => {
+                info!("Loading SRAM from {:?}", path);
+                console.load_sram(&sram);
+            }
+            Err(e) => error!("Unable to read file {:?}: {:?}", path, e),
+        }
+    }
+}
+
 /// The underlying engine of the emulator application
 /// Runs the application on a separate thread and sends data back and forth
 pub struct Engine {
@@ -64,22 +130,29 @@ pub struct Engine {
     settings: Arc<Mutex<Settings>>,
     pub cpu_dis: Arc<Mutex<Disassembler<CpuInstruction>>>,
     pub apu_dis: Arc<Mutex<Disassembler<ApuInstruction>>>,
+    /// Where to save the SRAM of the current ROM, if it was loaded from a file
+    /// Only changed by the emulation thread, when it swaps out the console
+    sram_path: Arc<Mutex<Option<PathBuf>>>,
 }
 
 impl Engine {
-    pub fn new(console: Console, ui_ptr: Weak<AppWindow>) -> Engine {
+    pub fn new(console: Console, sram_path: Option<PathBuf>, ui_ptr: Weak<AppWindow>) -> Engine {
         // Send data to the emulation thread telling it to update the emulator
         let (to_emu, from_main) = mpsc::channel::<UpdateEmuPayload>();
         // Initialize audio
         let mut audio = Audio::new();
         // Create console
         let console = Arc::new(Mutex::new(console));
+        let sram_path = Arc::new(Mutex::new(sram_path));
         // Create initial settings
         let settings = Arc::new(Mutex::new(Settings {
             volume: 20.0,
             is_paused: false,
             log_apu: false,
             log_cpu: false,
+            capture_voices: false,
+            output_filter: false,
+            log_ports: false,
         }));
 
         // Create disassmblers
@@ -88,7 +161,7 @@ impl Engine {
 
         thread::Builder::new()
             .name("Super Y.A.N.E. helper".to_string())
-            .spawn(closure!(clone console, clone settings, clone cpu_dis, clone apu_dis, clone ui_ptr, || {
+            .spawn(closure!(clone console, clone settings, clone cpu_dis, clone apu_dis, clone ui_ptr, clone sram_path, || {
                 {
                     let d = &mut cpu_dis.lock().unwrap();
                     // Add initial vectors and instruction
@@ -96,13 +169,44 @@ impl Engine {
                     d.add_current_instruction(&console.lock().unwrap());
                 }
                 apu_dis.lock().unwrap().add_current_instruction(&console.lock().unwrap());
+                /// Show the header of the cartridge that was just loaded
+                macro_rules! update_rom_info {
+                    ($c: ident) => {
+                        let info = get_rom_info($c.cartridge());
+                        ui_ptr
+                            .upgrade_in_event_loop(move |ui| ui.set_rom_info(info))
+                            .unwrap();
+                    };
+                }
+                {
+                    let c = console.lock().unwrap();
+                    update_rom_info!(c);
+                }
 
                 // Used to calculate delta time to advance the emulator
                 let mut last_time = Instant::now();
+                // Whether to only run the APU, i.e. when playing an SPC file
+                let mut apu_only = false;
+                let mut last_ui_update = Instant::now();
+                // Recent state of each voice, for the voice graphs
+                let mut voice_history = VoiceHistory::default();
+                // Where the SPC700 stopped for the last breakpoint it hit, while paused on it
+                let mut apu_stop_pc: Option<u16> = None;
                 loop {
+                    /// Log and disassemble the SPC700 instruction that was just run
+                    macro_rules! after_apu_step {
+                        ($console: ident, $settings: ident) => {
+                            apu_dis.lock().unwrap().add_current_instruction(&$console);
+                            if $settings.log_apu {
+                                let inst = ApuSnapshot::from(&$console);
+                                info!("[APU] {}", inst);
+                            }
+                        };
+                    }
                     /// Advance by 1 instruction
+                    /// Evaluates to the APU breakpoint hit while advancing, if any
                     macro_rules! advance {
-                        ($console: ident, $settings: ident) => {
+                        ($console: ident, $settings: ident) => {{
                             // let before_master_cycles = *c.total_master_clocks();
                             $console.step_cpu();
                             cpu_dis.lock().unwrap().add_current_instruction(&$console);
@@ -110,16 +214,15 @@ impl Engine {
                                 let inst = CpuSnapshot::from(&$console);
                                 info!("[CPU] {}", inst);
                             }
-                            while $console.apu_is_behind() {
+                            let mut hit = None;
+                            while hit.is_none() && $console.apu_is_behind() {
                                 $console.step_apu();
-                                apu_dis.lock().unwrap().add_current_instruction(&$console);
-                                if $settings.log_apu {
-                                    let inst = ApuSnapshot::from(&$console);
-                                    info!("[APU] {}", inst);
-                                }
+                                after_apu_step!($console, $settings);
+                                hit = $console.apu_mut().take_breakpoint_hit();
                             }
                             // profiler.add_current_state(&console, before_master_cycles);
-                        };
+                            hit
+                        }};
                     }
                        // Send data back to the main thread for slint to display
                         macro_rules! update_ui {
@@ -133,12 +236,18 @@ impl Engine {
                                 let pc = $c.pc();
                                 let pc = $c.cartridge().transform_address(pc);
                                 let cpu_dis_lines = cpu_dis.lock().unwrap().slint_instructions(pc, 16, 16);
-                                let apu_dis_lines = apu_dis.lock().unwrap().slint_instructions($c.apu().core.pc as usize, 16, 16);
+                                let apu_pc = apu_stop_pc.unwrap_or($c.apu().core.pc);
+                                let apu_dis_lines = apu_dis.lock().unwrap().slint_instructions(apu_pc as usize, 16, 16);
+                                voice_history.push($c.apu());
+                                let voice_graphs: Vec<_> = $c.apu().dsp().voice_scopes().iter().enumerate().map(|(i, s)| {
+                                    (scope_image(s), voice_history.envelope_image(i))
+                                }).collect();
+                                let piano_roll = voice_history.piano_roll_image();
                                 // Clone the Arc<Mutex<Console>> instead of the console here
                                 ui_ptr
                                     .upgrade_in_event_loop(closure!(clone console, |ui| {
                                         let c = console.lock().expect("Unable to get a lock on console");
-                                        let (data, img_data, len) =
+                                        let (data, img_data, len, highlights) =
                                             get_binary_data(
                                                 &c,
                                                 ui.get_binary_data_offset() as usize,
@@ -146,11 +255,20 @@ impl Engine {
                                                 ui.get_bpp(),
                                                 ui.get_palette_index() as usize,
                                             );
-                                        ui.set_console_data(c.deref().into());
+                                        let console_data: ConsoleData = c.deref().into();
+                                        voice_graphs.into_iter().enumerate().for_each(|(i, (scope, envelope))| {
+                                            let mut v = console_data.voices.row_data(i).unwrap();
+                                            v.scope = Image::from_rgb8(scope);
+                                            v.envelope_graph = Image::from_rgb8(envelope);
+                                            console_data.voices.set_row_data(i, v);
+                                        });
+                                        ui.set_console_data(console_data);
+                                        ui.set_piano_roll(Image::from_rgb8(piano_roll));
                                         ui.set_pixel_data(Image::from_rgb8(buf));
                                         ui.set_binary_data(data);
                                         ui.set_binary_image(Image::from_rgb8(img_data));
                                         ui.set_binary_data_len(len as i32);
+                                        ui.set_binary_highlights(highlights);
 
                                         if ui.get_cpu_disassembly_lines().row_count() == 0 {
                                             ui.set_cpu_disassembly_lines(ModelRc::new(VecModel::from(cpu_dis_lines)));
@@ -186,6 +304,22 @@ impl Engine {
                                     .unwrap();
                             };
                         }
+                        // Pause the emulator and show where the APU stopped
+                        macro_rules! pause_on_breakpoint {
+                            ($c: ident, $b: expr) => {
+                                info!("APU hit breakpoint on {}", $b);
+                                apu_stop_pc = Some($c.apu().stop_location($b));
+                                settings.lock().unwrap().is_paused = true;
+                                ui_ptr
+                                    .upgrade_in_event_loop(|ui| {
+                                        let mut s = ui.get_settings();
+                                        s.is_paused = true;
+                                        ui.set_settings(s);
+                                    })
+                                    .unwrap();
+                            };
+                        }
+                        let mut breakpoint = None;
                         let p = from_main.try_recv();
                         use Command::*;
                         match p {
@@ -199,44 +333,68 @@ impl Engine {
                                 match payload.command {
                                 Advance(a) => {
                                     use AdvanceAmount::*;
-                                    match a {
-                                        MasterCycles(n) => {
-                                            let goal_cycles = c.total_master_clocks() + n as u64;
-                                            while *c.total_master_clocks() < goal_cycles {
-                                                advance!(c, s);
-                                            }
+                                    apu_stop_pc = None;
+                                    // Stop advancing as soon as a breakpoint is hit
+                                    'advance: {
+                                        macro_rules! advance_or_break {
+                                            () => {
+                                                if let Some(b) = advance!(c, s) {
+                                                    breakpoint = Some(b);
+                                                    break 'advance;
+                                                }
+                                            };
                                         }
-                                        Scanlines(n) => (0..n).for_each(|_| {
-                                            let mut hblank = c.ppu().is_in_hblank();
-                                            while !(hblank && !c.ppu().is_in_hblank()) {
-                                                hblank = c.ppu().is_in_hblank();
-                                                advance!(c, s);
+                                        match a {
+                                            MasterCycles(n) => {
+                                                let goal_cycles = c.total_master_clocks() + n as u64;
+                                                while *c.total_master_clocks() < goal_cycles {
+                                                    advance_or_break!();
+                                                }
                                             }
-                                        }),
-                                        Instructions(instructions) => {
-                                            (0..instructions).for_each(|_| {
-                                                advance!(c, s);
-                                            });
-                                        }
-                                        Frames(n) => (0..n).for_each(|_| {
-                                            let mut v = c.ppu().is_in_vblank();
-                                            while !(!v && c.ppu().is_in_vblank()) {
-                                                v = c.ppu().is_in_vblank();
-                                                advance!(c,s);
+                                            Scanlines(n) => for _ in 0..n {
+                                                let mut hblank = c.ppu().is_in_hblank();
+                                                while !(hblank && !c.ppu().is_in_hblank()) {
+                                                    hblank = c.ppu().is_in_hblank();
+                                                    advance_or_break!();
+                                                }
+                                            },
+                                            Instructions(instructions) => {
+                                                for _ in 0..instructions {
+                                                    advance_or_break!();
+                                                }
                                             }
-                                        }),
-                                        StartVBlank => {
-                                            let mut vblank = c.ppu().is_in_vblank();
-                                            while !(!vblank && c.ppu().is_in_vblank()) {
-                                                vblank = c.ppu().is_in_vblank();
-                                                advance!(c, s);
+                                            ApuInstructions(instructions) => {
+                                                for _ in 0..instructions {
+                                                    c.step_apu_instruction();
+                                                    cpu_dis.lock().unwrap().add_current_instruction(&c);
+                                                    after_apu_step!(c, s);
+                                                    // The instruction was run on purpose, so only stop early if it hit a breakpoint part way through
+                                                    if let Some(b) = c.apu_mut().take_breakpoint_hit() {
+                                                        breakpoint = Some(b);
+                                                        break 'advance;
+                                                    }
+                                                }
                                             }
-                                        }
-                                        EndVBlank => {
-                                            let mut vblank = c.ppu().is_in_vblank();
-                                            while !(vblank && !c.ppu().is_in_vblank()) {
-                                                vblank = c.ppu().is_in_vblank();
-                                                advance!(c, s);
+                                            Frames(n) => for _ in 0..n {
+                                                let mut v = c.ppu().is_in_vblank();
+                                                while !(!v && c.ppu().is_in_vblank()) {
+                                                    v = c.ppu().is_in_vblank();
+                                                    advance_or_break!();
+                                                }
+                                            },
+                                            StartVBlank => {
+                                                let mut vblank = c.ppu().is_in_vblank();
+                                                while !(!vblank && c.ppu().is_in_vblank()) {
+                                                    vblank = c.ppu().is_in_vblank();
+                                                    advance_or_break!();
+                                                }
+                                            }
+                                            EndVBlank => {
+                                                let mut vblank = c.ppu().is_in_vblank();
+                                                while !(vblank && !c.ppu().is_in_vblank()) {
+                                                    vblank = c.ppu().is_in_vblank();
+                                                    advance_or_break!();
+                                                }
                                             }
                                         }
                                     }
@@ -245,35 +403,117 @@ impl Engine {
                                 UpdateInputPorts(input_ports) => {
                                     *c.input_ports_mut() = input_ports;
                                 }
-                                LoadRom(bytes) => {
-                                    *c = Console::with_cartridge(&bytes);
+                                LoadRom(console, path) => {
+                                    // Save the SRAM of the ROM being replaced, now that it can't be written to any more
+                                    let mut sram_path = sram_path.lock().unwrap();
+                                    if let Some(p) = sram_path.as_ref() {
+                                        save_sram_file(&mut c, p);
+                                    }
+                                    *sram_path = path;
+                                    let breakpoints = c.apu().breakpoints().clone();
+                                    *c = console;
+                                    *c.apu_mut().breakpoints_mut() = breakpoints;
+                                    update_rom_info!(c);
+                                    apu_only = false;
+                                }
+                                LoadSpc(spc) => {
+                                    if let Some(tag) = &spc.tag {
+                                        info!("Playing \"{}\" from \"{}\"", tag.song_title, tag.game_title);
+                                    }
+                                    c.load_spc(&spc);
+                                    apu_only = true;
                                 }
                                 LoadSavestate(state) => {
+                                    let mut sram_path = sram_path.lock().unwrap();
+                                    if let Some(p) = sram_path.as_ref() {
+                                        save_sram_file(&mut c, p);
+                                    }
+                                    // Don't save the SRAM of a different ROM over the current ROM's .srm file
+                                    if state.cartridge().data != c.cartridge().data {
+                                        *sram_path = None;
+                                    }
+                                    let breakpoints = c.apu().breakpoints().clone();
                                     *c = state;
+                                    *c.apu_mut().breakpoints_mut() = breakpoints;
+                                    update_rom_info!(c);
                                     c.ppu_mut().reset_vram_cache();
+                                    apu_only = false;
                                 }
                                 Reset => {
                                     c.reset();
+                                    apu_only = false;
+                                }
+                                PlaySamples(samples) => {
+                                    audio.push_samples(&samples, AUDITION_GAIN);
+                                }
+                                WriteAram(address, value) => {
+                                    c.apu_mut().write_ram(address as usize, value);
                                 }
                             };
                             update_ui!(c, s);
                             },
                             Err(_) => {}
                         }
+                        let s = settings.lock().unwrap().deref().clone();
+                        if let Some(b) = breakpoint.take() {
+                            let c = console.lock().unwrap();
+                            pause_on_breakpoint!(c, b);
+                            update_ui!(c, s);
+                        }
                         // Calculate delta time
                         let now = Instant::now();
                         let dt = now - last_time;
                         last_time = now;
-                        let s = settings.lock().unwrap().deref().clone();
+                        {
+                            let mut c = console.lock().unwrap();
+                            // Start each capture with empty buffers
+                            if s.capture_voices && !c.apu().dsp().capture_voices {
+                                c.apu_mut().voice_sample_queues();
+                            }
+                            let dsp = c.apu_mut().dsp_mut();
+                            dsp.capture_voices = s.capture_voices;
+                            dsp.output_filter_enabled = s.output_filter;
+                            dsp.scope_len = SCOPE_LEN;
+                            if s.log_ports != c.port_log().is_some() {
+                                if s.log_ports {
+                                    c.enable_port_log(PORT_LOG_CAPACITY);
+                                } else {
+                                    c.disable_port_log();
+                                }
+                            }
+                        }
                         // Advance emulator
                         if !s.is_paused {
+                            apu_stop_pc = None;
+                        }
+                        if !s.is_paused && apu_only {
+                            let mut c = console.lock().unwrap();
+                            let goal_clocks = *c.apu().total_clocks()
+                                + (dt.as_secs_f64() * APU_CLOCK_SPEED_HZ as f64) as usize;
+                            while *c.apu().total_clocks() < goal_clocks {
+                                c.step_apu();
+                                if let Some(b) = c.apu_mut().take_breakpoint_hit() {
+                                    breakpoint = Some(b);
+                                    break;
+                                }
+                            }
+                            if last_ui_update.elapsed() > APU_ONLY_UI_TIME {
+                                update_ui!(c, s);
+                                last_ui_update = Instant::now();
+                            }
+                            // Update audio
+                            let samples = c.apu_mut().sample_queue();
+                            let (a, b) = samples.as_slices();
+                            audio.push_samples(a, s.volume);
+                            audio.push_samples(b, s.volume);
+                        } else if !s.is_paused {
                             let initial_master_cycles = console.lock().unwrap().total_master_clocks().clone();
-                            while ((console.lock().unwrap().total_master_clocks() - initial_master_cycles) as f64)
+                            while breakpoint.is_none() && ((console.lock().unwrap().total_master_clocks() - initial_master_cycles) as f64)
                                 < dt.as_micros() as f64 / 1_000_000.0 * MASTER_CLOCK_SPEED_HZ as f64
                             {
                                 let mut c = console.lock().unwrap();
                                 let vblank = c.ppu().is_in_vblank();
-                                advance!(c, s);
+                                breakpoint = advance!(c, s);
                                 // Update canvas if we just entered vblank
                                 if !vblank && c.ppu().is_in_vblank() {
                                     update_ui!(c, s);
@@ -285,6 +525,12 @@ impl Engine {
                             audio.push_samples(a, s.volume);
                             audio.push_samples(b, s.volume);
                         }
+                        if let Some(b) = breakpoint.take() {
+                            let c = console.lock().unwrap();
+                            pause_on_breakpoint!(c, b);
+                            apu_dis.lock().unwrap().add_current_instruction(&c);
+                            update_ui!(c, s);
+                        }
                         // Sleep
                         thread::sleep(SLEEP_TIME);
                     }
@@ -302,6 +548,7 @@ impl Engine {
             settings,
             cpu_dis,
             apu_dis,
+            sram_path,
         }
     }
 
@@ -319,6 +566,115 @@ impl Engine {
         let c = self.console.lock().unwrap().clone();
         serde_brief::to_vec::<Console>(&c).expect("Unable to serialize console")
     }
+    pub fn get_spc(&self) -> (String, Vec<u8>) {
+        let c = self.console.lock().unwrap();
+        (
+            c.cartridge().title().trim().to_string(),
+            c.save_spc().to_bytes(),
+        )
+    }
+    /// Set the debug mute and solo flags of a voice
+    pub fn set_voice_flags(&mut self, index: usize, muted: bool, solo: bool) {
+        let mut c = self.console.lock().unwrap();
+        let dsp = c.apu_mut().dsp_mut();
+        dsp.voice_muted[index] = muted;
+        dsp.voice_solo[index] = solo;
+    }
+    /// Take the samples captured from each voice since the capture started
+    pub fn take_voice_captures(&mut self) -> [Vec<f32>; 8] {
+        self.console
+            .lock()
+            .unwrap()
+            .apu_mut()
+            .voice_sample_queues()
+            .map(|q| q.into_iter().collect())
+    }
+    pub fn start_midi_recording(&mut self) {
+        self.console
+            .lock()
+            .unwrap()
+            .apu_mut()
+            .start_midi_recording();
+    }
+    pub fn stop_midi_recording(&mut self) -> Option<Vec<u8>> {
+        self.console.lock().unwrap().apu_mut().stop_midi_recording()
+    }
+    /// Decode every sample in the APU's sample directory
+    pub fn brr_samples(&self) -> Vec<BrrSample> {
+        self.console.lock().unwrap().apu().brr_samples()
+    }
+    /// Decode a single sample in the APU's sample directory
+    pub fn brr_sample(&self, index: usize) -> Option<BrrSample> {
+        self.console.lock().unwrap().apu().brr_sample(index)
+    }
+    /// Play a sample from the sample directory, repeating its loop for up to a second
+    pub fn play_brr_sample(&mut self, index: usize) {
+        if let Some(s) = self.brr_sample(index) {
+            let mut pcm = s.samples.clone();
+            if let Some(loop_point) = s.loop_point {
+                while pcm.len() < AUDITION_LENGTH {
+                    pcm.extend_from_slice(&s.samples[loop_point..]);
+                }
+            }
+            self.update(Command::PlaySamples(
+                pcm.iter().map(|v| *v as f32 / i16::MAX as f32).collect(),
+            ));
+        }
+    }
+    pub fn apu_breakpoints(&self) -> ApuBreakpoints {
+        self.console.lock().unwrap().apu().breakpoints().clone()
+    }
+    pub fn set_apu_breakpoints(&mut self, breakpoints: ApuBreakpoints) {
+        *self.console.lock().unwrap().apu_mut().breakpoints_mut() = breakpoints;
+    }
+    /// The most recent entries in the port log that match `filter`, from oldest to newest
+    pub fn port_log(&self, filter: impl Fn(&PortLogEntry) -> bool) -> Vec<PortLogEntry> {
+        match self.console.lock().unwrap().port_log() {
+            Some(log) => {
+                let mut entries: Vec<PortLogEntry> = log
+                    .entries()
+                    .iter()
+                    .rev()
+                    .filter(|e| filter(e))
+                    .take(PORT_LOG_DISPLAY_LEN)
+                    .copied()
+                    .collect();
+                entries.reverse();
+                entries
+            }
+            None => vec![],
+        }
+    }
+    pub fn clear_port_log(&mut self) {
+        if let Some(log) = self.console.lock().unwrap().port_log_mut() {
+            log.clear();
+        }
+    }
+    /// Load a ROM, optionally forcing its memory map
+    /// If `sram_path` is given, the ROM's SRAM is loaded from and saved to that .srm file
+    /// The SRAM of the ROM being replaced is saved once the emulation thread has stopped running it
+    pub fn load_rom(
+        &mut self,
+        rom: &[u8],
+        memory_map: Option<MemoryMap>,
+        sram_path: Option<PathBuf>,
+    ) -> Result<(), CartridgeError> {
+        let mut c = Console::with_cartridge_map(rom, memory_map)?;
+        if let Some(p) = &sram_path {
+            load_sram_file(&mut c, p);
+        }
+        self.to_emu
+            .send(UpdateEmuPayload::new(Command::LoadRom(c, sram_path)))
+            .unwrap();
+        Ok(())
+    }
+    /// Save SRAM to the .srm file, if the game has written to it since it was last saved
+    pub fn save_sram(&mut self) {
+        let mut c = self.console.lock().unwrap();
+        if let Some(path) = self.sram_path.lock().unwrap().as_ref() {
+            save_sram_file(&mut c, path);
+        }
+    }
     pub fn load_savestate(&mut self, state: &[u8]) -> Result<(), serde_brief::Error> {
         let c: Console = serde_brief::from_slice(state)?;
         self.to_emu
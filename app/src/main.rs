@@ -1,11 +1,15 @@
 use closure::closure;
-use rfd::FileDialog;
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageLevel};
+use slint::{ModelRc, SharedString, TimerMode, VecModel};
 use std::{
     cell::RefCell,
     env,
+    fmt::Display,
     fs::File,
     io::{BufWriter, Write},
+    path::{Path, PathBuf},
     rc::Rc,
+    time::Duration,
 };
 
 use log::*;
@@ -15,17 +19,30 @@ mod apu_snapshot;
 mod audio;
 mod cpu_snapshot;
 mod engine;
+mod resampler;
 mod utils;
+mod voice_history;
 
 use crate::{
     LoadConsoleError::FileError,
-    engine::{AdvanceAmount, Command, Engine},
+    engine::{AdvanceAmount, Command, Engine, load_sram_file, sram_path},
+    utils::{get_breakpoint_data, port_log_filter_matches},
+};
+use super_yane::{
+    Console,
+    apu::SpcFile,
+    archive::{ArchiveEntry, ArchiveError, ArchiveFormat, ROM_EXTENSIONS, RomChoice, choose_rom},
+    cartridge::{CartridgeError, MemoryMap, strip_copier_header},
+    patch::{PatchError, PatchFormat, apply_patch},
 };
-use super_yane::Console;
 mod disassembler;
 mod profiler;
 
 const DEFAULT_CARTRIDGE: &[u8] = include_bytes!("../roms/HelloWorld.sfc");
+/// How often to save SRAM while the game is running
+const SRAM_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// How often to update the port log timeline while ports are being logged
+const PORT_LOG_REFRESH_INTERVAL: Duration = Duration::from_millis(250);
 
 slint::include_modules!();
 
@@ -33,29 +50,205 @@ slint::include_modules!();
 enum LoadConsoleError {
     FileError(std::io::Error),
     DeserializationError(serde_brief::Error),
+    CartridgeError(CartridgeError),
+    PatchError(PatchError),
+    ArchiveError(ArchiveError),
+}
+
+impl Display for LoadConsoleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileError(e) => write!(f, "Unable to read file: {}", e),
+            LoadConsoleError::DeserializationError(e) => {
+                write!(f, "Unable to load savestate: {}", e)
+            }
+            LoadConsoleError::CartridgeError(e) => write!(f, "Unable to load ROM: {}", e),
+            LoadConsoleError::PatchError(e) => write!(f, "Unable to apply patch: {}", e),
+            LoadConsoleError::ArchiveError(e) => write!(f, "Unable to open archive: {}", e),
+        }
+    }
 }
 
-fn initial_console(arg: Option<String>) -> Result<Console, LoadConsoleError> {
+/// Load the console from the ROM or savestate given as an argument
+/// Also returns the path to save SRAM to, if a ROM was loaded
+fn initial_console(arg: Option<String>) -> Result<(Console, Option<PathBuf>), LoadConsoleError> {
     // Load ROM/savestate
     match arg {
-        Some(f) => match std::fs::read(&f) {
-            Ok(bytes) => {
-                debug!("Reading {}", f);
-                if f.ends_with(".sy.bin") {
-                    let mut c: Console = serde_brief::from_slice(&bytes)
-                        .map_err(LoadConsoleError::DeserializationError)?;
-                    c.ppu_mut().reset_vram_cache();
-                    Ok(c)
-                } else {
-                    Ok(Console::with_cartridge(&bytes))
-                }
-            }
-            Err(e) => {
-                error!("Unable to read file {}: {:?}", f, e);
-                Err(FileError(e))
+        Some(f) => {
+            debug!("Reading {}", f);
+            if f.ends_with(".sy.bin") {
+                let bytes = std::fs::read(&f).map_err(FileError)?;
+                let mut c: Console = serde_brief::from_slice(&bytes)
+                    .map_err(LoadConsoleError::DeserializationError)?;
+                c.ppu_mut().reset_vram_cache();
+                Ok((c, None))
+            } else {
+                let (bytes, patch) = read_rom(Path::new(&f), None)?;
+                let mut c =
+                    Console::with_cartridge(&bytes).map_err(LoadConsoleError::CartridgeError)?;
+                let path = sram_path(Path::new(&f), patch.as_deref());
+                load_sram_file(&mut c, &path);
+                Ok((c, Some(path)))
             }
-        },
-        None => Ok(Console::with_cartridge(DEFAULT_CARTRIDGE)),
+        }
+        None => Ok((Console::with_cartridge(DEFAULT_CARTRIDGE).unwrap(), None)),
+    }
+}
+
+/// The contents of a ROM file
+enum RomFile {
+    Rom(Vec<u8>),
+    /// An archive with several files that could be the ROM, from most to least likely
+    Ambiguous(Vec<ArchiveEntry>),
+}
+
+/// An archive the user is picking a ROM out of
+struct PendingArchive {
+    path: PathBuf,
+    patch: Option<PathBuf>,
+    memory_map: Option<MemoryMap>,
+    entries: Vec<ArchiveEntry>,
+}
+
+/// Read a ROM file, decompressing it if it is a zip or gzip archive
+fn read_rom_file(path: &Path) -> Result<RomFile, LoadConsoleError> {
+    let data = std::fs::read(path).map_err(FileError)?;
+    if ArchiveFormat::from_bytes(&data).is_none() {
+        return Ok(RomFile::Rom(data));
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match choose_rom(&data, &name).map_err(LoadConsoleError::ArchiveError)? {
+        RomChoice::One(entry) => {
+            info!("Loading {} from {:?}", entry.name, path);
+            Ok(RomFile::Rom(entry.data))
+        }
+        RomChoice::Ambiguous(entries) => Ok(RomFile::Ambiguous(entries)),
+    }
+}
+
+/// Apply `patch` to a ROM read from `path`
+/// If no patch is given, a patch with the same name as the ROM file is applied if there is one
+/// Returns the ROM along with the patch that was applied to it, if any
+fn patch_rom(
+    rom: Vec<u8>,
+    path: &Path,
+    patch: Option<&Path>,
+) -> Result<(Vec<u8>, Option<PathBuf>), LoadConsoleError> {
+    let patch = patch.map(Path::to_path_buf).or_else(|| {
+        PatchFormat::EXTENSIONS
+            .iter()
+            .map(|e| path.with_extension(e))
+            .find(|p| p.exists())
+    });
+    match patch {
+        Some(p) => {
+            info!("Applying patch {:?}", p);
+            let patch = std::fs::read(&p).map_err(FileError)?;
+            // Patches are made for ROMs without copier headers
+            let rom = apply_patch(strip_copier_header(&rom), &patch)
+                .map_err(LoadConsoleError::PatchError)?;
+            Ok((rom, Some(p)))
+        }
+        None => Ok((rom, None)),
+    }
+}
+
+/// Read a ROM file and apply `patch` to it
+/// If the ROM is in an archive with several ROMs, the most likely one is used
+fn read_rom(
+    path: &Path,
+    patch: Option<&Path>,
+) -> Result<(Vec<u8>, Option<PathBuf>), LoadConsoleError> {
+    let rom = match read_rom_file(path)? {
+        RomFile::Rom(rom) => rom,
+        RomFile::Ambiguous(mut entries) => {
+            warn!("{:?} has several ROMs, loading {}", path, entries[0].name);
+            entries.remove(0).data
+        }
+    };
+    patch_rom(rom, path, patch)
+}
+
+/// Ask the user for a ROM file
+fn pick_rom() -> Option<PathBuf> {
+    FileDialog::new()
+        .add_filter(
+            "Super NES Rom",
+            &[
+                ROM_EXTENSIONS.as_slice(),
+                ArchiveFormat::EXTENSIONS.as_slice(),
+            ]
+            .concat(),
+        )
+        .pick_file()
+}
+
+/// Ask the user for a patch file
+fn pick_patch() -> Option<PathBuf> {
+    FileDialog::new()
+        .add_filter("ROM Patch", &PatchFormat::EXTENSIONS)
+        .set_title("Select patch")
+        .pick_file()
+}
+
+/// Tell the user a ROM couldn't be loaded
+fn show_load_error(e: LoadConsoleError) {
+    error!("{}", e);
+    MessageDialog::new()
+        .set_level(MessageLevel::Error)
+        .set_title("Unable to load ROM")
+        .set_description(e.to_string())
+        .set_buttons(MessageButtons::Ok)
+        .show();
+}
+
+/// Patch a ROM and load it into the engine, telling the user if it couldn't be loaded
+fn load_rom_data(
+    engine: &RefCell<Engine>,
+    rom: Vec<u8>,
+    path: &Path,
+    patch: Option<&Path>,
+    memory_map: Option<MemoryMap>,
+) {
+    let result = patch_rom(rom, path, patch).and_then(|(rom, patch)| {
+        engine
+            .borrow_mut()
+            .load_rom(&rom, memory_map, Some(sram_path(path, patch.as_deref())))
+            .map_err(LoadConsoleError::CartridgeError)
+    });
+    if let Err(e) = result {
+        show_load_error(e);
+    }
+}
+
+/// Read a ROM and load it into the engine
+/// If the ROM is in an archive with several ROMs, the user is asked which one to load
+fn load_rom_file(
+    engine: &RefCell<Engine>,
+    ui: &AppWindow,
+    pending: &RefCell<Option<PendingArchive>>,
+    path: &Path,
+    patch: Option<&Path>,
+    memory_map: Option<MemoryMap>,
+) {
+    match read_rom_file(path) {
+        Ok(RomFile::Rom(rom)) => load_rom_data(engine, rom, path, patch, memory_map),
+        Ok(RomFile::Ambiguous(entries)) => {
+            ui.set_archive_entries(ModelRc::from(Rc::from(VecModel::from_iter(
+                entries.iter().map(|e| SharedString::from(e.name.as_str())),
+            ))));
+            pending.replace(Some(PendingArchive {
+                path: path.to_path_buf(),
+                patch: patch.map(Path::to_path_buf),
+                memory_map,
+                entries,
+            }));
+            ui.invoke_show_archive_picker();
+        }
+        Err(e) => show_load_error(e),
     }
 }
 
@@ -84,11 +277,38 @@ fn main() {
     // Initialize UI
     let ui = AppWindow::new().unwrap();
     let ui_ptr = ui.as_weak();
+    let ui_weak = ui.as_weak();
     // Initialize engine
-    let engine = Rc::new(RefCell::new(Engine::new(
-        initial_console(env::args().nth(1)).unwrap(),
-        ui_ptr,
-    )));
+    let (console, sram_path) = match initial_console(env::args().nth(1)) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let engine = Rc::new(RefCell::new(Engine::new(console, sram_path, ui_ptr)));
+    let pending_archive: Rc<RefCell<Option<PendingArchive>>> = Rc::new(RefCell::new(None));
+    // Save SRAM every so often, so that in-game saves aren't lost if the app crashes
+    let sram_timer = slint::Timer::default();
+    sram_timer.start(
+        TimerMode::Repeated,
+        SRAM_SAVE_INTERVAL,
+        closure!(clone engine, || {
+            engine.borrow_mut().save_sram();
+        }),
+    );
+    // Keep the port log timeline scrolling while ports are being logged
+    let port_log_timer = slint::Timer::default();
+    port_log_timer.start(
+        TimerMode::Repeated,
+        PORT_LOG_REFRESH_INTERVAL,
+        closure!(clone ui_weak, || {
+            let ui = ui_weak.unwrap();
+            if ui.get_settings().log_ports {
+                ui.invoke_refresh_port_log();
+            }
+        }),
+    );
     // Update controllers
     ui.on_controller_changed(closure!(clone engine, |controller| {
         // Todo: Support player 2
@@ -108,6 +328,10 @@ fn main() {
     ui.on_advance_frames(closure!(clone engine, |n| {
         engine.borrow_mut().update(Command::Advance(AdvanceAmount::Frames(n as u32)));
     }));
+    // Advance SPC700 instructions
+    ui.on_advance_apu_instructions(closure!(clone engine, |n| {
+        engine.borrow_mut().update(Command::Advance(AdvanceAmount::ApuInstructions(n as u32)));
+    }));
     ui.on_reset(closure!(clone engine, || {
         engine.borrow_mut().update(Command::Reset);
     }));
@@ -116,16 +340,50 @@ fn main() {
     funcs.on_byte_to_hex(|b| format!("{:02X}", b).into());
     funcs.on_word_to_hex(|b| format!("{:04X}", b).into());
     funcs.on_addr_to_hex(|b| format!("{:06X}", b).into());
-    ui.on_load_rom(closure!(clone engine, || {
-        match FileDialog::new().add_filter("Super NES Rom", &["rom", "sfc", "smc"]).pick_file() {
+    ui.on_load_rom(
+        closure!(clone engine, clone ui_weak, clone pending_archive, || {
+            if let Some(path) = pick_rom() {
+                load_rom_file(&engine, &ui_weak.unwrap(), &pending_archive, &path, None, None);
+            }
+        }),
+    );
+    ui.on_load_rom_as(
+        closure!(clone engine, clone ui_weak, clone pending_archive, |map| {
+            if let Some(path) = pick_rom() {
+                let map = Some(MemoryMap::ALL[map as usize]);
+                load_rom_file(&engine, &ui_weak.unwrap(), &pending_archive, &path, None, map);
+            }
+        }),
+    );
+    ui.on_load_rom_with_patch(closure!(clone engine, clone ui_weak, clone pending_archive, || {
+        if let Some(path) = pick_rom() {
+            if let Some(patch) = pick_patch() {
+                load_rom_file(&engine, &ui_weak.unwrap(), &pending_archive, &path, Some(&patch), None);
+            }
+        }
+    }));
+    ui.on_archive_entry_picked(closure!(clone engine, clone pending_archive, |i| {
+        let Some(p) = pending_archive.take() else {
+            return;
+        };
+        if let Some(entry) = p.entries.into_iter().nth(i as usize) {
+            info!("Loading {} from {:?}", entry.name, p.path);
+            load_rom_data(&engine, entry.data, &p.path, p.patch.as_deref(), p.memory_map);
+        }
+    }));
+    ui.on_load_spc(closure!(clone engine, || {
+        match FileDialog::new().add_filter("SPC Music File", &["spc"]).pick_file() {
             None => {}
             Some(path) => {
-                match std::fs::read(&path) {
+                match std::fs::read(&path).map(|bytes| SpcFile::from_bytes(&bytes)) {
                     Err(e) => {
                         error!("Unable to read file {:?}: {:?}", &path, e);
                     }
-                    Ok(bytes) => {
-                        engine.borrow_mut().update(Command::LoadRom(bytes))
+                    Ok(Err(e)) => {
+                        error!("Unable to parse SPC file {:?}: {}", &path, e);
+                    }
+                    Ok(Ok(spc)) => {
+                        engine.borrow_mut().update(Command::LoadSpc(spc))
                     }
                 }
             }
@@ -146,6 +404,132 @@ fn main() {
             }
         }
     }));
+    ui.on_save_spc(closure!(clone engine, || {
+        let (title, data) = engine.borrow().get_spc();
+        match FileDialog::new()
+            .add_filter("SPC Music File", &["spc"])
+            .set_title("Save SPC")
+            .set_file_name(format!("{}.spc", title))
+            .save_file() {
+            None => {},
+            Some(path) => {
+                match std::fs::write(&path, &data) {
+                    Ok(_) => {},
+                    Err(e) => error!("Unable to write to file {:?}: {:?}", path, e)
+                }
+            }
+        }
+    }));
+    ui.on_set_voice_flags(closure!(clone engine, |i, muted, solo| {
+        engine.borrow_mut().set_voice_flags(i as usize, muted, solo);
+    }));
+    ui.on_save_stems(closure!(clone engine, || {
+        let stems = engine.borrow_mut().take_voice_captures();
+        match FileDialog::new().set_title("Save stems").pick_folder() {
+            None => {}
+            Some(dir) => stems.iter().enumerate().for_each(|(i, samples)| {
+                let path = dir.join(format!("voice_{}.wav", i));
+                match wavers::write(&path, samples, 32_000, 1) {
+                    Ok(_) => {}
+                    Err(e) => error!("Unable to write to file {:?}: {:?}", path, e),
+                }
+            }),
+        }
+    }));
+    ui.on_toggle_midi_recording(closure!(clone engine, clone ui_weak, || {
+        let ui = ui_weak.unwrap();
+        if !ui.get_recording_midi() {
+            engine.borrow_mut().start_midi_recording();
+            ui.set_recording_midi(true);
+            return;
+        }
+        ui.set_recording_midi(false);
+        let Some(data) = engine.borrow_mut().stop_midi_recording() else {
+            return;
+        };
+        match FileDialog::new()
+            .add_filter("MIDI File", &["mid"])
+            .set_title("Save MIDI")
+            .set_file_name("recording.mid")
+            .save_file()
+        {
+            None => {}
+            Some(path) => match std::fs::write(&path, &data) {
+                Ok(_) => {}
+                Err(e) => error!("Unable to write to file {:?}: {:?}", path, e),
+            },
+        }
+    }));
+    ui.on_refresh_samples(closure!(clone engine, clone ui_weak, || {
+        let samples = engine.borrow().brr_samples();
+        ui_weak.unwrap().set_brr_samples(ModelRc::from(Rc::from(VecModel::from_iter(
+            samples.iter().map(|s| s.into()),
+        ))));
+    }));
+    ui.on_edit_aram(closure!(clone engine, |address, value| {
+        match u8::from_str_radix(value.trim().trim_start_matches('$'), 16) {
+            Ok(v) => engine.borrow_mut().update(Command::WriteAram(address as u16, v)),
+            Err(_) => warn!("Invalid byte {}", value),
+        }
+    }));
+    ui.on_add_apu_breakpoint(closure!(clone engine, clone ui_weak, |kind, address| {
+        let Ok(address) = u16::from_str_radix(address.trim().trim_start_matches('$'), 16) else {
+            warn!("Invalid breakpoint address {}", address);
+            return;
+        };
+        let mut b = engine.borrow().apu_breakpoints();
+        match kind {
+            0 => b.pc.insert(address),
+            1 => b.read.insert(address),
+            _ => b.write.insert(address),
+        };
+        ui_weak.unwrap().set_apu_breakpoints(ModelRc::from(Rc::from(VecModel::from(get_breakpoint_data(&b)))));
+        engine.borrow_mut().set_apu_breakpoints(b);
+    }));
+    ui.on_remove_apu_breakpoint(closure!(clone engine, clone ui_weak, |kind, address| {
+        let mut b = engine.borrow().apu_breakpoints();
+        let address = address as u16;
+        match kind {
+            0 => b.pc.remove(&address),
+            1 => b.read.remove(&address),
+            _ => b.write.remove(&address),
+        };
+        ui_weak.unwrap().set_apu_breakpoints(ModelRc::from(Rc::from(VecModel::from(get_breakpoint_data(&b)))));
+        engine.borrow_mut().set_apu_breakpoints(b);
+    }));
+    ui.on_refresh_port_log(closure!(clone engine, clone ui_weak, || {
+        let ui = ui_weak.unwrap();
+        let filter = ui.get_port_log_filter();
+        let entries = engine.borrow().port_log(|e| port_log_filter_matches(&filter, e));
+        ui.set_port_log_lines(ModelRc::from(Rc::from(VecModel::from_iter(
+            entries.iter().map(|e| e.into()),
+        ))));
+    }));
+    ui.on_clear_port_log(closure!(clone engine, clone ui_weak, || {
+        engine.borrow_mut().clear_port_log();
+        ui_weak.unwrap().set_port_log_lines(ModelRc::default());
+    }));
+    ui.on_play_sample(closure!(clone engine, |i| {
+        engine.borrow_mut().play_brr_sample(i as usize);
+    }));
+    ui.on_export_sample(closure!(clone engine, |i| {
+        let Some(sample) = engine.borrow().brr_sample(i as usize) else {
+            return;
+        };
+        match FileDialog::new()
+            .add_filter("WAV Audio", &["wav"])
+            .set_title("Save sample")
+            .set_file_name(format!("sample_{:02X}.wav", i))
+            .save_file() {
+            None => {},
+            Some(path) => {
+                match wavers::write(&path, &sample.samples, 32_000, 1) {
+                    Ok(_) => {},
+                    Err(e) => error!("Unable to write to file {:?}: {:?}", path, e)
+                }
+            }
+        }
+    }));
     ui.on_load_savestate(closure!(clone engine, || {
         match FileDialog::new()
             .add_filter("Super Y.A.N.E. Savestate", &["bin"])
@@ -161,6 +545,7 @@ fn main() {
             }
     }));
     ui.run().expect("Unable to start Slint application");
+    engine.borrow_mut().save_sram();
 
     let e = engine.borrow();
     let d = e.cpu_dis.lock().unwrap();
@@ -0,0 +1,138 @@
+use std::f64::consts::PI;
+
+/// Number of input samples each output sample is interpolated from
+const TAPS: usize = 16;
+/// Number of fractional positions between two input samples the kernel is computed for
+const PHASES: usize = 256;
+
+/// Band-limited resampler, using a windowed sinc kernel
+pub struct Resampler {
+    /// Number of input samples consumed per output sample
+    step: f64,
+    /// Position of the next output sample, as an index into `history`
+    position: f64,
+    /// Input samples which are still needed to generate output samples
+    history: Vec<f32>,
+    /// The kernel, `PHASES` rows of `TAPS` coefficients
+    kernel: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Resampler {
+        let step = input_rate as f64 / output_rate as f64;
+        // Cut off just below the lower of the two nyquist frequencies,
+        // unless the rates are the same and the samples can be passed straight through
+        let cutoff = if input_rate == output_rate {
+            1.0
+        } else {
+            (1.0 / step).min(1.0) * 0.95
+        };
+        let kernel = (0..PHASES)
+            .flat_map(|p| {
+                let frac = p as f64 / PHASES as f64;
+                let row: Vec<f64> = (0..TAPS)
+                    .map(|i| {
+                        // Distance between the tap and the output sample
+                        let x = i as f64 - (TAPS / 2 - 1) as f64 - frac;
+                        let sinc = if x == 0.0 {
+                            1.0
+                        } else {
+                            (PI * cutoff * x).sin() / (PI * cutoff * x)
+                        };
+                        // Blackman window
+                        let n = (x + (TAPS / 2) as f64) / TAPS as f64;
+                        let window =
+                            0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+                        sinc * window
+                    })
+                    .collect();
+                // Normalize so that each row doesn't change the volume
+                let sum: f64 = row.iter().sum();
+                row.into_iter().map(move |v| (v / sum) as f32)
+            })
+            .collect();
+        Resampler {
+            step,
+            position: (TAPS / 2 - 1) as f64,
+            history: vec![0.0; TAPS / 2 - 1],
+            kernel,
+        }
+    }
+    /// Resample a chunk of input samples.
+    /// `adjust` scales the number of input samples consumed per output sample,
+    /// so values above 1.0 generate fewer output samples.
+    pub fn process(&mut self, input: &[f32], adjust: f64) -> Vec<f32> {
+        self.history.extend_from_slice(input);
+        let step = self.step * adjust;
+        let mut output = Vec::with_capacity((input.len() as f64 / step) as usize + 1);
+        while (self.position as usize) + TAPS / 2 < self.history.len() {
+            let base = self.position as usize;
+            let phase = (((self.position - base as f64) * PHASES as f64) as usize).min(PHASES - 1);
+            let row = &self.kernel[phase * TAPS..(phase + 1) * TAPS];
+            let start = base + 1 - TAPS / 2;
+            output.push(
+                self.history[start..(start + TAPS)]
+                    .iter()
+                    .zip(row)
+                    .map(|(s, k)| s * k)
+                    .sum(),
+            );
+            self.position += step;
+        }
+        // Drop the input samples that are no longer needed
+        let consumed = (self.position as usize)
+            .saturating_sub(TAPS / 2 - 1)
+            .min(self.history.len());
+        self.history.drain(..consumed);
+        self.position -= consumed as f64;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(len: usize, frequency: f32, rate: u32) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_same_rate_passes_through() {
+        let mut r = Resampler::new(48_000, 48_000);
+        let input = sine(1000, 440.0, 48_000);
+        let output = r.process(&input, 1.0);
+        // The output lags behind the input by half the kernel
+        assert_eq!(output.len(), input.len() - TAPS / 2);
+        output
+            .iter()
+            .zip(input.iter())
+            .for_each(|(o, i)| assert!((o - i).abs() < 1e-5));
+    }
+
+    #[test]
+    fn test_upsample_length() {
+        let mut r = Resampler::new(32_000, 48_000);
+        let input = sine(320, 440.0, 32_000);
+        let len: usize = (0..100).map(|_| r.process(&input, 1.0).len()).sum();
+        // 32,000 samples at 32kHz is 48,000 samples at 48kHz, less the samples still in the kernel
+        assert!((47_980..=48_000).contains(&len), "{len}");
+    }
+
+    #[test]
+    fn test_rate_adjust() {
+        let input = sine(320, 440.0, 32_000);
+        let total = |adjust: f64| {
+            let mut r = Resampler::new(32_000, 48_000);
+            (0..100)
+                .map(|_| r.process(&input, adjust).len())
+                .sum::<usize>() as f64
+        };
+        let normal = total(1.0);
+        // Consuming the input faster generates fewer samples, and slower generates more
+        assert!(((normal / total(1.005)) - 1.005).abs() < 0.001);
+        assert!(((normal / total(0.995)) - 0.995).abs() < 0.001);
+    }
+}
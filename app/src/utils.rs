@@ -1,17 +1,19 @@
 use std::rc::Rc;
 
-use slint::{ModelRc, Rgb8Pixel, SharedPixelBuffer, SharedString, VecModel};
+use slint::{Model, ModelRc, Rgb8Pixel, SharedPixelBuffer, SharedString, VecModel};
 use super_yane::{
-    Background, Console, InputPort, Ppu,
-    apu::{Apu, Dsp, Voice},
+    Background, Cartridge, Console, InputPort, Ppu,
+    apu::{Apu, ApuBreakpoints, Dsp, Voice, brr::BrrSample},
+    port_log::{PortLogEntry, PortSide},
     ppu::Sprite,
     utils::color_to_rgb_bytes,
 };
 use wdc65816::{Processor, StatusRegister};
 
 use crate::{
-    ApuData, BackgroundData, BinaryDataSrc, ConsoleData, CpuData, DspData, OamData, PpuData,
-    StandardController, StatusRegisterData, Voice as SlintVoice,
+    ApuData, BackgroundData, BinaryDataSrc, BreakpointData, ConsoleData, CpuData, DspData, OamData,
+    PortLogFilter, PortLogLine, PpuData, RomInfo, SampleData, StandardController,
+    StatusRegisterData, Voice as SlintVoice,
 };
 
 /// Interprets a chunk of binary data as SNES 2bpp tile date, and rewrites it into a 2BPP format
@@ -83,7 +85,12 @@ pub fn get_binary_data(
     ram_type: BinaryDataSrc,
     bpp: i32,
     palette_index: usize,
-) -> (ModelRc<ModelRc<i32>>, SharedPixelBuffer<Rgb8Pixel>, usize) {
+) -> (
+    ModelRc<ModelRc<i32>>,
+    SharedPixelBuffer<Rgb8Pixel>,
+    usize,
+    ModelRc<ModelRc<i32>>,
+) {
     // Copy some section of ram
     let mut data = [[0u8; 32]; 8];
     // Create a copy of CGRAM as a u8 array
@@ -131,6 +138,15 @@ pub fn get_binary_data(
         8 * NUM_TILES_WIDTH as u32,
         8 * NUM_TILES_HEIGHT as u32,
     );
+    // Highlight the regions of ARAM in use
+    let highlights = (0..8).map(|i| {
+        ModelRc::from(Rc::from(VecModel::from_iter((0..32).map(
+            |j| match ram_type {
+                Aram => aram_highlight(c.apu(), offset + 32 * i + j),
+                _ => 0,
+            },
+        ))))
+    });
     return (
         ModelRc::from(Rc::from(VecModel::from_iter((0..8).map(|i| {
             ModelRc::from(Rc::from(VecModel::from_iter(
@@ -139,8 +155,28 @@ pub fn get_binary_data(
         })))),
         buf,
         data_len,
+        ModelRc::from(Rc::from(VecModel::from_iter(highlights))),
     );
 }
+/// What a byte in ARAM is being used for, so that the memory viewer can highlight it
+/// 0 for nothing, 1 for the sample directory, 2 for the echo buffer, 3 for the SPC700's PC and 4 for its SP
+fn aram_highlight(apu: &Apu, address: usize) -> i32 {
+    let dsp = apu.dsp();
+    // Whether the address is in the `len` bytes from `start`, which wrap around the end of ARAM
+    let in_range = |start: usize, len: usize| address.wrapping_sub(start) % 0x10000 < len;
+    if address == apu.core.pc as usize {
+        3
+    } else if address == 0x100 + apu.core.sp as usize {
+        4
+    } else if in_range(dsp.echo_addr, dsp.echo_size.max(4)) {
+        // An echo size of 0 still uses 4 bytes
+        2
+    } else if in_range(dsp.sample_dir, 4 * 0x100) {
+        1
+    } else {
+        0
+    }
+}
 // Macro to copy a bunch of fields between structs
 macro_rules! copy_fields {
     ($from: ident, $to: ident, $($field:ident),*) => {
@@ -177,6 +213,12 @@ impl Into<ConsoleData> for &Console {
         };
         let dsp = self.apu().dsp();
         copy_array_fields!(dsp, data, voices);
+        (0..dsp.voices.len()).for_each(|i| {
+            let mut v = data.voices.row_data(i).unwrap();
+            v.muted = dsp.voice_muted[i];
+            v.solo = dsp.voice_solo[i];
+            data.voices.set_row_data(i, v);
+        });
         data
     }
 }
@@ -260,6 +302,11 @@ impl Into<ApuData> for &Apu {
         let r = &self.rest;
         copy_fields!(r, data, expose_ipl_rom, dsp_addr, dsp_read_only);
         copy_array_fields!(r, data, cpu_to_apu_reg, apu_to_cpu_reg);
+        data.state = match self.halted() {
+            Some(h) => format!("Halted ({})", h),
+            None => "Running".to_string(),
+        }
+        .into();
         data
     }
 }
@@ -289,6 +336,103 @@ impl Into<DspData> for &Dsp {
     }
 }
 
+impl Into<SampleData> for &BrrSample {
+    fn into(self) -> SampleData {
+        SampleData {
+            index: self.index as i32,
+            start_addr: self.start_addr as i32,
+            loop_addr: self.loop_addr as i32,
+            length: self.samples.len() as i32,
+            looped: self.loop_point.is_some(),
+        }
+    }
+}
+
+/// Get the information shown in the ROM info panel
+/// Computes the ROM's checksum, so shouldn't be called every frame
+pub fn get_rom_info(cartridge: &Cartridge) -> RomInfo {
+    let h = cartridge.header();
+    RomInfo {
+        title: h.title.clone().into(),
+        map_mode: match h.memory_map() {
+            Some(m) => format!("{:?} ({:02X})", m, h.map_mode),
+            None => format!("Unknown ({:02X})", h.map_mode),
+        }
+        .into(),
+        detected_map: format!("{:?}", cartridge.memory_map()).into(),
+        speed: if h.fast_rom { "FastROM" } else { "SlowROM" }.into(),
+        chipset: h.chipset.to_string().into(),
+        rom_size: h.rom_size as i32,
+        sram_size: h.sram_size as i32,
+        region: h.region.to_string().into(),
+        developer: match &h.extended {
+            Some(e) => e.maker_code.clone(),
+            None => format!("{:02X}", h.developer_id),
+        }
+        .into(),
+        version: h.version as i32,
+        checksum: h.checksum as i32,
+        checksum_complement: h.checksum_complement as i32,
+        checksum_valid: cartridge.checksum() == h.checksum,
+        has_extended_header: h.extended.is_some(),
+        game_code: h
+            .extended
+            .as_ref()
+            .map_or(String::new(), |e| e.game_code.clone())
+            .into(),
+        expansion_ram_size: h.extended.as_ref().map_or(0, |e| e.expansion_ram_size) as i32,
+        special_version: h.extended.as_ref().map_or(0, |e| e.special_version) as i32,
+    }
+}
+
+/// List the APU breakpoints, in the format used by the breakpoints panel
+pub fn get_breakpoint_data(breakpoints: &ApuBreakpoints) -> Vec<BreakpointData> {
+    [&breakpoints.pc, &breakpoints.read, &breakpoints.write]
+        .into_iter()
+        .enumerate()
+        .flat_map(|(kind, addresses)| {
+            addresses.iter().map(move |a| BreakpointData {
+                kind: kind as i32,
+                address: *a as i32,
+            })
+        })
+        .collect()
+}
+
+impl Into<PortLogLine> for &PortLogEntry {
+    fn into(self) -> PortLogLine {
+        PortLogLine {
+            master_clock: self.master_clock.to_string().into(),
+            side: match self.side {
+                PortSide::Cpu => "CPU",
+                PortSide::Apu => "APU",
+            }
+            .into(),
+            write: self.access.write,
+            port: self.access.port as i32,
+            value: self.access.value as i32,
+            cpu_pc: self.cpu_pc as i32,
+            apu_pc: self.apu_pc as i32,
+        }
+    }
+}
+
+/// Whether an entry in the port log should be shown with the filter selected in the UI
+pub fn port_log_filter_matches(filter: &PortLogFilter, entry: &PortLogEntry) -> bool {
+    let side = match filter.side {
+        1 => entry.side == PortSide::Cpu,
+        2 => entry.side == PortSide::Apu,
+        _ => true,
+    };
+    let access = match filter.access {
+        1 => !entry.access.write,
+        2 => entry.access.write,
+        _ => true,
+    };
+    let port = filter.port == 0 || filter.port - 1 == entry.access.port as i32;
+    side && access && port
+}
+
 impl Into<SlintVoice> for Voice {
     fn into(self) -> SlintVoice {
         let mut data = SlintVoice::default();
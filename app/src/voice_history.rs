@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use slint::{Rgb8Pixel, SharedPixelBuffer};
+use super_yane::apu::{AdsrStage, Apu, State, pitch_to_note};
+
+/// Number of UI updates to keep in the envelope graphs and piano roll
+pub const HISTORY_LEN: usize = 256;
+/// Number of samples shown in each voice's oscilloscope
+pub const SCOPE_LEN: usize = 256;
+/// Height of the oscilloscope and envelope graph images
+const GRAPH_HEIGHT: usize = 64;
+/// Lowest and highest notes shown in the piano roll
+const LOWEST_NOTE: usize = 24;
+const HIGHEST_NOTE: usize = 108;
+const ENVELOPE_MAX_VALUE: f32 = 0x7FF as f32;
+
+const BACKGROUND: Rgb8Pixel = Rgb8Pixel::new(0x22, 0x22, 0x22);
+/// Background of the rows of C notes in the piano roll, to make it easier to read
+const OCTAVE_BACKGROUND: Rgb8Pixel = Rgb8Pixel::new(0x33, 0x33, 0x33);
+const SCOPE_COLOR: Rgb8Pixel = Rgb8Pixel::new(0x5b, 0xb9, 0xeb);
+/// Colors used for each voice in the piano roll
+const VOICE_COLORS: [Rgb8Pixel; 8] = [
+    Rgb8Pixel::new(0xd1, 0x47, 0x57),
+    Rgb8Pixel::new(0xbf, 0x63, 0x36),
+    Rgb8Pixel::new(0xe3, 0xc8, 0x4f),
+    Rgb8Pixel::new(0x5d, 0xd4, 0x63),
+    Rgb8Pixel::new(0x4f, 0xd1, 0xc5),
+    Rgb8Pixel::new(0x5b, 0xb9, 0xeb),
+    Rgb8Pixel::new(0xab, 0x62, 0xe3),
+    Rgb8Pixel::new(0xe3, 0x62, 0xb8),
+];
+
+/// The state of a voice on a single UI update
+#[derive(Clone, Copy, Default)]
+struct VoiceFrame {
+    envelope: u16,
+    state: State,
+    /// The note being played, if the voice is keyed on and can be heard
+    note: Option<f64>,
+}
+
+/// Keeps the recent state of each voice, and draws it as graphs
+#[derive(Default)]
+pub struct VoiceHistory {
+    frames: VecDeque<[VoiceFrame; 8]>,
+    /// Total clocks of the APU when the last frame was added
+    last_clocks: usize,
+}
+
+impl VoiceHistory {
+    /// Add the current state of each voice, if the APU has run since the last call
+    pub fn push(&mut self, apu: &Apu) {
+        if *apu.total_clocks() == self.last_clocks {
+            return;
+        }
+        self.last_clocks = *apu.total_clocks();
+        let frame = core::array::from_fn(|i| {
+            let v = &apu.dsp().voices[i];
+            VoiceFrame {
+                envelope: v.envelope,
+                state: v.state,
+                note: match v.state {
+                    State::Release => None,
+                    _ if v.envelope == 0 => None,
+                    _ => Some(pitch_to_note(v.sample_pitch)),
+                },
+            }
+        });
+        if self.frames.len() == HISTORY_LEN {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+    /// Draw a voice's envelope over time, colored by the ADSR/GAIN stage it was in
+    pub fn envelope_image(&self, voice: usize) -> SharedPixelBuffer<Rgb8Pixel> {
+        let mut buf = background(HISTORY_LEN, GRAPH_HEIGHT);
+        let pixels = buf.make_mut_slice();
+        self.frames.iter().enumerate().for_each(|(x, f)| {
+            let f = f[voice];
+            let height = (f.envelope as f32 / ENVELOPE_MAX_VALUE * GRAPH_HEIGHT as f32) as usize;
+            let color = stage_color(f.state);
+            (GRAPH_HEIGHT - height.min(GRAPH_HEIGHT)..GRAPH_HEIGHT)
+                .for_each(|y| pixels[y * HISTORY_LEN + x] = color);
+        });
+        buf
+    }
+    /// Draw which voices were playing which notes over time
+    pub fn piano_roll_image(&self) -> SharedPixelBuffer<Rgb8Pixel> {
+        let height = HIGHEST_NOTE - LOWEST_NOTE;
+        let mut buf = background(HISTORY_LEN, height);
+        let pixels = buf.make_mut_slice();
+        (LOWEST_NOTE..HIGHEST_NOTE)
+            .filter(|n| n % 12 == 0)
+            .for_each(|n| {
+                let y = HIGHEST_NOTE - 1 - n;
+                pixels[y * HISTORY_LEN..(y + 1) * HISTORY_LEN].fill(OCTAVE_BACKGROUND);
+            });
+        self.frames.iter().enumerate().for_each(|(x, f)| {
+            f.iter().enumerate().for_each(|(i, v)| {
+                if let Some(note) = v.note {
+                    let note = note.round() as usize;
+                    if (LOWEST_NOTE..HIGHEST_NOTE).contains(&note) {
+                        let y = HIGHEST_NOTE - 1 - note;
+                        pixels[y * HISTORY_LEN + x] = VOICE_COLORS[i];
+                    }
+                }
+            })
+        });
+        buf
+    }
+}
+
+/// Draw the most recent outputs of a voice as an oscilloscope
+pub fn scope_image(samples: &VecDeque<i16>) -> SharedPixelBuffer<Rgb8Pixel> {
+    let mut buf = background(SCOPE_LEN, GRAPH_HEIGHT);
+    let pixels = buf.make_mut_slice();
+    let to_y =
+        |s: i16| ((i16::MAX as i32 - s as i32) as usize * (GRAPH_HEIGHT - 1)) / u16::MAX as usize;
+    let mut prev = None;
+    samples
+        .iter()
+        .take(SCOPE_LEN)
+        .enumerate()
+        .for_each(|(x, s)| {
+            let y = to_y(*s);
+            // Join each point to the previous one so that steep edges are still drawn
+            let (top, bottom) = match prev {
+                Some(p) => (y.min(p), y.max(p)),
+                None => (y, y),
+            };
+            (top..=bottom).for_each(|y| pixels[y * SCOPE_LEN + x] = SCOPE_COLOR);
+            prev = Some(y);
+        });
+    buf
+}
+
+fn background(width: usize, height: usize) -> SharedPixelBuffer<Rgb8Pixel> {
+    let mut buf = SharedPixelBuffer::new(width as u32, height as u32);
+    buf.make_mut_slice().fill(BACKGROUND);
+    buf
+}
+
+fn stage_color(state: State) -> Rgb8Pixel {
+    match state {
+        State::Adsr(AdsrStage::Attack) => VOICE_COLORS[0],
+        State::Adsr(AdsrStage::Decay) => VOICE_COLORS[1],
+        State::Adsr(AdsrStage::Sustain) => VOICE_COLORS[3],
+        State::Gain(_) => VOICE_COLORS[5],
+        State::Release => Rgb8Pixel::new(0xAA, 0xAA, 0xAA),
+    }
+}
@@ -5,6 +5,7 @@ use core::ops::DerefMut;
 use std::{
     env::args,
     fs,
+    io::stdin,
     time::{Duration, Instant},
 };
 
@@ -19,11 +20,44 @@ use sdl3::{
 };
 use super_yane::{
     Console, InputPort, MASTER_CLOCK_SPEED_HZ,
+    archive::{ArchiveFormat, RomChoice, choose_rom},
     ppu::{DOTS_PER_SCANLINE, SCANLINES, SCREEN_RESOLUTION},
     utils::color_to_rgb,
 };
 
 const SCREEN_SCALE: f32 = 3.0;
+
+/// Get the ROM out of a file, asking which ROM to use if it is an archive with several
+fn read_rom(path: &str, contents: Vec<u8>) -> Vec<u8> {
+    if ArchiveFormat::from_bytes(&contents).is_none() {
+        return contents;
+    }
+    let entries = match choose_rom(&contents, path) {
+        Ok(RomChoice::One(entry)) => return entry.data,
+        Ok(RomChoice::Ambiguous(entries)) => entries,
+        Err(e) => {
+            eprintln!("Unable to open archive '{}': {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    println!("'{}' has several ROMs:", path);
+    entries
+        .iter()
+        .enumerate()
+        .for_each(|(i, e)| println!("{}: {}", i, e.name));
+    loop {
+        println!("Pick a ROM to load:");
+        let mut line = String::new();
+        stdin()
+            .read_line(&mut line)
+            .expect("Unable to read input: ");
+        match line.trim().parse::<usize>() {
+            Ok(i) if i < entries.len() => return entries[i].data.clone(),
+            _ => println!("Invalid choice"),
+        }
+    }
+}
+
 fn main() {
     let context = sdl3::init().expect("Unable to initialize SDL3: ");
     let video = context.video().expect("Unable to initialize video: ");
@@ -49,7 +83,13 @@ fn main() {
                 c
             } else {
                 println!("Loading ROM");
-                Console::with_cartridge(&contents)
+                match Console::with_cartridge(&read_rom(&s, contents)) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        eprintln!("Unable to load ROM '{}': {}", s, e);
+                        std::process::exit(1);
+                    }
+                }
             }
         }
         None => panic!("No file provided"),
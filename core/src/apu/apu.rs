@@ -2,13 +2,18 @@ use derive_new::new;
 use std::collections::VecDeque;
 
 use crate::{
-    apu::{Dsp, voice::State},
+    apu::{
+        ApuBreak, ApuBreakpoints, Dsp, MidiRecorder, SpcFile,
+        brr::{BrrSample, decode_sample_directory, decode_sample_directory_entry},
+        voice::State,
+    },
+    port_log::{PendingPortAccesses, PortLog},
     utils::bit,
 };
-use log::{debug, error};
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 use serde_big_array::Array;
-use spc700::{HasAddressBus, IPL, Processor as Spc700Processor};
+use spc700::{HaltState, HasAddressBus, IPL, Processor as Spc700Processor};
 
 use derivative::Derivative;
 use paste::paste;
@@ -16,6 +21,14 @@ use paste::paste;
 pub const APU_RAM_SIZE: usize = 0x10000;
 /// Generate a new sample every 64 APU clocks (32 SPC700 clocks, or 96 ceramic resonator cycles)
 pub const CLOCKS_PER_SAMPLE: usize = 96;
+/// Value of the TEST register ($F0) on reset, with the timers and RAM writes enabled
+pub const TEST_REG_DEFAULT: u8 = 0x0A;
+/// Number of extra SPC700 cycles each memory access takes, indexed by the wait state bits in TEST
+const WAIT_STATES: [usize; 4] = [0, 1, 4, 9];
+
+fn default_test_reg() -> u8 {
+    TEST_REG_DEFAULT
+}
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, new)]
 pub struct ApuTimer {
@@ -39,6 +52,13 @@ pub struct ApuTimer {
 pub struct Apu {
     pub core: Spc700Processor,
     pub rest: ApuMemory,
+    /// Emulate the IPL ROM's upload protocol directly instead of running it on the SPC700.
+    /// The ports, registers and RAM end up the same as if the ROM had been run, but uploads finish much sooner.
+    #[serde(default)]
+    pub fast_ipl: bool,
+    /// Address of the last instruction the SPC700 started running
+    #[serde(skip)]
+    last_pc: u16,
 }
 
 // Internal struct used to advance the APU core
@@ -57,20 +77,62 @@ pub struct ApuMemory {
     pub dsp_addr: u8,
     pub dsp: Dsp,
     pub dsp_read_only: bool,
+    /// The TEST register ($F0)
+    #[derivative(Default(value = "TEST_REG_DEFAULT"))]
+    #[serde(default = "default_test_reg")]
+    pub test: u8,
+    /// Whether P is set in the SPC700's PSW, in which case writes to TEST are ignored
+    /// Updated before each instruction
+    #[serde(skip)]
+    pub direct_page_high: bool,
+    /// Accesses to $F4-$F7 since they were last taken, when the port log is enabled
+    #[serde(skip)]
+    pub port_accesses: PendingPortAccesses,
+    /// Breakpoints set by the debugger
+    #[serde(skip)]
+    pub breakpoints: ApuBreakpoints,
+    /// The first breakpoint hit since it was last taken
+    #[serde(skip)]
+    breakpoint_hit: Option<ApuBreak>,
 }
 
 impl ApuMemory {
+    /// Whether the timers are running, according to the TEST register
+    /// Bit 0 halts the timers when set, and bit 3 halts them when clear
+    pub fn timers_running(&self) -> bool {
+        !bit(self.test, 0) && bit(self.test, 3)
+    }
+    /// Whether the SPC700 can write to RAM, according to the TEST register
+    /// Only writes made by the SPC700 are affected, so the DSP still writes the echo buffer to RAM
+    pub fn ram_writable(&self) -> bool {
+        bit(self.test, 1)
+    }
+    /// Number of APU clocks an access to `address` takes, including any wait states set in TEST
+    fn access_clocks(&self, address: usize) -> usize {
+        let wait_states = match address {
+            // IO registers and the IPL ROM
+            0x00F0..0x0100 => (self.test >> 6) & 0x03,
+            0xFFC0..0x1_0000 if self.expose_ipl_rom => (self.test >> 6) & 0x03,
+            // RAM
+            _ => (self.test >> 4) & 0x03,
+        };
+        2 * (1 + WAIT_STATES[wait_states as usize])
+    }
     pub fn advance_apu_clocks(&mut self, clocks: usize) {
         // Advance timers
         (0..clocks).for_each(|_| {
             self.total_clocks += 1;
-            if self.total_clocks % 3 == 0 {
+            if self.total_clocks.is_multiple_of(3) {
+                let timers_running = self.timers_running();
                 // Clock the timers every 128 (timers 0 and 1) or 16 (timer 2) APU cycles
                 [128, 128, 16]
                     .into_iter()
                     .enumerate()
                     .for_each(|(i, clks)| {
-                        if (self.total_clocks / 3) % clks == 0 {
+                        if timers_running
+                            && self.timers[i].enabled
+                            && (self.total_clocks / 3).is_multiple_of(clks)
+                        {
                             let t = &mut self.timers[i];
                             // Increment timer and increment counter if it overflows
                             t.value = t.value.wrapping_add(1);
@@ -80,9 +142,8 @@ impl ApuMemory {
                             }
                         }
                     });
-                if self.total_clocks % CLOCKS_PER_SAMPLE == 0 {
-                    self.dsp.generate_sample(self.ram.as_mut_slice());
-                }
+                // The DSP runs one step per SPC700 clock, and generates a sample every `CLOCKS_PER_SAMPLE` clocks
+                self.dsp.step(self.ram.as_mut_slice());
             }
         });
     }
@@ -94,13 +155,29 @@ impl HasAddressBus for ApuMemory {
         self.advance_apu_clocks(2);
     }
     fn read(&mut self, address: usize) -> u8 {
-        self.advance_apu_clocks(2);
+        let clock = self.total_clocks as u64;
+        self.advance_apu_clocks(self.access_clocks(address));
+        if self.breakpoints.read.contains(&(address as u16)) {
+            self.breakpoint_hit
+                .get_or_insert(ApuBreak::Read(address as u16));
+        }
         match address {
+            // TEST and CONTROL are write only
             0xF0 => 0,
             0xF1 => 0,
             0x00F2 => self.dsp_addr as u8,
             0x00F3 => self.dsp.read(self.dsp_addr as usize),
-            0x00F4..0x00F8 => self.cpu_to_apu_reg[address - 0x00F4],
+            0x00F4..0x00F8 => {
+                let value = self.cpu_to_apu_reg[address - 0x00F4];
+                PortLog::record(
+                    &mut self.port_accesses,
+                    clock,
+                    false,
+                    address - 0x00F4,
+                    value,
+                );
+                value
+            }
             0x00FD..0x0100 => {
                 let v = self.timers[address - 0x00FD].counter;
                 self.timers[address - 0x00FD].counter = 0;
@@ -118,9 +195,21 @@ impl HasAddressBus for ApuMemory {
         }
     }
     fn write(&mut self, address: usize, value: u8) {
-        self.advance_apu_clocks(2);
+        let clock = self.total_clocks as u64;
+        self.advance_apu_clocks(self.access_clocks(address));
+        if self.breakpoints.write.contains(&(address as u16)) {
+            self.breakpoint_hit
+                .get_or_insert(ApuBreak::Write(address as u16));
+        }
         match address {
-            0xF0 => todo!(),
+            // TEST can only be written while P is clear
+            0xF0 if self.direct_page_high => {}
+            0xF0 => {
+                if bit(value, 2) {
+                    warn!("TEST register write {:02X} would crash the SPC700", value);
+                }
+                self.test = value;
+            }
             0x00F1 => {
                 self.expose_ipl_rom = (value & 0x80) != 0;
                 if value & 0x10 != 0 {
@@ -132,11 +221,14 @@ impl HasAddressBus for ApuMemory {
                     self.cpu_to_apu_reg[3] = 0x00;
                 }
                 (0..3).for_each(|i| {
-                    if bit(value, i) {
-                        self.timers[i].enabled = false;
-                        self.timers[i].value = 0;
-                        self.timers[i].counter = 0;
+                    let t = &mut self.timers[i];
+                    let enabled = bit(value, i);
+                    // Timers restart when they go from disabled to enabled
+                    if enabled && !t.enabled {
+                        t.value = 0;
+                        t.counter = 0;
                     }
+                    t.enabled = enabled;
                 });
             }
             0x00F2 => {
@@ -149,12 +241,23 @@ impl HasAddressBus for ApuMemory {
                 }
             }
             0x00F4..0x00F8 => {
+                PortLog::record(
+                    &mut self.port_accesses,
+                    clock,
+                    true,
+                    address - 0x00F4,
+                    value,
+                );
                 self.apu_to_cpu_reg[address - 0x00F4] = value;
             }
             0x00FA..0x00FD => {
                 self.timers[address - 0x00FA].target = value;
             }
-            _ => self.ram[address] = value,
+            _ => {
+                if self.ram_writable() {
+                    self.ram[address] = value;
+                }
+            }
         }
     }
 }
@@ -175,15 +278,154 @@ macro_rules! rest_field {
 impl Apu {
     rest_field! {total_clocks, usize}
     rest_field! {dsp, Dsp}
+    rest_field! {breakpoints, ApuBreakpoints}
     pub fn ram(&self) -> &[u8] {
         self.rest.ram.as_slice()
     }
-    /// Takes the CPU to APU side registers and returns the values written to the APU to CPU registers
-    pub fn step(&mut self, cpu_reg: &mut [u8; 4]) -> [u8; 4] {
+    /// Takes the CPU to APU side registers and returns the values written to the APU to CPU registers,
+    /// along with whether the SPC700 is halted
+    pub fn step(&mut self, cpu_reg: &mut [u8; 4]) -> ([u8; 4], Option<HaltState>) {
         self.rest.cpu_to_apu_reg = cpu_reg.clone();
-        self.core.step(&mut self.rest);
+        let was_halted = self.core.halted.is_some();
+        self.last_pc = self.core.pc;
+        if !(self.fast_ipl && self.rest.expose_ipl_rom && self.step_fast_ipl()) {
+            self.rest.direct_page_high = self.core.psw.p;
+            self.core.step(&mut self.rest);
+        }
         *cpu_reg = self.rest.cpu_to_apu_reg;
-        self.rest.apu_to_cpu_reg
+        if let (false, Some(h)) = (was_halted, self.core.halted) {
+            warn!(
+                "SPC700 halted by {} at {:04X}",
+                h,
+                self.core.pc.wrapping_sub(1)
+            );
+        }
+        if self.core.halted.is_none() && self.rest.breakpoints.pc.contains(&self.core.pc) {
+            self.rest
+                .breakpoint_hit
+                .get_or_insert(ApuBreak::Pc(self.core.pc));
+        }
+        (self.rest.apu_to_cpu_reg, self.core.halted)
+    }
+    /// Run the part of the IPL ROM at the current PC, if it is one of the loops in the upload protocol.
+    /// Returns whether it did.
+    fn step_fast_ipl(&mut self) -> bool {
+        let c = &mut self.core;
+        let r = &mut self.rest;
+        match c.pc {
+            // Reset, clear the zero page and tell the CPU the APU is ready
+            0xFFC0 => {
+                (0x01..=0xEF).for_each(|i| r.ram[i] = 0);
+                c.sp = 0xEF;
+                c.a = 0;
+                c.x = 0;
+                // Left by the last DEC X of the loop
+                c.psw.n = false;
+                c.psw.z = true;
+                r.apu_to_cpu_reg[0] = 0xAA;
+                r.apu_to_cpu_reg[1] = 0xBB;
+                c.pc = 0xFFCF;
+            }
+            // Wait for the CPU to write $CC, then start the first block
+            0xFFCF => {
+                if r.cpu_to_apu_reg[0] == 0xCC {
+                    // CMP $F4, #$CC
+                    c.psw.c = true;
+                    Self::start_ipl_block(c, r);
+                }
+            }
+            // Wait for the CPU to send the first byte of the block
+            0xFFD6 => {
+                c.y = r.cpu_to_apu_reg[0];
+                if c.y == 0 {
+                    c.pc = 0xFFDA;
+                }
+            }
+            // Transfer a byte, or start the next block once the CPU skips ahead
+            0xFFDA => {
+                let index = r.cpu_to_apu_reg[0];
+                if index == c.y {
+                    c.a = r.cpu_to_apu_reg[1];
+                    r.apu_to_cpu_reg[0] = c.y;
+                    let addr = u16::from_le_bytes([r.ram[0x00], r.ram[0x01]]);
+                    r.write(addr.wrapping_add(c.y as u16) as usize, c.a);
+                    c.y = c.y.wrapping_add(1);
+                    if c.y == 0 {
+                        r.ram[0x01] = r.ram[0x01].wrapping_add(1);
+                    }
+                } else if bit(c.y.wrapping_sub(index), 7) {
+                    // CMP Y, $F4
+                    c.psw.c = c.y >= index;
+                    Self::start_ipl_block(c, r);
+                }
+            }
+            _ => return false,
+        }
+        // Each loop takes at least a few cycles
+        r.io();
+        true
+    }
+    /// Read the address of the next block, and either start transferring it or jump to it
+    fn start_ipl_block(c: &mut Spc700Processor, r: &mut ApuMemory) {
+        r.ram[0x00] = r.cpu_to_apu_reg[2];
+        r.ram[0x01] = r.cpu_to_apu_reg[3];
+        r.apu_to_cpu_reg[0] = r.cpu_to_apu_reg[0];
+        c.y = r.cpu_to_apu_reg[1];
+        c.a = c.y;
+        c.x = c.y;
+        // MOV X, A
+        c.psw.n = bit(c.x, 7);
+        c.psw.z = c.x == 0;
+        c.pc = if c.x != 0 {
+            0xFFD6
+        } else {
+            u16::from_le_bytes([r.ram[0x00], r.ram[0x01]])
+        };
+    }
+    /// Copy `blocks` of data into RAM and jump to `entry`, without running the IPL ROM's handshake.
+    /// Leaves the APU as the IPL ROM would after the CPU uploaded the blocks with the usual protocol,
+    /// starting each block with the index after the last byte plus one.
+    pub fn upload(&mut self, blocks: &[(u16, &[u8])], entry: u16) {
+        let c = &mut self.core;
+        let r = &mut self.rest;
+        (0x01..=0xEF).for_each(|i| r.ram[i] = 0);
+        blocks.iter().for_each(|(addr, data)| {
+            data.iter().enumerate().for_each(|(i, b)| {
+                r.write(addr.wrapping_add(i as u16) as usize, *b);
+            });
+        });
+        r.ram[0x00..0x02].copy_from_slice(&entry.to_le_bytes());
+        // The value the CPU writes to port 0 to jump to the entry point
+        let (kick, carry) = match blocks.last() {
+            Some((_, data)) => ((data.len() as u8).wrapping_add(1), false),
+            None => (0xCC, true),
+        };
+        r.apu_to_cpu_reg[0] = kick;
+        r.apu_to_cpu_reg[1] = 0xBB;
+        c.sp = 0xEF;
+        c.a = 0;
+        c.x = 0;
+        c.y = 0;
+        c.psw.n = false;
+        c.psw.z = true;
+        c.psw.c = carry;
+        c.pc = entry;
+    }
+    /// Address of the instruction the SPC700 stopped on for a breakpoint it just hit
+    /// For reads and writes, this is the instruction that made the access
+    pub fn stop_location(&self, hit: ApuBreak) -> u16 {
+        match hit {
+            ApuBreak::Pc(pc) => pc,
+            ApuBreak::Read(_) | ApuBreak::Write(_) => self.last_pc,
+        }
+    }
+    /// Take the first breakpoint the SPC700 has hit since the last call
+    pub fn take_breakpoint_hit(&mut self) -> Option<ApuBreak> {
+        self.rest.breakpoint_hit.take()
+    }
+    /// Why the SPC700 is halted, if it is
+    pub fn halted(&self) -> Option<HaltState> {
+        self.core.halted
     }
     /// Reads from ram, and thus doesn't require a mutable reference
     pub fn read_ram(&self, address: usize) -> u8 {
@@ -199,15 +441,42 @@ impl Apu {
             _ => 0,
         }
     }
+    /// Writes to ram directly, without any of the side effects of a write by the SPC700
+    /// Writes under the IPL ROM go to the RAM it hides
+    pub fn write_ram(&mut self, address: usize, value: u8) {
+        self.rest.ram[address % APU_RAM_SIZE] = value;
+    }
+    /// Start recording the notes played by the DSP
+    pub fn start_midi_recording(&mut self) {
+        self.rest.dsp.midi_recorder = Some(MidiRecorder::default());
+    }
+    /// Stop recording notes, and return the recording as a Standard MIDI File
+    pub fn stop_midi_recording(&mut self) -> Option<Vec<u8>> {
+        self.rest.dsp.midi_recorder.take().map(|r| r.to_smf())
+    }
+    /// Decode every sample in the DSP's sample directory
+    pub fn brr_samples(&self) -> Vec<BrrSample> {
+        decode_sample_directory(self.ram(), self.rest.dsp.sample_dir)
+    }
+    /// Decode a single sample in the sample directory, without decoding the rest
+    pub fn brr_sample(&self, index: usize) -> Option<BrrSample> {
+        decode_sample_directory_entry(self.ram(), self.rest.dsp.sample_dir, index)
+    }
     pub fn sample_queue(&mut self) -> VecDeque<f32> {
         let mut s = VecDeque::new();
         std::mem::swap(&mut self.rest.dsp.sample_queue, &mut s);
         s
     }
+    /// Take the samples captured from each voice since the last call
+    /// Only filled while `Dsp::capture_voices` is set
+    pub fn voice_sample_queues(&mut self) -> [VecDeque<f32>; 8] {
+        core::array::from_fn(|i| std::mem::take(&mut self.rest.dsp.voice_sample_queues[i]))
+    }
 
     pub fn reset(&mut self) {
         self.core.reset();
         self.rest.expose_ipl_rom = true;
+        self.rest.test = TEST_REG_DEFAULT;
         self.rest.timers.iter_mut().for_each(|i| i.counter = 0);
         // Silence every voice
         self.rest
@@ -216,4 +485,52 @@ impl Apu {
             .iter_mut()
             .for_each(|c| c.state = State::Release);
     }
+
+    /// Replace the state of the APU with the one saved in an SPC file
+    pub fn load_spc(&mut self, spc: &SpcFile) {
+        let dsp = &mut self.rest.dsp;
+        let (muted, solo, capture) = (dsp.voice_muted, dsp.voice_solo, dsp.capture_voices);
+        let (filter, recorder) = (dsp.output_filter_enabled, dsp.midi_recorder.take());
+        let scope_len = dsp.scope_len;
+        let fast_ipl = self.fast_ipl;
+        let breakpoints = std::mem::take(&mut self.rest.breakpoints);
+        *self = Apu::default();
+        self.fast_ipl = fast_ipl;
+        self.rest.breakpoints = breakpoints;
+        // Keep the debug settings
+        let dsp = &mut self.rest.dsp;
+        dsp.voice_muted = muted;
+        dsp.voice_solo = solo;
+        dsp.capture_voices = capture;
+        dsp.output_filter_enabled = filter;
+        dsp.midi_recorder = recorder;
+        dsp.scope_len = scope_len;
+        self.core = spc.core;
+        let r = &mut self.rest;
+        r.ram.copy_from_slice(&spc.ram);
+        // Restore the IO registers from their values in RAM
+        // TEST is left at its default value, since many dumps have it set to 0, which would halt the timers
+        let control = spc.ram[0xF1];
+        r.expose_ipl_rom = bit(control, 7);
+        if r.expose_ipl_rom {
+            r.ram[0xFFC0..].copy_from_slice(&spc.extra_ram);
+        }
+        r.dsp_addr = spc.ram[0xF2] & 0x7F;
+        r.dsp_read_only = bit(spc.ram[0xF2], 7);
+        r.cpu_to_apu_reg.copy_from_slice(&spc.ram[0xF4..0xF8]);
+        r.timers.iter_mut().enumerate().for_each(|(i, t)| {
+            t.enabled = bit(control, i);
+            t.target = spc.ram[0xFA + i];
+            t.counter = spc.ram[0xFD + i] & 0x0F;
+        });
+        // Restore the DSP registers, keying on voices last so that they start with the
+        // rest of their registers already set
+        spc.dsp_registers
+            .iter()
+            .enumerate()
+            // Skip KON and the read only ENVX/OUTX registers
+            .filter(|(addr, _)| *addr != 0x4C && !(0x08..0x0A).contains(&(addr & 0x0F)))
+            .for_each(|(addr, value)| r.dsp.write(addr, *value));
+        r.dsp.write(0x4C, spc.dsp_registers[0x4C]);
+    }
 }
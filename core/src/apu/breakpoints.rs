@@ -0,0 +1,41 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+/// Breakpoints on the SPC700, used when debugging
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApuBreakpoints {
+    /// Stop before running the instruction at any of these addresses
+    pub pc: BTreeSet<u16>,
+    /// Stop after the SPC700 reads any of these addresses, including when fetching instructions
+    pub read: BTreeSet<u16>,
+    /// Stop after the SPC700 writes to any of these addresses
+    pub write: BTreeSet<u16>,
+}
+
+impl ApuBreakpoints {
+    pub fn is_empty(&self) -> bool {
+        self.pc.is_empty() && self.read.is_empty() && self.write.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.pc.clear();
+        self.read.clear();
+        self.write.clear();
+    }
+}
+
+/// A breakpoint that the SPC700 has hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApuBreak {
+    Pc(u16),
+    Read(u16),
+    Write(u16),
+}
+
+impl Display for ApuBreak {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApuBreak::Pc(a) => write!(f, "PC {:04X}", a),
+            ApuBreak::Read(a) => write!(f, "read from {:04X}", a),
+            ApuBreak::Write(a) => write!(f, "write to {:04X}", a),
+        }
+    }
+}
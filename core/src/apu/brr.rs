@@ -0,0 +1,164 @@
+use crate::utils::bit;
+
+/// Size of a BRR block, in bytes
+pub const BRR_BLOCK_SIZE: usize = 9;
+/// Number of samples encoded in a BRR block
+pub const BRR_BLOCK_SAMPLES: usize = 16;
+/// Number of entries in the sample directory
+pub const SAMPLE_DIR_ENTRIES: usize = 0x100;
+
+/// The first byte of a BRR block
+#[derive(Debug, Clone, Copy)]
+pub struct BrrHeader {
+    pub shift: u8,
+    pub filter: u8,
+    pub loop_flag: bool,
+    pub end_flag: bool,
+}
+
+impl From<u8> for BrrHeader {
+    fn from(value: u8) -> Self {
+        BrrHeader {
+            // High nibble
+            shift: value >> 4,
+            filter: (value >> 2) & 0x03,
+            loop_flag: bit(value, 1),
+            end_flag: bit(value, 0),
+        }
+    }
+}
+
+/// Decode a single BRR block.
+/// `old` and `older` are the previous two decoded samples, which are used by the filters.
+pub fn decode_block(block: &[u8; BRR_BLOCK_SIZE], old: i16, older: i16) -> (BrrHeader, [i16; 16]) {
+    let header = BrrHeader::from(block[0]);
+    let shift = header.shift;
+    // Read and parse nibbles
+    let sample_bytes: [[i16; 2]; 8] = core::array::from_fn(|i| block[1 + i]).map(|v| {
+        [v >> 4, v & 0xF].map(|v| {
+            // If negative, flip all the other bits
+            let v = if v > 0x07 {
+                0xFFF0 | (v as u16)
+            } else {
+                v as u16
+            };
+            // shift right by 1 since the sample is out of 15 bits
+            if shift >= 0xD {
+                // When shift=13..15, decoding works as if shift=12 and nibble=(nibble SAR 3).
+                (((v >> 3) << 12) as i16) >> 1
+            } else {
+                ((v << shift) as i16) >> 1
+            }
+        })
+    });
+    let mut old = old as i32;
+    let mut older = older as i32;
+    let samples: [i16; 16] = core::array::from_fn(|i| {
+        let s = sample_bytes.as_flattened()[i] as i32;
+        let value = match header.filter {
+            0 => s,
+            1 => s + old + ((-old) >> 4),
+            2 => s + 2 * old + ((-3 * old) >> 5) - older + (older >> 4),
+            3 => s + 2 * old + ((-13 * old) >> 6) - older + ((older * 3) >> 4),
+            _ => unreachable!(),
+        } as i16;
+        older = old;
+        old = value as i32;
+        value
+    });
+    (header, samples)
+}
+
+/// Read the BRR block at `addr`, wrapping around the end of RAM
+pub fn read_block(ram: &[u8], addr: usize) -> [u8; BRR_BLOCK_SIZE] {
+    core::array::from_fn(|i| ram[(addr + i) % ram.len()])
+}
+
+/// A sample from the sample directory, decoded to PCM
+#[derive(Debug, Clone)]
+pub struct BrrSample {
+    /// Index of the sample in the sample directory
+    pub index: usize,
+    /// Address of the first BRR block
+    pub start_addr: usize,
+    /// Address of the block to jump to when the sample loops
+    pub loop_addr: usize,
+    /// The decoded samples, at 32kHz
+    pub samples: Vec<i16>,
+    /// The index in `samples` that playback returns to once the end is reached,
+    /// or `None` if the sample does not loop back into itself
+    pub loop_point: Option<usize>,
+}
+
+impl BrrSample {
+    /// Decode the sample starting at `start_addr`.
+    /// Returns `None` if no end block is found before running through all of RAM.
+    pub fn decode(ram: &[u8], index: usize, start_addr: usize, loop_addr: usize) -> Option<Self> {
+        let mut samples: Vec<i16> = Vec::new();
+        let mut addr = start_addr;
+        let max_blocks = ram.len() / BRR_BLOCK_SIZE;
+        for _ in 0..max_blocks {
+            let (old, older) = match samples.len() {
+                0 => (0, 0),
+                n => (samples[n - 1], samples[n - 2]),
+            };
+            let (header, block) = decode_block(&read_block(ram, addr), old, older);
+            samples.extend_from_slice(&block);
+            if header.end_flag {
+                let offset = loop_addr.wrapping_sub(start_addr);
+                let loop_point = if header.loop_flag
+                    && offset <= addr - start_addr
+                    && offset.is_multiple_of(BRR_BLOCK_SIZE)
+                {
+                    Some(offset / BRR_BLOCK_SIZE * BRR_BLOCK_SAMPLES)
+                } else {
+                    None
+                };
+                return Some(BrrSample {
+                    index,
+                    start_addr,
+                    loop_addr,
+                    samples,
+                    loop_point,
+                });
+            }
+            addr += BRR_BLOCK_SIZE;
+        }
+        None
+    }
+}
+
+/// Start and loop addresses of an entry in the sample directory at `sample_dir`
+fn directory_entry(ram: &[u8], sample_dir: usize, index: usize) -> (usize, usize) {
+    let u16_at =
+        |addr: usize| u16::from_le_bytes([ram[addr % ram.len()], ram[(addr + 1) % ram.len()]]);
+    let entry = sample_dir + 4 * index;
+    (u16_at(entry) as usize, u16_at(entry + 2) as usize)
+}
+
+/// Decode a single sample in the sample directory at `sample_dir`.
+/// Returns `None` if the sample never reaches an end block.
+pub fn decode_sample_directory_entry(
+    ram: &[u8],
+    sample_dir: usize,
+    index: usize,
+) -> Option<BrrSample> {
+    let (start, loop_addr) = directory_entry(ram, sample_dir, index);
+    BrrSample::decode(ram, index, start, loop_addr)
+}
+
+/// Decode every sample in the sample directory at `sample_dir`.
+/// Entries which point to the same sample as a previous entry, or never reach an end block, are skipped.
+pub fn decode_sample_directory(ram: &[u8], sample_dir: usize) -> Vec<BrrSample> {
+    let mut samples: Vec<BrrSample> = Vec::new();
+    (0..SAMPLE_DIR_ENTRIES).for_each(|i| {
+        let (start, loop_addr) = directory_entry(ram, sample_dir, i);
+        if samples.iter().any(|s| s.start_addr == start) {
+            return;
+        }
+        if let Some(s) = BrrSample::decode(ram, i, start, loop_addr) {
+            samples.push(s);
+        }
+    });
+    samples
+}
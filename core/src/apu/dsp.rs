@@ -1,6 +1,6 @@
 use crate::{
     apu::{
-        Voice,
+        MidiRecorder, OutputFilter, Voice,
         constants::{LEFT, PERIOD_TABLE, RIGHT},
         voice::AdsrStage,
     },
@@ -10,8 +10,41 @@ use derivative::Derivative;
 use log::*;
 use seeded_random::{Random, Seed};
 use serde::{Deserialize, Serialize};
+use serde_big_array::Array;
 use std::collections::VecDeque;
 
+/// Number of DSP registers
+pub const NUM_DSP_REGISTERS: usize = 0x80;
+/// Number of steps the DSP takes to generate each sample, one per SPC700 clock
+pub const STEPS_PER_SAMPLE: usize = 32;
+/// Step on which a new sample is started
+const START_STEP: usize = 0;
+/// Number of steps between each voice running a stage, so voice 1 runs each stage 3 steps after voice 0
+const VOICE_STAGE_SPACING: usize = 3;
+/// Steps on which voice 0 runs each of its stages, which follow the hardware's schedule
+/// Read the voice's entry in the sample directory
+const DIRECTORY_STEP: usize = 29;
+/// Interpolate the sample and apply the envelope, then clock the envelope
+const INTERPOLATE_STEP: usize = 30;
+/// Advance the pitch counter and decode BRR, then output the left side
+const ADVANCE_STEP: usize = 31;
+/// Output the right side
+const RIGHT_OUTPUT_STEP: usize = 0;
+/// Update OUTX
+const OUTX_STEP: usize = 3;
+/// Update ENVX
+const ENVX_STEP: usize = 4;
+/// Step on which voices are keyed on and off
+const KEY_STEP: usize = 30;
+/// Steps on which the echo buffer is read, left then right
+const ECHO_READ_STEPS: [usize; 2] = [22, 23];
+/// Step on which the FIR filter is applied to the echo
+const ECHO_FILTER_STEP: usize = 25;
+/// Step on which the voices and echo are mixed into the final output
+const OUTPUT_STEP: usize = 27;
+/// Steps on which the echo is written back to the buffer, left then right
+const ECHO_WRITE_STEPS: [usize; 2] = [29, 30];
+
 /// The DSP
 #[derive(Clone, Derivative, Serialize, Deserialize)]
 #[derivative(Default)]
@@ -50,31 +83,82 @@ pub struct Dsp {
     pub echo_enabled: bool,
     #[serde(default)]
     pub mute: bool,
+    /// The values last written to each register
+    #[serde(default)]
+    registers: Array<u8, NUM_DSP_REGISTERS>,
+    /// Whether `registers` is up to date, which isn't the case for savestates made before it was added
+    /// Until it is rebuilt, registers are read back from the state of the DSP instead
+    #[serde(default)]
+    registers_valid: bool,
+    /// Voices silenced in the output, for debugging
+    /// Does not affect the emulated state of the voice
+    #[serde(skip)]
+    pub voice_muted: [bool; 8],
+    /// Voices to play alone, for debugging
+    /// If any voice is soloed, every voice that is not is silenced
+    #[serde(skip)]
+    pub voice_solo: [bool; 8],
+    /// Whether to record the output of each voice into its own sample queue
+    #[serde(skip)]
+    pub capture_voices: bool,
+    /// The output of each voice, when `capture_voices` is set
+    #[serde(skip)]
+    pub(super) voice_sample_queues: [VecDeque<f32>; 8],
+    /// Whether to pass the output through a model of the console's analog filter
+    /// Off by default, so that the output is exactly what the DSP generates
+    #[serde(skip)]
+    pub output_filter_enabled: bool,
+    #[serde(skip)]
+    output_filter: OutputFilter,
+    /// Number of recent outputs to keep for each voice, for drawing oscilloscopes
+    /// None are kept when this is 0
+    #[serde(skip)]
+    pub scope_len: usize,
+    #[serde(skip)]
+    voice_scopes: [VecDeque<i16>; 8],
+    /// Records the notes being played, while recording
+    #[serde(skip)]
+    pub midi_recorder: Option<MidiRecorder>,
+    /// The step that will be run next
+    #[serde(default)]
+    step_index: usize,
+    /// The noise value for the current sample
+    #[serde(default)]
+    noise_value: i32,
+    /// Output of each voice for the current sample, left then right
+    #[serde(default)]
+    voice_outputs: [[i32; 2]; 8],
+    /// Output of the echo for the current sample, left then right
+    #[serde(default)]
+    echo_out: [i16; 2],
+    /// Value to write back to the echo buffer for the current sample, left then right
+    #[serde(default)]
+    echo_write: [i16; 2],
+    /// Voices written to KON which have not been keyed on yet
+    #[serde(default)]
+    key_on: u8,
+    /// Voices written to KOFF which have not been keyed off yet
+    #[serde(default)]
+    key_off: u8,
 }
 
 impl Dsp {
     pub fn write(&mut self, address: usize, value: u8) {
+        if !self.registers_valid {
+            self.registers = Array(core::array::from_fn(|i| self.register_from_state(i)));
+            self.registers_valid = true;
+        }
+        if address < NUM_DSP_REGISTERS {
+            self.registers[address] = value;
+        }
         match address {
             0x0C => self.volume[LEFT] = value as i8,
             0x1C => self.volume[RIGHT] = value as i8,
             0x2C => self.echo_volume[LEFT] = value as i8,
             0x3C => self.echo_volume[RIGHT] = value as i8,
-            // KON
-            0x4C => {
-                self.voices.iter_mut().enumerate().for_each(|(i, v)| {
-                    if bit(value, i) {
-                        v.key_on();
-                    }
-                });
-            }
-            // KOFF
-            0x5C => {
-                self.voices.iter_mut().enumerate().for_each(|(i, v)| {
-                    if bit(value, i) {
-                        v.key_off();
-                    }
-                });
-            }
+            // KON and KOFF are applied on the next KEY_STEP
+            0x4C => self.key_on |= value,
+            0x5C => self.key_off |= value,
             0x6C => {
                 // Soft reset
                 if bit(value, 7) {
@@ -132,68 +216,194 @@ impl Dsp {
                 debug!("Unknown DSP register {address:02X} value={value:02X}");
             }
         }
+        if let Some(r) = &mut self.midi_recorder {
+            r.on_write(address, value, &self.voices);
+        }
     }
-    pub fn read(&mut self, address: usize) -> u8 {
-        match address % 0x80 {
-            0x0D => self.echo_feedback as u8,
-            0x2C => self.echo_volume[0] as u8,
-            0x3C => self.echo_volume[1] as u8,
-            0x4D => self
+    pub fn read(&self, address: usize) -> u8 {
+        let address = address % NUM_DSP_REGISTERS;
+        match address {
+            0x7C => self
                 .voices
                 .iter()
                 .enumerate()
-                .map(|(i, c)| u8::from(c.echo_enabled) << i)
+                .map(|(i, v)| u8::from(v.end_flag) << (7 - i))
                 .sum(),
+            // ENVX and OUTX are updated by the voices
+            reg if (0x08..0x0A).contains(&(reg & 0x0F)) => self.voices[reg >> 4].read(address),
+            // Everything else reads back what was written
+            _ if self.registers_valid => self.registers[address],
+            _ => self.register_from_state(address),
+        }
+    }
+    /// Work out what was last written to a register from the state of the DSP
+    /// Used to rebuild the registers when loading a savestate that doesn't have them
+    fn register_from_state(&self, address: usize) -> u8 {
+        let bits = |f: &dyn Fn(&Voice) -> bool| {
+            self.voices
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, v)| acc | (u8::from(f(v)) << i))
+        };
+        match address {
+            0x0C => self.volume[LEFT] as u8,
+            0x1C => self.volume[RIGHT] as u8,
+            0x2C => self.echo_volume[LEFT] as u8,
+            0x3C => self.echo_volume[RIGHT] as u8,
             0x6C => {
-                warn!("DSP register 6C not implemented");
-                0
+                let noise = PERIOD_TABLE
+                    .iter()
+                    .position(|p| *p == self.noise_frequency)
+                    .unwrap_or(0);
+                (u8::from(self.mute) << 6) | (u8::from(!self.echo_enabled) << 5) | noise as u8
             }
+            0x0D => self.echo_feedback as u8,
+            0x2D => bits(&|v| v.pitch_mod_enabled),
+            0x3D => bits(&|v| v.noise_enabled),
+            0x4D => bits(&|v| v.echo_enabled),
+            0x5D => (self.sample_dir >> 8) as u8,
             0x6D => (self.echo_addr >> 8) as u8,
-            0x7C => self
-                .voices
-                .iter()
-                .enumerate()
-                .map(|(i, v)| u8::from(v.end_flag) << (7 - i))
-                .sum(),
             0x7D => (self.echo_size / 512) as u8,
-            reg if address & 0x0F < 0x0A => {
-                let index = (reg / 0x10) & 0x0F;
-                if index < self.voices.len() {
-                    self.voices[index].read(address)
-                } else {
-                    0
-                }
-            }
-            _ => todo!("Read {:04X}", address),
+            reg if reg & 0x0F < 0x08 => self.voices[reg >> 4].read(reg),
+            reg if reg & 0x0F == 0x0F => self.fir_coeffs[reg >> 4] as u8,
+            // KON, KOFF and the unused registers can't be worked out
+            _ => 0,
+        }
+    }
+    /// Whether a voice can be heard, taking into account the debug mute and solo flags
+    pub fn is_voice_audible(&self, voice: usize) -> bool {
+        !self.voice_muted[voice] && (!self.voice_solo.iter().any(|s| *s) || self.voice_solo[voice])
+    }
+    /// The most recent outputs of each voice, oldest first, up to `scope_len` of them
+    pub fn voice_scopes(&self) -> &[VecDeque<i16>; 8] {
+        &self.voice_scopes
+    }
+    /// Run a single step of the DSP.
+    /// Each sample is generated over `STEPS_PER_SAMPLE` steps, one per SPC700 clock,
+    /// so that register reads and writes made while a sample is being generated
+    /// only see or affect the work done after them, as on hardware.
+    pub fn step(&mut self, ram: &mut [u8]) {
+        if self.step_index == KEY_STEP {
+            self.apply_keys();
         }
+        (0..self.voices.len()).for_each(|i| self.run_voice_stage(i, ram));
+        match self.step_index {
+            START_STEP => self.start_sample(),
+            s if ECHO_READ_STEPS.contains(&s) => self.read_echo(s - ECHO_READ_STEPS[0], ram),
+            ECHO_FILTER_STEP => self.filter_echo(),
+            OUTPUT_STEP => self.output_sample(),
+            s if ECHO_WRITE_STEPS.contains(&s) => self.write_echo(s - ECHO_WRITE_STEPS[0], ram),
+            _ => {}
+        }
+        self.step_index = (self.step_index + 1) % STEPS_PER_SAMPLE;
     }
+    /// Run all the steps needed to generate a sample
     pub fn generate_sample(&mut self, ram: &mut [u8]) {
-        let mut prev_pitch: i32 = 0;
+        (0..STEPS_PER_SAMPLE).for_each(|_| self.step(ram));
+    }
+    /// Index of the step that will be run next
+    pub fn step_index(&self) -> usize {
+        self.step_index
+    }
+    fn start_sample(&mut self) {
+        if let Some(r) = &mut self.midi_recorder {
+            r.tick();
+        }
         // Clock noise
         self.noise_index = self.noise_index.wrapping_add(1);
         // Generate noise value
-        let noise_val = Random::from_seed(Seed::unsafe_new(
+        self.noise_value = Random::from_seed(Seed::unsafe_new(
             (self.noise_index / self.noise_frequency.max(1)) as u64,
         ))
         .i32()
             & 0xFFFF;
-
-        let voices: [[i32; 2]; 8] = core::array::from_fn(|i| {
-            self.voices[i].generate_sample(self.sample_dir, &mut prev_pitch, &ram, noise_val)
-        });
-
-        let voice_out: [i16; 2] = core::array::from_fn(|side| {
-            (voices.iter().map(|arr| arr[side]).sum::<i32>() / self.voices.len() as i32) as i16
+    }
+    fn apply_keys(&mut self) {
+        let (key_on, key_off) = (self.key_on, self.key_off);
+        self.voices.iter_mut().enumerate().for_each(|(i, v)| {
+            if bit(key_on, i) {
+                v.key_on();
+            }
+            if bit(key_off, i) {
+                v.key_off();
+            }
         });
-
+        self.key_on = 0;
+        self.key_off = 0;
+    }
+    /// Run whichever stage voice `i` has on the current step
+    fn run_voice_stage(&mut self, i: usize, ram: &[u8]) {
+        let stage_step =
+            (self.step_index + STEPS_PER_SAMPLE - VOICE_STAGE_SPACING * i) % STEPS_PER_SAMPLE;
+        match stage_step {
+            DIRECTORY_STEP => self.voices[i].read_directory(self.sample_dir),
+            INTERPOLATE_STEP => {
+                self.voices[i].interpolate(self.noise_value);
+                if self.scope_len > 0 {
+                    let scope = &mut self.voice_scopes[i];
+                    scope.push_back(self.voices[i].output);
+                    while scope.len() > self.scope_len {
+                        scope.pop_front();
+                    }
+                }
+            }
+            ADVANCE_STEP => {
+                let prev_sample = match i {
+                    0 => 0,
+                    _ => self.voices[i - 1].interpolated,
+                };
+                self.voices[i].advance(prev_sample, ram);
+                self.output_voice(i, LEFT);
+            }
+            RIGHT_OUTPUT_STEP => {
+                self.output_voice(i, RIGHT);
+                if self.capture_voices {
+                    let v = &self.voices[i];
+                    self.voice_sample_queues[i].push_back(
+                        (v.volume_output(LEFT) + v.volume_output(RIGHT)) as f32
+                            / 2.0
+                            / 0x7FFF as f32,
+                    )
+                }
+            }
+            OUTX_STEP => self.voices[i].latch_outx(),
+            ENVX_STEP => self.voices[i].latch_envx(),
+            _ => {}
+        }
+    }
+    fn output_voice(&mut self, i: usize, side: usize) {
+        // Silence any muted voices, after they have been clocked so their state is unaffected
+        self.voice_outputs[i][side] = if self.is_voice_audible(i) {
+            self.voices[i].volume_output(side)
+        } else {
+            0
+        };
+    }
+    /// Address in RAM of one side of the current echo sample
+    fn echo_sample_addr(&self, side: usize, ram: &[u8]) -> usize {
+        (self.echo_addr + self.echo_index + 2 * side) % ram.len()
+    }
+    fn read_echo(&mut self, side: usize, ram: &[u8]) {
+        if side == LEFT {
+            // Go to next cache value
+            self.fir_index = (self.fir_index + 1) % self.fir_cache.len();
+        }
+        if self.echo_enabled {
+            let index = self.echo_sample_addr(side, ram);
+            self.fir_cache[self.fir_index][side] =
+                i16::from_le_bytes([ram[index], ram[(index + 1) % ram.len()]]);
+        }
+    }
+    fn filter_echo(&mut self) {
+        let voices = self.voice_outputs;
         // Do for left/right
-        let echo_out: [i16; 2] = core::array::from_fn(|side| {
+        self.echo_write = core::array::from_fn(|side| {
             if self.echo_enabled {
-                // Compute value of FIR taps
+                // Compute value of FIR taps, with the newest sample last
                 let fir_val: f32 = (0..8)
                     .map(|j| {
                         (self.fir_coeffs[j] as f32 / 128.0)
-                            * self.fir_cache[(self.fir_index + j) % self.fir_cache.len()][side]
+                            * self.fir_cache[(self.fir_index + 1 + j) % self.fir_cache.len()][side]
                                 as f32
                     })
                     .sum::<f32>();
@@ -205,40 +415,38 @@ impl Dsp {
                     .map(|(_, v)| v[side])
                     .sum::<i32>();
                 // Multiply FIR tap by feedback value before adding voices
-                let output = ((fir_val * self.echo_feedback as f32 / 128.0).floor() as i32
-                    + echo_voices) as i16;
-
-                // Write value to memory
-                if self.echo_size == 0 {
-                    self.fir_cache[self.fir_index][side] = output;
-                } else {
-                    let index = self.echo_addr + self.echo_index;
-                    // Read value from RAM to FIR cache
-                    let ram_val = i16::from_le_bytes([ram[index], ram[(index + 1) % ram.len()]]);
-                    self.fir_cache[self.fir_index][side] = ram_val;
-                    // Write current output to RAM
-                    let to_write = output as i16;
-                    to_write
-                        .to_le_bytes()
-                        .iter()
-                        .enumerate()
-                        .for_each(|(i, v)| {
-                            ram[(index + i) % ram.len()] = *v;
-                        });
-                    // Increment by 2 since we added 2 bytes
-                    self.echo_index = (self.echo_index + 2) % self.echo_size;
-                }
-                output / 128
+                ((fir_val * self.echo_feedback as f32 / 128.0).floor() as i32 + echo_voices) as i16
             } else {
                 0
             }
         });
-        // Go to next cache value
-        self.fir_index = (self.fir_index + 1) % self.fir_cache.len();
-
+        self.echo_out = self.echo_write.map(|v| v / 128);
+    }
+    fn write_echo(&mut self, side: usize, ram: &mut [u8]) {
+        if self.echo_enabled {
+            let index = self.echo_sample_addr(side, ram);
+            self.echo_write[side]
+                .to_le_bytes()
+                .iter()
+                .enumerate()
+                .for_each(|(i, v)| {
+                    ram[(index + i) % ram.len()] = *v;
+                });
+        }
+        if side == RIGHT {
+            // An echo size of 0 still uses a buffer of one sample
+            self.echo_index = (self.echo_index + 4) % self.echo_size.max(4);
+        }
+    }
+    fn output_sample(&mut self) {
+        let voice_out: [i16; 2] = core::array::from_fn(|side| {
+            (self.voice_outputs.iter().map(|arr| arr[side]).sum::<i32>() / self.voices.len() as i32)
+                as i16
+        });
+        let echo_out = self.echo_out;
         // Check if muted
-        if self.mute {
-            self.sample_queue.push_back(0.0);
+        let s = if self.mute {
+            0.0
         } else {
             let final_out: [f32; 2] = core::array::from_fn(|side| {
                 (echo_out[side] as f32 * self.echo_volume[side] as f32 / 128.0)
@@ -248,10 +456,16 @@ impl Dsp {
             let s = (final_out[0] + final_out[1]) / 2.0 / 0x3FFF as f32;
             if s > 1.0 || s < -1.0 {
                 error!("Invalid audio sample generated: {}", s);
-                self.sample_queue.push_back(0.0);
+                0.0
             } else {
-                self.sample_queue.push_back(s);
+                s
             }
-        }
+        };
+        let s = if self.output_filter_enabled {
+            self.output_filter.apply(s)
+        } else {
+            s
+        };
+        self.sample_queue.push_back(s);
     }
 }
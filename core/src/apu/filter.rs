@@ -0,0 +1,30 @@
+/// Coefficient of the previous sample in the low-pass stage
+const LOW_PASS_PREV: f32 = 0.75;
+/// How much of the high-pass filter's output is kept each sample.
+/// Removes DC offset and some of the lowest bass, as the console's output capacitors do.
+const HIGH_PASS_DECAY: f32 = 1.0 - 1.0 / 256.0;
+
+/// Model of the analog filter the console passes the DSP's output through
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputFilter {
+    /// Previous input sample
+    prev_input: f32,
+    /// Previous output of the low-pass stage
+    prev_low_pass: f32,
+    /// Previous output of the high-pass stage
+    prev_output: f32,
+}
+
+impl OutputFilter {
+    /// Filter a single sample
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        // Low-pass, which softens the high end
+        let low_pass = (1.0 - LOW_PASS_PREV) * sample + LOW_PASS_PREV * self.prev_input;
+        self.prev_input = sample;
+        // High-pass, which removes any DC offset
+        let output = low_pass - self.prev_low_pass + HIGH_PASS_DECAY * self.prev_output;
+        self.prev_low_pass = low_pass;
+        self.prev_output = output;
+        output.clamp(-1.0, 1.0)
+    }
+}
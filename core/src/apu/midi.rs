@@ -0,0 +1,184 @@
+use crate::{
+    apu::{
+        Voice,
+        constants::{LEFT, RIGHT},
+    },
+    utils::bit,
+};
+
+/// Pulses per quarter note in the written file
+const TICKS_PER_QUARTER: u16 = 480;
+/// Tempo of the written file, in microseconds per quarter note (120 BPM)
+const TEMPO: u32 = 500_000;
+/// Rate the DSP generates samples at, which is used as the recorder's clock
+const SAMPLE_RATE: u64 = 32_000;
+/// Note a voice is assumed to play when its pitch is 0x1000, i.e. the sample is played back unchanged
+const BASE_NOTE: f64 = 60.0;
+/// Range of the pitch wheel, in semitones either way. This is the General MIDI default.
+const PITCH_BEND_RANGE: f64 = 2.0;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xB0;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const PITCH_BEND: u8 = 0xE0;
+/// Channel volume controller
+const CHANNEL_VOLUME: u8 = 7;
+
+/// The state of a single MIDI channel
+#[derive(Debug, Clone, Copy, Default)]
+struct Channel {
+    /// The note currently playing, if any
+    note: Option<u8>,
+    program: Option<u8>,
+    pitch_bend: Option<u16>,
+    volume: Option<u8>,
+}
+
+/// Records the notes played by the DSP, and writes them as a Standard MIDI File.
+/// Each voice is written to its own channel.
+#[derive(Debug, Clone, Default)]
+pub struct MidiRecorder {
+    /// Number of samples since recording started
+    samples: u64,
+    /// Events, as the sample they happened on and the raw MIDI message
+    events: Vec<(u64, Vec<u8>)>,
+    channels: [Channel; 8],
+}
+
+/// Convert a voice's pitch into a (fractional) MIDI note number
+pub fn pitch_to_note(pitch: u16) -> f64 {
+    BASE_NOTE + 12.0 * (pitch.max(1) as f64 / 0x1000 as f64).log2()
+}
+
+/// Get the pitch wheel value needed to play `semitones` while `note` is held
+fn pitch_bend(semitones: f64, note: u8) -> u16 {
+    let bend = ((semitones - note as f64) / PITCH_BEND_RANGE).clamp(-1.0, 1.0);
+    (0x2000 as f64 + bend * 0x1FFF as f64).round() as u16
+}
+
+/// Write a number as a MIDI variable length quantity
+fn write_var_len(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+impl MidiRecorder {
+    /// Advance the recorder's clock by one sample
+    pub fn tick(&mut self) {
+        self.samples += 1;
+    }
+    fn push(&mut self, message: Vec<u8>) {
+        self.events.push((self.samples, message));
+    }
+    fn note_off(&mut self, channel: usize) {
+        if let Some(note) = self.channels[channel].note.take() {
+            self.push(vec![NOTE_OFF | channel as u8, note, 0]);
+        }
+    }
+    /// Update the pitch bend of a channel, if it has changed
+    fn update_pitch_bend(&mut self, channel: usize, bend: u16) {
+        if self.channels[channel].pitch_bend != Some(bend) {
+            self.channels[channel].pitch_bend = Some(bend);
+            self.push(vec![
+                PITCH_BEND | channel as u8,
+                (bend & 0x7F) as u8,
+                (bend >> 7) as u8,
+            ]);
+        }
+    }
+    /// Update the volume of a channel, if it has changed
+    fn update_volume(&mut self, channel: usize, voice: &Voice) {
+        let volume = ((voice.volume[LEFT].unsigned_abs() as u16
+            + voice.volume[RIGHT].unsigned_abs() as u16)
+            / 2)
+        .min(0x7F) as u8;
+        if self.channels[channel].volume != Some(volume) {
+            self.channels[channel].volume = Some(volume);
+            self.push(vec![CONTROL_CHANGE | channel as u8, CHANNEL_VOLUME, volume]);
+        }
+    }
+    /// Record the effect of a write to a DSP register.
+    /// Should be called after the write has been applied to `voices`.
+    pub fn on_write(&mut self, address: usize, value: u8, voices: &[Voice; 8]) {
+        match address {
+            // KON
+            0x4C => (0..8).filter(|i| bit(value, *i)).for_each(|i| {
+                let voice = &voices[i];
+                self.note_off(i);
+                // Use the sample as the instrument
+                let program = ((voice.sample_src / 4) & 0x7F) as u8;
+                if self.channels[i].program != Some(program) {
+                    self.channels[i].program = Some(program);
+                    self.push(vec![PROGRAM_CHANGE | i as u8, program]);
+                }
+                self.update_volume(i, voice);
+                let semitones = pitch_to_note(voice.sample_pitch);
+                let note = semitones.round().clamp(0.0, 127.0) as u8;
+                self.update_pitch_bend(i, pitch_bend(semitones, note));
+                self.channels[i].note = Some(note);
+                self.push(vec![NOTE_ON | i as u8, note, 0x7F]);
+            }),
+            // KOFF
+            0x5C => (0..8)
+                .filter(|i| bit(value, *i))
+                .for_each(|i| self.note_off(i)),
+            // Voice registers
+            reg if reg < 0x80 && (reg >> 4) < 8 => {
+                let i = reg >> 4;
+                match reg & 0x0F {
+                    0 | 1 => self.update_volume(i, &voices[i]),
+                    2 | 3 => {
+                        // Only bend notes that are already playing
+                        if let Some(note) = self.channels[i].note {
+                            let bend = pitch_bend(pitch_to_note(voices[i].sample_pitch), note);
+                            self.update_pitch_bend(i, bend);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Write everything recorded so far as a Standard MIDI File, ending any notes still playing
+    pub fn to_smf(&self) -> Vec<u8> {
+        let mut recorder = self.clone();
+        (0..8).for_each(|i| recorder.note_off(i));
+
+        let mut track = Vec::new();
+        // Set the tempo
+        write_var_len(&mut track, 0);
+        track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+        track.extend_from_slice(&TEMPO.to_be_bytes()[1..]);
+        // Convert from samples to ticks
+        let ticks_per_second = TICKS_PER_QUARTER as u64 * 1_000_000 / TEMPO as u64;
+        let mut last_tick = 0;
+        recorder.events.iter().for_each(|(sample, message)| {
+            let tick = sample * ticks_per_second / SAMPLE_RATE;
+            write_var_len(&mut track, (tick - last_tick) as u32);
+            track.extend_from_slice(message);
+            last_tick = tick;
+        });
+        // End of track
+        write_var_len(&mut track, 0);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"MThd");
+        data.extend_from_slice(&6u32.to_be_bytes());
+        // Format 0, with a single track
+        data.extend_from_slice(&0u16.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+        data.extend_from_slice(b"MTrk");
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend(track);
+        data
+    }
+}
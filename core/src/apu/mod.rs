@@ -1,8 +1,17 @@
 mod apu;
+mod breakpoints;
+pub mod brr;
 mod constants;
 mod dsp;
+mod filter;
+mod midi;
+mod spc_file;
 mod voice;
 
 pub use apu::*;
-pub use dsp::Dsp;
-pub use voice::Voice;
+pub use breakpoints::{ApuBreak, ApuBreakpoints};
+pub use dsp::{Dsp, STEPS_PER_SAMPLE};
+pub use filter::OutputFilter;
+pub use midi::{MidiRecorder, pitch_to_note};
+pub use spc_file::*;
+pub use voice::{AdsrStage, GainMode, State, Voice};
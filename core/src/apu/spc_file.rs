@@ -0,0 +1,251 @@
+use std::fmt::Display;
+
+use spc700::{Processor as Spc700Processor, ProgramStatusWord};
+
+use crate::apu::{APU_RAM_SIZE, Apu};
+
+/// Magic string at the start of every SPC file
+pub const SPC_MAGIC: &[u8; 27] = b"SNES-SPC700 Sound File Data";
+/// Full header written when saving an SPC file
+const SPC_HEADER: &[u8; 33] = b"SNES-SPC700 Sound File Data v0.30";
+/// Size of an SPC file, not including any extended ID666 data appended to the end
+pub const SPC_FILE_SIZE: usize = 0x10200;
+/// Number of DSP registers stored in an SPC file
+pub const DSP_REGISTERS_SIZE: usize = 0x80;
+/// Size of the RAM hidden behind the IPL ROM
+pub const EXTRA_RAM_SIZE: usize = 0x40;
+
+const TAG_OFFSET: usize = 0x2E;
+const RAM_OFFSET: usize = 0x100;
+const DSP_OFFSET: usize = 0x10100;
+const EXTRA_RAM_OFFSET: usize = 0x101C0;
+/// Value of byte 0x23 when the file contains an ID666 tag
+const HAS_TAG: u8 = 26;
+/// Value of byte 0x23 when the file does not contain an ID666 tag
+const NO_TAG: u8 = 27;
+/// Minor version of the SPC format
+const VERSION_MINOR: u8 = 30;
+
+#[derive(Debug)]
+pub enum SpcError {
+    /// The file is smaller than a complete SPC dump
+    TooSmall(usize),
+    /// The file does not start with the SPC magic string
+    InvalidHeader,
+}
+
+impl Display for SpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpcError::TooSmall(len) => write!(
+                f,
+                "File is {} bytes, but an SPC file is at least {} bytes",
+                len, SPC_FILE_SIZE
+            ),
+            SpcError::InvalidHeader => write!(f, "File is missing the SPC header"),
+        }
+    }
+}
+
+impl std::error::Error for SpcError {}
+
+/// Metadata stored in the header of an SPC file
+#[derive(Debug, Clone, Default)]
+pub struct Id666Tag {
+    pub song_title: String,
+    pub game_title: String,
+    /// Name of the person who dumped the file
+    pub dumper: String,
+    pub comments: String,
+    /// Date the file was dumped, as MM/DD/YYYY
+    pub date: String,
+    /// Number of seconds to play the song before fading out
+    pub length_seconds: u32,
+    /// Length of the fade out, in milliseconds
+    pub fade_ms: u32,
+    pub artist: String,
+    /// Bitmask of the voices that should be muted by default
+    pub channel_disables: u8,
+    /// Emulator used to create the dump
+    pub emulator: u8,
+}
+
+/// Read a NUL terminated string
+fn read_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| *b as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Write a string into a fixed size field, truncating it if it is too long
+fn write_string(bytes: &mut [u8], value: &str) {
+    bytes.fill(0);
+    value
+        .chars()
+        .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+        .take(bytes.len())
+        .enumerate()
+        .for_each(|(i, b)| bytes[i] = b);
+}
+
+/// Read a little endian number made up of any number of bytes
+fn read_le(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0u32, |acc, b| (acc << 8) | *b as u32)
+}
+
+impl Id666Tag {
+    /// Parse the tag from the 0xD2 bytes starting at offset 0x2E of the SPC file
+    pub fn from_bytes(tag: &[u8]) -> Id666Tag {
+        // The tag comes in a text and a binary flavour, which differ in where the
+        // date, length and artist are stored.
+        // In the text format, the date, song length and fade length are all ASCII
+        let is_text = tag[0x70..0x83]
+            .iter()
+            .all(|b| b.is_ascii_digit() || *b == b'/' || *b == 0);
+        let mut t = Id666Tag {
+            song_title: read_string(&tag[0x00..0x20]),
+            game_title: read_string(&tag[0x20..0x40]),
+            dumper: read_string(&tag[0x40..0x50]),
+            comments: read_string(&tag[0x50..0x70]),
+            ..Default::default()
+        };
+        if is_text {
+            t.date = read_string(&tag[0x70..0x7B]);
+            t.length_seconds = read_string(&tag[0x7B..0x7E]).parse().unwrap_or(0);
+            t.fade_ms = read_string(&tag[0x7E..0x83]).parse().unwrap_or(0);
+            t.artist = read_string(&tag[0x83..0xA3]);
+            t.channel_disables = tag[0xA3];
+            t.emulator = tag[0xA4];
+        } else {
+            let (day, month, year) = (tag[0x70], tag[0x71], read_le(&tag[0x72..0x74]));
+            if year != 0 {
+                t.date = format!("{:02}/{:02}/{:04}", month, day, year);
+            }
+            t.length_seconds = read_le(&tag[0x7B..0x7E]);
+            t.fade_ms = read_le(&tag[0x7E..0x82]);
+            t.artist = read_string(&tag[0x82..0xA2]);
+            t.channel_disables = tag[0xA2];
+            t.emulator = tag[0xA3];
+        }
+        t
+    }
+    /// Write the tag in the text format, into the 0xD2 bytes starting at offset 0x2E of the SPC file
+    pub fn write_bytes(&self, tag: &mut [u8]) {
+        write_string(&mut tag[0x00..0x20], &self.song_title);
+        write_string(&mut tag[0x20..0x40], &self.game_title);
+        write_string(&mut tag[0x40..0x50], &self.dumper);
+        write_string(&mut tag[0x50..0x70], &self.comments);
+        write_string(&mut tag[0x70..0x7B], &self.date);
+        write_string(
+            &mut tag[0x7B..0x7E],
+            &self.length_seconds.min(999).to_string(),
+        );
+        write_string(&mut tag[0x7E..0x83], &self.fade_ms.min(99999).to_string());
+        write_string(&mut tag[0x83..0xA3], &self.artist);
+        tag[0xA3] = self.channel_disables;
+        tag[0xA4] = self.emulator;
+    }
+}
+
+/// The contents of an SPC file, a snapshot of the entire sound system
+#[derive(Clone)]
+pub struct SpcFile {
+    /// SPC700 registers
+    pub core: Spc700Processor,
+    /// The full 64KB of ARAM, including the IO registers at $F0-$FF
+    pub ram: Vec<u8>,
+    /// The 128 DSP registers
+    pub dsp_registers: [u8; DSP_REGISTERS_SIZE],
+    /// The RAM at $FFC0-$FFFF, used when the IPL ROM is mapped over it
+    pub extra_ram: [u8; EXTRA_RAM_SIZE],
+    pub tag: Option<Id666Tag>,
+}
+
+impl SpcFile {
+    pub fn from_bytes(data: &[u8]) -> Result<SpcFile, SpcError> {
+        if data.len() < SPC_FILE_SIZE {
+            return Err(SpcError::TooSmall(data.len()));
+        }
+        if !data.starts_with(SPC_MAGIC) {
+            return Err(SpcError::InvalidHeader);
+        }
+        let core = Spc700Processor {
+            pc: u16::from_le_bytes([data[0x25], data[0x26]]),
+            a: data[0x27],
+            x: data[0x28],
+            y: data[0x29],
+            psw: ProgramStatusWord::from_byte(data[0x2A]),
+            sp: data[0x2B],
+            halted: None,
+        };
+        let tag = if data[0x23] == HAS_TAG {
+            Some(Id666Tag::from_bytes(&data[TAG_OFFSET..RAM_OFFSET]))
+        } else {
+            None
+        };
+        Ok(SpcFile {
+            core,
+            ram: data[RAM_OFFSET..(RAM_OFFSET + APU_RAM_SIZE)].to_vec(),
+            dsp_registers: core::array::from_fn(|i| data[DSP_OFFSET + i]),
+            extra_ram: core::array::from_fn(|i| data[EXTRA_RAM_OFFSET + i]),
+            tag,
+        })
+    }
+    /// Take a snapshot of the APU's current state
+    pub fn from_apu(apu: &Apu, tag: Option<Id666Tag>) -> SpcFile {
+        let r = &apu.rest;
+        let mut ram = r.ram.to_vec();
+        // The IO registers are not stored in RAM, so copy their current values in
+        ram[0xF0] = r.test;
+        ram[0xF1] = (u8::from(r.expose_ipl_rom) << 7)
+            | r.timers
+                .iter()
+                .enumerate()
+                .map(|(i, t)| u8::from(t.enabled) << i)
+                .sum::<u8>();
+        ram[0xF2] = r.dsp_addr | (u8::from(r.dsp_read_only) << 7);
+        ram[0xF3] = r.dsp.read(r.dsp_addr as usize);
+        ram[0xF4..0xF8].copy_from_slice(&r.cpu_to_apu_reg);
+        r.timers.iter().enumerate().for_each(|(i, t)| {
+            ram[0xFA + i] = t.target;
+            ram[0xFD + i] = t.counter & 0x0F;
+        });
+        SpcFile {
+            core: apu.core,
+            dsp_registers: core::array::from_fn(|i| r.dsp.read(i)),
+            extra_ram: core::array::from_fn(|i| ram[0xFFC0 + i]),
+            ram,
+            tag,
+        }
+    }
+    /// Serialize into the SPC file format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0u8; SPC_FILE_SIZE];
+        data[0..SPC_HEADER.len()].copy_from_slice(SPC_HEADER);
+        data[0x21] = 26;
+        data[0x22] = 26;
+        data[0x23] = if self.tag.is_some() { HAS_TAG } else { NO_TAG };
+        data[0x24] = VERSION_MINOR;
+        data[0x25..0x27].copy_from_slice(&self.core.pc.to_le_bytes());
+        data[0x27] = self.core.a;
+        data[0x28] = self.core.x;
+        data[0x29] = self.core.y;
+        data[0x2A] = self.core.psw.to_byte();
+        data[0x2B] = self.core.sp;
+        if let Some(tag) = &self.tag {
+            tag.write_bytes(&mut data[TAG_OFFSET..RAM_OFFSET]);
+        }
+        data[RAM_OFFSET..(RAM_OFFSET + APU_RAM_SIZE)].copy_from_slice(&self.ram);
+        data[DSP_OFFSET..(DSP_OFFSET + DSP_REGISTERS_SIZE)].copy_from_slice(&self.dsp_registers);
+        data[EXTRA_RAM_OFFSET..(EXTRA_RAM_OFFSET + EXTRA_RAM_SIZE)]
+            .copy_from_slice(&self.extra_ram);
+        data
+    }
+}
@@ -1,5 +1,6 @@
 use std::ops::Shr;
 
+use crate::apu::brr::{BRR_BLOCK_SIZE, decode_block, read_block};
 use crate::apu::constants::{
     ENVELOPE_MAX_VALUE, GAUSS_TABLE, LEFT, PERIOD_OFFSET_TABLE, PERIOD_TABLE, RELEASE_PERIOD_RATE,
     RIGHT,
@@ -142,6 +143,22 @@ pub struct Voice {
     period_counter: usize,
     /// Replace the output of the voice with noise
     pub noise_enabled: bool,
+    /// The last sample generated, after the envelope was applied but before the volume
+    #[serde(default)]
+    pub output: i16,
+    /// The last interpolated sample, before the envelope was applied
+    /// Used to modulate the pitch of the next voice
+    #[serde(default)]
+    pub(super) interpolated: i16,
+    /// Address of the voice's entry in the sample directory
+    #[serde(default)]
+    directory_addr: usize,
+    /// The value of ENVX, which is only updated once per sample
+    #[serde(default)]
+    envx: u8,
+    /// The value of OUTX, which is only updated once per sample
+    #[serde(default)]
+    outx: u8,
 }
 
 impl Voice {
@@ -190,7 +207,22 @@ impl Voice {
             2 => self.sample_pitch.to_le_bytes()[0],
             3 => self.sample_pitch.to_le_bytes()[1],
             4 => (self.sample_src / 0x04) as u8,
-            8 => (self.envelope >> 4) as u8,
+            5 => {
+                (u8::from(self.adsr_enabled) << 7)
+                    | ((self.decay_rate.saturating_sub(0x10) / 2) << 4) as u8
+                    | (self.attack_rate.saturating_sub(1) / 2) as u8
+            }
+            6 => {
+                (((self.sustain_level / 0x200).saturating_sub(1) as u8) << 5)
+                    | self.sustain_rate as u8
+            }
+            7 => match self.state {
+                State::Gain(GainMode::Fixed) => (self.envelope / 0x10) as u8 & 0x7F,
+                State::Gain(mode) => (u8::from(mode) << 5) | self.gain_rate as u8,
+                _ => 0,
+            },
+            8 => self.envx,
+            9 => self.outx,
             _ => {
                 debug!("Read from {:02X}", addr);
                 0
@@ -267,97 +299,16 @@ impl Voice {
         };
         self.envelope = self.envelope.clamp(0, ENVELOPE_MAX_VALUE);
     }
-    /// Generate a new sample from this voice
-    /// Returned as a [right, left] array
-    pub fn generate_sample(
-        &mut self,
-        sample_dir_addr: usize,
-        prev_pitch: &mut i32,
-        ram: &[u8],
-        noise_value: i32,
-    ) -> [i32; 2] {
-        self.clock();
-        // Add sample pitch to counter
-        let (counter, o) = self.counter.overflowing_add(if self.pitch_mod_enabled {
-            ((self.sample_pitch as i32 * ((*prev_pitch >> 4) + 0x400)) >> 10)
-                .clamp(0, u16::MAX as i32) as u16
-        } else {
-            self.sample_pitch
-        });
-        // If overflowed, we need to load the next block
-        if o {
-            // If enabled and block address is None, we need to load the first address
-            let block_addr = self.block_addr.unwrap_or_else(|| {
-                // Get the directory address
-                let addr = sample_dir_addr + self.sample_src;
-                u16::from_le_bytes([ram[addr], ram[addr + 1]]) as usize
-            });
-            // Read the head and parse into flags
-            let head = ram[block_addr];
-            // High nibble
-            let shift = head >> 4;
-            // Low nibble
-            let filter = (head >> 2) & 0x03;
-            let loop_flag = bit(head, 1);
-            self.end_flag = bit(head, 0);
-            // Read and parse blocks
-            let sample_bytes: [[i16; 2]; 8] =
-                core::array::from_fn(|i| ram[(block_addr + 1 + i) % ram.len()]).map(|v| {
-                    [v >> 4, v & 0xF].map(|v| {
-                        // If negative, flip all the other bits
-                        let v = if v > 0x07 {
-                            0xFFF0 | (v as u16)
-                        } else {
-                            v as u16
-                        };
-                        // shift right by 1 since the sample is out of 15 bits
-                        if shift >= 0xD {
-                            // When shift=13..15, decoding works as if shift=12 and nibble=(nibble SAR 3).
-                            (((v >> 3) << 12) as i16) >> 1
-                        } else {
-                            ((v << shift) as i16) >> 1
-                        }
-                    })
-                });
-            self.prev_sample_data.copy_from_slice(&self.samples);
-
-            let mut old = self.samples[15] as i32;
-            let mut older = self.samples[14] as i32;
-            let samples: [i16; 16] = core::array::from_fn(|i| {
-                let s = sample_bytes.as_flattened()[i] as i32;
-                let value = match filter {
-                    0 => s,
-                    1 => s + old + ((-old) >> 4),
-                    2 => s + 2 * old + ((-3 * old) >> 5) - older + (older >> 4),
-                    3 => s + 2 * old + ((-13 * old) >> 6) - older + ((older * 3) >> 4),
-                    _ => unreachable!(),
-                } as i16;
-                older = old;
-                old = value as i32;
-                value
-            });
-            self.samples.copy_from_slice(&samples);
-
-            // Get next block address
-            self.block_addr = if self.end_flag {
-                if loop_flag {
-                    // Point to loop address
-                    let addr = sample_dir_addr + self.sample_src + 2;
-                    Some(u16::from_le_bytes([ram[addr], ram[addr + 1]]) as usize)
-                } else {
-                    // Disable channel
-                    self.key_off();
-                    None
-                }
-            } else {
-                Some(block_addr + 9)
-            };
-        }
-        self.counter = counter;
+    /// Latch the address of this voice's entry in the sample directory
+    /// The entry is read when the next BRR block is needed
+    pub fn read_directory(&mut self, sample_dir_addr: usize) {
+        self.directory_addr = sample_dir_addr + self.sample_src;
+    }
+    /// Interpolate the current sample and apply the envelope to it, then clock the envelope
+    pub fn interpolate(&mut self, noise_value: i32) {
         // Select the top 4 bits as the sample index
         let sample_index = (self.counter >> 12) as usize;
         let gauss_index = (self.counter as usize & 0xFF0) >> 4;
-        // Gaussian interpolation goes here
         let sample = (0..4)
             .map(|i| {
                 // i = 0 => newest, i = 3 => oldest
@@ -379,8 +330,7 @@ impl Voice {
             .fold(0i32, |a, b| a.saturating_add(b))
             .shr(1i32)
             .clamp(i16::MIN as i32, i16::MAX as i32);
-
-        *prev_pitch = sample;
+        self.interpolated = sample as i16;
 
         let s = (if self.noise_enabled {
             noise_value
@@ -390,8 +340,64 @@ impl Voice {
             * self.envelope as f32
             / ENVELOPE_MAX_VALUE as f32)
             .floor() as i32;
+        self.output = s.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        self.clock();
+    }
+    /// Add the pitch to the counter, decoding the next BRR block if it overflows
+    /// `prev_sample` is the interpolated sample of the previous voice, used for pitch modulation
+    pub fn advance(&mut self, prev_sample: i16, ram: &[u8]) {
+        let (counter, o) = self.counter.overflowing_add(if self.pitch_mod_enabled {
+            ((self.sample_pitch as i32 * ((prev_sample as i32 >> 4) + 0x400)) >> 10)
+                .clamp(0, u16::MAX as i32) as u16
+        } else {
+            self.sample_pitch
+        });
+        self.counter = counter;
+        // If overflowed, we need to load the next block
+        if o {
+            // If block address is None, we need to load the first address
+            let block_addr = self.block_addr.unwrap_or_else(|| {
+                let addr = self.directory_addr;
+                u16::from_le_bytes([ram[addr], ram[addr + 1]]) as usize
+            });
+            // Decode the block
+            let (header, samples) = decode_block(
+                &read_block(ram, block_addr),
+                self.samples[15],
+                self.samples[14],
+            );
+            let loop_flag = header.loop_flag;
+            self.end_flag = header.end_flag;
+            self.prev_sample_data.copy_from_slice(&self.samples);
+            self.samples.copy_from_slice(&samples);
 
-        core::array::from_fn(|i| ((s * self.volume[i] as i32) >> 7) as i32)
+            // Get next block address
+            self.block_addr = if self.end_flag {
+                if loop_flag {
+                    // Point to loop address
+                    let addr = self.directory_addr + 2;
+                    Some(u16::from_le_bytes([ram[addr], ram[addr + 1]]) as usize)
+                } else {
+                    // Disable channel
+                    self.key_off();
+                    None
+                }
+            } else {
+                Some(block_addr + BRR_BLOCK_SIZE)
+            };
+        }
+    }
+    /// The output of the voice on one side, after the volume is applied
+    pub fn volume_output(&self, side: usize) -> i32 {
+        (self.output as i32 * self.volume[side] as i32) >> 7
+    }
+    /// Update the value read from OUTX
+    pub fn latch_outx(&mut self) {
+        self.outx = (self.output >> 8) as u8;
+    }
+    /// Update the value read from ENVX
+    pub fn latch_envx(&mut self) {
+        self.envx = (self.envelope >> 4) as u8;
     }
     fn get_period_elapsed(&self, rate: usize) -> bool {
         let table_val = PERIOD_TABLE[rate];
@@ -400,6 +406,7 @@ impl Voice {
     pub fn key_on(&mut self) {
         self.state = State::Adsr(AdsrStage::Attack);
         self.block_addr = None;
+        self.counter = 0;
     }
     pub fn key_off(&mut self) {
         self.state = State::Release;
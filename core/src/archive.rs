@@ -0,0 +1,147 @@
+use std::{
+    fmt::Display,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::cartridge::{
+    MIN_MEMORY_MAP_SCORE, MIN_ROM_SIZE, detect_memory_map, strip_copier_header,
+};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+/// File extensions used for ROMs, which are preferred when picking a ROM out of an archive
+pub const ROM_EXTENSIONS: [&str; 5] = ["sfc", "smc", "rom", "swc", "fig"];
+
+/// A compressed file a ROM can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Gzip,
+}
+
+impl ArchiveFormat {
+    /// Detect the format of an archive from its magic number
+    pub fn from_bytes(data: &[u8]) -> Option<ArchiveFormat> {
+        if data.starts_with(ZIP_MAGIC) {
+            Some(ArchiveFormat::Zip)
+        } else if data.starts_with(GZIP_MAGIC) {
+            Some(ArchiveFormat::Gzip)
+        } else {
+            None
+        }
+    }
+    /// File extensions used for each format
+    pub const EXTENSIONS: [&str; 2] = ["zip", "gz"];
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(std::io::Error),
+    Zip(zip::result::ZipError),
+    /// None of the files in the archive look like ROMs
+    NoRom,
+}
+
+impl Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "Unable to decompress archive: {}", e),
+            ArchiveError::Zip(e) => write!(f, "Unable to read zip file: {}", e),
+            ArchiveError::NoRom => write!(f, "Archive doesn't contain a ROM"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+/// A file in an archive that could be a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+    /// Score of the most likely memory map, higher meaning the header is more plausible
+    pub score: i32,
+}
+
+/// Which file in an archive to load
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomChoice {
+    /// A single file is the ROM
+    One(ArchiveEntry),
+    /// Several files could be the ROM, from most to least likely, so the user should pick one
+    Ambiguous(Vec<ArchiveEntry>),
+}
+
+fn has_rom_extension(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ROM_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Decompress every file in an archive, given as its name and contents
+fn extract(
+    data: &[u8],
+    format: ArchiveFormat,
+    name: &str,
+) -> Result<Vec<(String, Vec<u8>)>, ArchiveError> {
+    match format {
+        ArchiveFormat::Gzip => {
+            let mut contents = vec![];
+            GzDecoder::new(data)
+                .read_to_end(&mut contents)
+                .map_err(ArchiveError::Io)?;
+            // The file inside a gzip is named after the gzip
+            let name = name.strip_suffix(".gz").unwrap_or(name).to_string();
+            Ok(vec![(name, contents)])
+        }
+        ArchiveFormat::Zip => {
+            let mut archive = ZipArchive::new(Cursor::new(data)).map_err(ArchiveError::Zip)?;
+            (0..archive.len())
+                .filter_map(|i| {
+                    let mut file = match archive.by_index(i) {
+                        Ok(f) => f,
+                        Err(e) => return Some(Err(ArchiveError::Zip(e))),
+                    };
+                    if file.is_dir() {
+                        return None;
+                    }
+                    let mut contents = vec![];
+                    Some(
+                        file.read_to_end(&mut contents)
+                            .map(|_| (file.name().to_string(), contents))
+                            .map_err(ArchiveError::Io),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// Pick the ROM out of an archive named `name`.
+/// Files with a ROM extension are preferred, and files that don't have a plausible header are ignored.
+pub fn choose_rom(data: &[u8], name: &str) -> Result<RomChoice, ArchiveError> {
+    let format = ArchiveFormat::from_bytes(data).ok_or(ArchiveError::NoRom)?;
+    let mut entries: Vec<ArchiveEntry> = extract(data, format, name)?
+        .into_iter()
+        // Files too small to be a ROM can't be scored
+        .filter(|(_, data)| strip_copier_header(data).len() >= MIN_ROM_SIZE)
+        .filter_map(|(name, data)| {
+            let (_, score) = detect_memory_map(strip_copier_header(&data))?;
+            (score >= MIN_MEMORY_MAP_SCORE).then_some(ArchiveEntry { name, data, score })
+        })
+        .collect();
+    if entries.iter().any(|e| has_rom_extension(&e.name)) {
+        entries.retain(|e| has_rom_extension(&e.name));
+    }
+    entries.sort_by_key(|e| std::cmp::Reverse(e.score));
+    match entries.len() {
+        0 => Err(ArchiveError::NoRom),
+        1 => Ok(RomChoice::One(entries.remove(0))),
+        _ => Ok(RomChoice::Ambiguous(entries)),
+    }
+}
@@ -0,0 +1,76 @@
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Coprocessor, Sa1, SuperFx};
+
+/// A cartridge board with an enhancement chip on it.
+/// The board sits between the CPU and the rest of the console, so it sees every access the CPU makes
+/// and can handle any of them instead of the cartridge or the console's own registers.
+pub trait CoprocessorBoard {
+    /// Read a byte, or return `None` if `address` isn't mapped to the board
+    fn read_byte(&mut self, cartridge: &mut Cartridge, address: usize) -> Option<u8>;
+    /// Write a byte, returning whether `address` is mapped to the board
+    fn write_byte(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) -> bool;
+    /// Advance the board by the number of master clocks the CPU just took
+    fn advance(&mut self, cartridge: &mut Cartridge, master_clocks: u32);
+    /// Whether the board is asserting an IRQ on the CPU
+    fn irq(&self) -> bool;
+    /// Reset the board, along with the rest of the console
+    fn reset(&mut self, cartridge: &mut Cartridge);
+}
+
+/// Every board this emulator supports, so that whichever board the cartridge has can be saved in savestates.
+/// Each board implements [`CoprocessorBoard`], and this forwards to it.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Board {
+    Sa1(Sa1),
+    SuperFx(SuperFx),
+}
+
+impl Board {
+    /// Create the board a cartridge needs, from the chipset in its header
+    /// Returns `None` if the cartridge has no coprocessor, or has one this emulator doesn't support
+    pub fn for_cartridge(cartridge: &Cartridge) -> Option<Board> {
+        match cartridge.header().chipset.coprocessor? {
+            Coprocessor::Sa1 => Some(Board::Sa1(Sa1::default())),
+            Coprocessor::SuperFx => Some(Board::SuperFx(SuperFx::for_cartridge(cartridge))),
+            c => {
+                warn!("Coprocessor {} is not supported", c);
+                None
+            }
+        }
+    }
+}
+
+impl CoprocessorBoard for Board {
+    fn read_byte(&mut self, cartridge: &mut Cartridge, address: usize) -> Option<u8> {
+        match self {
+            Board::Sa1(b) => b.read_byte(cartridge, address),
+            Board::SuperFx(b) => b.read_byte(cartridge, address),
+        }
+    }
+    fn write_byte(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) -> bool {
+        match self {
+            Board::Sa1(b) => b.write_byte(cartridge, address, value),
+            Board::SuperFx(b) => b.write_byte(cartridge, address, value),
+        }
+    }
+    fn advance(&mut self, cartridge: &mut Cartridge, master_clocks: u32) {
+        match self {
+            Board::Sa1(b) => b.advance(cartridge, master_clocks),
+            Board::SuperFx(b) => b.advance(cartridge, master_clocks),
+        }
+    }
+    fn irq(&self) -> bool {
+        match self {
+            Board::Sa1(b) => b.irq(),
+            Board::SuperFx(b) => b.irq(),
+        }
+    }
+    fn reset(&mut self, cartridge: &mut Cartridge) {
+        match self {
+            Board::Sa1(b) => b.reset(cartridge),
+            Board::SuperFx(b) => b.reset(cartridge),
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt::Display;
+
+use crate::{cartridge::MemoryMap, utils::bit};
+
+/// Size of the header, starting at $FFB0 and including the extended header
+pub const HEADER_SIZE: usize = 0x30;
+/// Length of the title, in bytes
+pub const TITLE_LEN: usize = 21;
+/// Developer ID which means the developer is instead given by the maker code in the extended header
+const EXTENDED_DEVELOPER_ID: u8 = 0x33;
+
+// Offsets of each field from the start of the header ($FFB0)
+const MAKER_CODE: usize = 0x00;
+const GAME_CODE: usize = 0x02;
+const EXPANSION_FLASH_SIZE: usize = 0x0C;
+const EXPANSION_RAM_SIZE: usize = 0x0D;
+const SPECIAL_VERSION: usize = 0x0E;
+const CHIPSET_SUBTYPE: usize = 0x0F;
+const TITLE: usize = 0x10;
+const MAP_MODE: usize = 0x25;
+const CHIPSET: usize = 0x26;
+const ROM_SIZE: usize = 0x27;
+const SRAM_SIZE: usize = 0x28;
+const REGION: usize = 0x29;
+const DEVELOPER_ID: usize = 0x2A;
+const VERSION: usize = 0x2B;
+const CHECKSUM_COMPLEMENT: usize = 0x2C;
+const CHECKSUM: usize = 0x2E;
+
+/// Enhancement chip on the cartridge board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coprocessor {
+    /// One of the DSP-n chips
+    Dsp,
+    SuperFx,
+    Obc1,
+    Sa1,
+    SDd1,
+    SRtc,
+    Spc7110,
+    /// ST010 or ST011
+    St010,
+    St018,
+    Cx4,
+    /// A coprocessor type this emulator doesn't know, given as the chipset byte and subtype
+    Other(u8, u8),
+}
+
+impl Display for Coprocessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Coprocessor::*;
+        match self {
+            Dsp => write!(f, "DSP"),
+            SuperFx => write!(f, "Super FX"),
+            Obc1 => write!(f, "OBC1"),
+            Sa1 => write!(f, "SA-1"),
+            SDd1 => write!(f, "S-DD1"),
+            SRtc => write!(f, "S-RTC"),
+            Spc7110 => write!(f, "SPC7110"),
+            St010 => write!(f, "ST010/ST011"),
+            St018 => write!(f, "ST018"),
+            Cx4 => write!(f, "CX4"),
+            Other(chipset, subtype) => write!(f, "Unknown ({:02X}/{:02X})", chipset, subtype),
+        }
+    }
+}
+
+/// The hardware on the cartridge board, from the chipset byte ($FFD6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chipset {
+    /// The raw chipset byte
+    pub value: u8,
+    pub has_ram: bool,
+    pub has_battery: bool,
+    pub coprocessor: Option<Coprocessor>,
+}
+
+impl Chipset {
+    /// Decode the chipset byte, using the subtype in the extended header for custom chips
+    pub fn from_bytes(value: u8, subtype: u8) -> Chipset {
+        use Coprocessor::*;
+        let (has_ram, has_battery, has_coprocessor) = match value & 0x0F {
+            0x00 => (false, false, false),
+            0x01 => (true, false, false),
+            0x02 => (true, true, false),
+            0x03 => (false, false, true),
+            0x04 => (true, false, true),
+            0x05 => (true, true, true),
+            0x06 => (false, true, true),
+            // With a real time clock
+            0x09 => (true, true, true),
+            // Used by the Super FX 2 for RAM with a battery
+            0x0A => (true, true, true),
+            _ => (false, false, false),
+        };
+        let coprocessor = has_coprocessor.then_some(match value >> 4 {
+            0x0 => Dsp,
+            0x1 => SuperFx,
+            0x2 => Obc1,
+            0x3 => Sa1,
+            0x4 => SDd1,
+            0x5 => SRtc,
+            0xF => match subtype {
+                0x00 => Spc7110,
+                0x01 => St010,
+                0x02 => St018,
+                0x10 => Cx4,
+                _ => Other(value, subtype),
+            },
+            _ => Other(value, subtype),
+        });
+        Chipset {
+            value,
+            has_ram,
+            has_battery,
+            coprocessor,
+        }
+    }
+}
+
+impl Display for Chipset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ROM")?;
+        if let Some(c) = self.coprocessor {
+            write!(f, " + {}", c)?;
+        }
+        if self.has_ram {
+            write!(f, " + RAM")?;
+        }
+        if self.has_battery {
+            write!(f, " + Battery")?;
+        }
+        Ok(())
+    }
+}
+
+/// Region the cartridge was released in, from the destination code ($FFD9)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+    Sweden,
+    Finland,
+    Denmark,
+    France,
+    Netherlands,
+    Spain,
+    Germany,
+    Italy,
+    China,
+    Indonesia,
+    Korea,
+    International,
+    Canada,
+    Brazil,
+    Australia,
+    Other(u8),
+}
+
+impl From<u8> for Region {
+    fn from(value: u8) -> Self {
+        use Region::*;
+        match value {
+            0x00 => Japan,
+            0x01 => NorthAmerica,
+            0x02 => Europe,
+            0x03 => Sweden,
+            0x04 => Finland,
+            0x05 => Denmark,
+            0x06 => France,
+            0x07 => Netherlands,
+            0x08 => Spain,
+            0x09 => Germany,
+            0x0A => Italy,
+            0x0B => China,
+            0x0C => Indonesia,
+            0x0D => Korea,
+            0x0E => International,
+            0x0F => Canada,
+            0x10 => Brazil,
+            0x11 => Australia,
+            v => Other(v),
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Region::*;
+        match self {
+            Japan => write!(f, "Japan"),
+            NorthAmerica => write!(f, "North America"),
+            Europe => write!(f, "Europe"),
+            Sweden => write!(f, "Sweden"),
+            Finland => write!(f, "Finland"),
+            Denmark => write!(f, "Denmark"),
+            France => write!(f, "France"),
+            Netherlands => write!(f, "Netherlands"),
+            Spain => write!(f, "Spain"),
+            Germany => write!(f, "Germany"),
+            Italy => write!(f, "Italy"),
+            China => write!(f, "China"),
+            Indonesia => write!(f, "Indonesia"),
+            Korea => write!(f, "Korea"),
+            International => write!(f, "International"),
+            Canada => write!(f, "Canada"),
+            Brazil => write!(f, "Brazil"),
+            Australia => write!(f, "Australia"),
+            Other(v) => write!(f, "Unknown ({:02X})", v),
+        }
+    }
+}
+
+/// The extended header ($FFB0-$FFBF), only present when the developer ID is $33
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedHeader {
+    /// Two character code of the developer
+    pub maker_code: String,
+    /// Four character code of the game
+    pub game_code: String,
+    /// Size of any flash memory, in bytes
+    pub expansion_flash_size: usize,
+    /// Size of any extra RAM on the board, in bytes
+    pub expansion_ram_size: usize,
+    pub special_version: u8,
+    /// Used to tell apart the custom coprocessors
+    pub chipset_subtype: u8,
+}
+
+/// The cartridge header, found at $FFB0-$FFDF in the cartridge's memory map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// The raw map mode byte ($FFD5)
+    pub map_mode: u8,
+    /// Whether the ROM can be accessed at the faster FastROM speed
+    pub fast_rom: bool,
+    pub chipset: Chipset,
+    /// Size of the ROM, in bytes
+    pub rom_size: usize,
+    /// Size of the SRAM, in bytes
+    pub sram_size: usize,
+    pub region: Region,
+    pub developer_id: u8,
+    pub version: u8,
+    pub checksum: u16,
+    pub checksum_complement: u16,
+    pub extended: Option<ExtendedHeader>,
+}
+
+/// Convert a size byte, which is the log2 of the size in kilobytes, into bytes
+/// A value of 0 means there is no memory
+pub(crate) fn size_from_byte(value: u8) -> usize {
+    match value {
+        0 => 0,
+        // Cap the size, since garbage headers would otherwise overflow
+        v => 1024usize << v.min(24),
+    }
+}
+
+/// Decode a string in JIS X 0201, which is ASCII with half-width katakana in $A1-$DF
+pub fn decode_jis_x_0201(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            0x20..0x7F => *b as char,
+            0xA1..0xE0 => char::from_u32(0xFF61 + (*b - 0xA1) as u32).unwrap(),
+            _ => ' ',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Decode a code in the extended header, which should be ASCII
+fn decode_code(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            0x20..0x7F => *b as char,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+impl CartridgeHeader {
+    /// Parse the header from the `HEADER_SIZE` bytes starting at $FFB0
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE]) -> CartridgeHeader {
+        let developer_id = bytes[DEVELOPER_ID];
+        let extended = (developer_id == EXTENDED_DEVELOPER_ID).then(|| ExtendedHeader {
+            maker_code: decode_code(&bytes[MAKER_CODE..MAKER_CODE + 2]),
+            game_code: decode_code(&bytes[GAME_CODE..GAME_CODE + 4]),
+            expansion_flash_size: size_from_byte(bytes[EXPANSION_FLASH_SIZE]),
+            expansion_ram_size: size_from_byte(bytes[EXPANSION_RAM_SIZE]),
+            special_version: bytes[SPECIAL_VERSION],
+            chipset_subtype: bytes[CHIPSET_SUBTYPE],
+        });
+        let subtype = extended.as_ref().map_or(0, |e| e.chipset_subtype);
+        CartridgeHeader {
+            title: decode_jis_x_0201(&bytes[TITLE..TITLE + TITLE_LEN]),
+            map_mode: bytes[MAP_MODE],
+            fast_rom: bit(bytes[MAP_MODE], 4),
+            chipset: Chipset::from_bytes(bytes[CHIPSET], subtype),
+            rom_size: size_from_byte(bytes[ROM_SIZE]),
+            sram_size: size_from_byte(bytes[SRAM_SIZE]),
+            region: Region::from(bytes[REGION]),
+            developer_id,
+            version: bytes[VERSION],
+            checksum: u16::from_le_bytes([bytes[CHECKSUM], bytes[CHECKSUM + 1]]),
+            checksum_complement: u16::from_le_bytes([
+                bytes[CHECKSUM_COMPLEMENT],
+                bytes[CHECKSUM_COMPLEMENT + 1],
+            ]),
+            extended,
+        }
+    }
+    /// The memory map given by the map mode byte, if it is one this emulator supports
+    pub fn memory_map(&self) -> Option<MemoryMap> {
+        match self.map_mode & 0x0F {
+            0x00 => Some(MemoryMap::LoRom),
+            0x01 => Some(MemoryMap::HiRom),
+            0x02 => Some(MemoryMap::ExLoRom),
+            0x05 => Some(MemoryMap::ExHiRom),
+            _ => None,
+        }
+    }
+    /// Whether the checksum and its complement are consistent with each other
+    /// This doesn't check that the checksum matches the ROM's contents
+    pub fn checksum_is_consistent(&self) -> bool {
+        self.checksum ^ self.checksum_complement == 0xFFFF
+    }
+}
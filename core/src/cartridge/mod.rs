@@ -0,0 +1,11 @@
+mod board;
+mod header;
+mod rom;
+mod sa1;
+mod super_fx;
+
+pub use board::*;
+pub use header::*;
+pub use rom::*;
+pub use sa1::*;
+pub use super_fx::*;
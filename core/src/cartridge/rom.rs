@@ -0,0 +1,389 @@
+use std::fmt::Display;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{CartridgeHeader, HEADER_SIZE, Region, TITLE_LEN, header::size_from_byte};
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum MemoryMap {
+    LoRom,
+    HiRom,
+    ExHiRom,
+    /// LoROM extended past 4MB, with the first 4MB in banks $80-$FF and the rest in banks $00-$7D
+    ExLoRom,
+}
+
+impl MemoryMap {
+    /// Every memory map, in the order they are preferred when detecting the memory map of a ROM
+    pub const ALL: [MemoryMap; 4] = [
+        MemoryMap::LoRom,
+        MemoryMap::HiRom,
+        MemoryMap::ExLoRom,
+        MemoryMap::ExHiRom,
+    ];
+    pub fn transform_address(&self, address: usize) -> usize {
+        match self {
+            MemoryMap::LoRom => (address & 0x7FFF) + ((address >> 1) & 0x3F_8000),
+            MemoryMap::HiRom => address & 0x3F_FFFF,
+            MemoryMap::ExHiRom => (((!address) & 0x80_0000) >> 1) | (address & 0x3F_FFFF),
+            MemoryMap::ExLoRom => {
+                (((!address) & 0x80_0000) >> 1) | (address & 0x7FFF) | ((address >> 1) & 0x3F_8000)
+            }
+        }
+    }
+    /// Index of the header ($FFC0) in the ROM
+    pub fn header_index(&self) -> usize {
+        self.transform_address(0x00_FFC0)
+    }
+    pub fn is_sram_address(&self, address: usize) -> bool {
+        self.sram_index(address).is_some()
+    }
+    /// Index of an address in SRAM, before it is mirrored to the size of the SRAM,
+    /// or `None` if the address isn't mapped to SRAM
+    pub fn sram_index(&self, address: usize) -> Option<usize> {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        let is_sram = match self {
+            // Banks $70-$7D and $F0-$FF, $0000-$7FFF
+            MemoryMap::LoRom | MemoryMap::ExLoRom => {
+                ((0x70..0x7E).contains(&bank) || bank >= 0xF0) && offset < 0x8000
+            }
+            // Banks $30-$3F and $B0-$BF, $6000-$7FFF
+            MemoryMap::HiRom => {
+                (0x30..0x40).contains(&(bank & 0x7F)) && (0x6000..0x8000).contains(&offset)
+            }
+            // Banks $80-$BF, $6000-$7FFF
+            MemoryMap::ExHiRom => {
+                (0x80..0xC0).contains(&bank) && (0x6000..0x8000).contains(&offset)
+            }
+        };
+        is_sram.then(|| match self {
+            MemoryMap::LoRom | MemoryMap::ExLoRom => (bank & 0x0F) * 0x8000 + offset,
+            MemoryMap::HiRom | MemoryMap::ExHiRom => (bank & 0x0F) * 0x2000 + (offset - 0x6000),
+        })
+    }
+}
+
+/// Mirror an index into memory that is `size` bytes long, the way the cartridge's address lines do.
+/// Memory that isn't a power of two in size is split into power of two sized chunks,
+/// and each chunk is mirrored to fill the space up to the next power of two,
+/// i.e. a 3MB ROM is a 2MB chunk followed by a 1MB chunk which is mirrored twice
+pub fn mirror_address(address: usize, size: usize) -> usize {
+    if size == 0 {
+        return 0;
+    }
+    let (mut address, mut size, mut base) = (address, size, 0);
+    while address >= size {
+        let mask = 1 << address.ilog2();
+        address -= mask;
+        if size > mask {
+            size -= mask;
+            base += mask;
+        }
+    }
+    base + address
+}
+
+/// Smallest ROM that has room for a header, which is a single LoROM bank
+pub const MIN_ROM_SIZE: usize = 0x8000;
+/// Largest SRAM size byte that a cartridge can have, which is 1MB
+const MAX_SRAM_SIZE_BYTE: u8 = 0x0A;
+/// Lowest score a memory map can have and still be used
+/// Scores lower than this mean that none of the headers look like real headers
+pub const MIN_MEMORY_MAP_SCORE: i32 = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The ROM is smaller than `MIN_ROM_SIZE`, after removing any copier header
+    TooSmall(usize),
+    /// None of the places the header could be have a plausible header
+    UnknownMapping,
+    /// The SRAM size byte in the header is too large
+    BadSramSize(u8),
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartridgeError::TooSmall(len) => write!(
+                f,
+                "ROM is {} bytes, but a ROM is at least {} bytes",
+                len, MIN_ROM_SIZE
+            ),
+            CartridgeError::UnknownMapping => {
+                write!(f, "Unable to determine the ROM's memory map")
+            }
+            CartridgeError::BadSramSize(n) => write!(f, "Invalid SRAM size in header ({:02X})", n),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+fn u16_at(arr: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([arr[i], arr[i + 1]])
+}
+
+/// Opcodes that games commonly start with, such as SEI, CLC, REP/SEP and jumps
+const LIKELY_FIRST_OPCODES: [u8; 8] = [0x78, 0x18, 0xC2, 0xE2, 0x5C, 0x4C, 0x9C, 0xA9];
+/// Opcodes that a game is very unlikely to start with, such as BRK, COP, STP and WDM,
+/// along with $FF which is usually unused space
+const UNLIKELY_FIRST_OPCODES: [u8; 5] = [0x00, 0x02, 0xDB, 0x42, 0xFF];
+
+/// Remove the 512 byte header some copiers add to the start of the ROM, if there is one
+pub fn strip_copier_header(data: &[u8]) -> &[u8] {
+    if data.len() % 1024 == 512 {
+        &data[512..]
+    } else {
+        data
+    }
+}
+
+/// Score how likely it is that `data` uses `memory_map`, by checking whether the header found where that
+/// map would put it is plausible.
+/// Returns `None` if the ROM is too small to have a header there.
+fn score_memory_map(data: &[u8], memory_map: MemoryMap, checksum: u16) -> Option<i32> {
+    let index = memory_map.header_index();
+    if index + 0x40 > data.len() {
+        return None;
+    }
+    let header = CartridgeHeader::from_bytes(
+        data[index - 0x10..index - 0x10 + HEADER_SIZE]
+            .try_into()
+            .unwrap(),
+    );
+    let mut score = 0;
+    // Checksum
+    if header.checksum_is_consistent() {
+        score += if header.checksum == checksum { 8 } else { 4 };
+    }
+    // Map mode
+    if header.memory_map() == Some(memory_map) {
+        score += 4;
+    }
+    if header.map_mode & 0xE0 == 0x20 {
+        score += 2;
+    }
+    // The ROM size should be the smallest power of two that fits the ROM
+    if header.rom_size >= data.len() && header.rom_size < 2 * data.len() {
+        score += 2;
+    } else if (0x2_0000..=0x80_0000).contains(&header.rom_size) {
+        score += 1;
+    }
+    if header.sram_size <= 0x4_0000 {
+        score += 1;
+    }
+    if !matches!(header.region, Region::Other(_)) {
+        score += 1;
+    }
+    if data[index..index + TITLE_LEN]
+        .iter()
+        .all(|b| (0x20..0x7F).contains(b) || (0xA1..0xE0).contains(b))
+    {
+        score += 1;
+    }
+    // The reset vector must point to ROM, and should point to a plausible first instruction
+    let reset = u16_at(data, index + 0x3C) as usize;
+    if reset < 0x8000 {
+        score -= 8;
+    } else {
+        let opcode = data[mirror_address(memory_map.transform_address(reset), data.len())];
+        if LIKELY_FIRST_OPCODES.contains(&opcode) {
+            score += 4;
+        } else if UNLIKELY_FIRST_OPCODES.contains(&opcode) {
+            score -= 4;
+        }
+    }
+    Some(score)
+}
+
+/// Detect the memory map of a ROM, which should not have a copier header, by scoring the header each map would have.
+/// Returns the map and its score, or `None` if the ROM is too small to have a header.
+pub fn detect_memory_map(data: &[u8]) -> Option<(MemoryMap, i32)> {
+    // Too small to hold a header
+    if data.len() < MIN_ROM_SIZE {
+        return None;
+    }
+    let checksum = compute_checksum(data);
+    debug!("Checksum: {:04X}", checksum);
+    MemoryMap::ALL
+        .into_iter()
+        .filter_map(|m| score_memory_map(data, m, checksum).map(|s| (m, s)))
+        .inspect(|(m, s)| debug!("{:?} has score {}", m, s))
+        // Prefer the earlier maps when tied
+        .fold(None, |best, (m, s)| match best {
+            Some((_, b)) if b >= s => best,
+            _ => Some((m, s)),
+        })
+}
+
+fn compute_checksum(data: &[u8]) -> u16 {
+    if data.len().is_power_of_two() {
+        // Cartridge is already a power of 2
+        debug!("Cartridge size is power of 2");
+        data.iter().fold(0u16, |acc, b| acc.wrapping_add(*b as u16))
+    } else {
+        debug!("Cartridge is not a power of 2");
+        // Get largest power of two that is smaller than the data length
+        let lesser = data.len().ilog2();
+        let index = 2usize.pow(lesser);
+        // Get the smallest power of two that is largest than the remainder length
+        let remainder_len = data.len() - index;
+        let greater = remainder_len.ilog2()
+            + if remainder_len.is_power_of_two() {
+                0
+            } else {
+                1
+            };
+        // Compute checksums
+        let larger_checksum = &data[0..index]
+            .iter()
+            .fold(0u16, |acc, b| acc.wrapping_add(*b as u16));
+        let smaller_checksum = &data[index..data.len()]
+            .iter()
+            .fold(0u16, |acc, b| acc.wrapping_add(*b as u16));
+        // Combine as if repeating the remainder checksum to get an array that is 2 * index long
+        larger_checksum
+            .wrapping_add(smaller_checksum.wrapping_mul((index / 2usize.pow(greater)) as u16))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Cartridge {
+    memory_map: MemoryMap,
+    pub data: Vec<u8>,
+    sram: Vec<u8>,
+    /// Whether SRAM has been written to since `take_sram_written` was last called
+    #[serde(skip)]
+    sram_written: bool,
+}
+
+impl Cartridge {
+    pub fn from_data(data: &[u8]) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_data_with_map(data, None)
+    }
+    /// Load a cartridge, using `memory_map` if it is given instead of detecting the memory map
+    pub fn from_data_with_map(
+        data: &[u8],
+        memory_map: Option<MemoryMap>,
+    ) -> Result<Cartridge, CartridgeError> {
+        let data = strip_copier_header(data).to_vec();
+        if data.len() < MIN_ROM_SIZE {
+            return Err(CartridgeError::TooSmall(data.len()));
+        }
+        let memory_map = match memory_map {
+            Some(m) => {
+                debug!("Memory map forced to {:?}", m);
+                m
+            }
+            None => match detect_memory_map(&data) {
+                Some((m, score)) if score >= MIN_MEMORY_MAP_SCORE => {
+                    debug!("Memory map detected as {:?}", m);
+                    m
+                }
+                _ => return Err(CartridgeError::UnknownMapping),
+            },
+        };
+        let sram_len = {
+            let n = data[mirror_address(memory_map.transform_address(0x00FFD8), data.len())];
+            if n > MAX_SRAM_SIZE_BYTE {
+                return Err(CartridgeError::BadSramSize(n));
+            }
+            size_from_byte(n)
+        };
+        debug!("SRAM len: {}", sram_len);
+        let cartridge = Cartridge {
+            data,
+            sram: vec![0; sram_len],
+            memory_map,
+            sram_written: false,
+        };
+        debug!("Region is {}", cartridge.header().region);
+        Ok(cartridge)
+    }
+    pub fn transform_address(&self, address: usize) -> usize {
+        self.memory_map.transform_address(address)
+    }
+    /// Whether an address is mapped to SRAM, which is never the case if the cartridge has no SRAM
+    pub fn is_sram_address(&self, address: usize) -> bool {
+        self.sram_index(address).is_some()
+    }
+    /// Index of an address in SRAM, if it is mapped to SRAM and the cartridge has SRAM
+    fn sram_index(&self, address: usize) -> Option<usize> {
+        if self.sram.is_empty() {
+            return None;
+        }
+        self.memory_map
+            .sram_index(address)
+            .map(|i| mirror_address(i, self.sram.len()))
+    }
+    pub fn write_byte(&mut self, address: usize, value: u8) {
+        if let Some(i) = self.sram_index(address) {
+            self.sram[i] = value;
+            self.sram_written = true;
+        }
+    }
+    pub fn read_byte(&self, address: usize) -> u8 {
+        match self.sram_index(address) {
+            Some(i) => self.sram[i],
+            None => self.data[mirror_address(self.transform_address(address), self.data.len())],
+        }
+    }
+    /// Parse the cartridge header
+    pub fn header(&self) -> CartridgeHeader {
+        CartridgeHeader::from_bytes(&core::array::from_fn(|i| self.read_byte(0xFFB0 + i)))
+    }
+    pub fn title(&self) -> String {
+        self.header().title
+    }
+    pub fn memory_map(&self) -> MemoryMap {
+        self.memory_map
+    }
+    pub fn sram(&self) -> &[u8] {
+        &self.sram
+    }
+    /// Replace the contents of SRAM, i.e. with a .srm file
+    /// If `sram` is a different size than the cartridge's SRAM, it is truncated or padded with zeros
+    pub fn load_sram(&mut self, sram: &[u8]) {
+        if sram.len() != self.sram.len() {
+            warn!(
+                "SRAM is {} bytes, but the cartridge has {} bytes of SRAM",
+                sram.len(),
+                self.sram.len()
+            );
+        }
+        let len = sram.len().min(self.sram.len());
+        self.sram.fill(0);
+        self.sram[..len].copy_from_slice(&sram[..len]);
+        self.sram_written = false;
+    }
+    /// Whether SRAM has been written to since this was last called
+    pub fn take_sram_written(&mut self) -> bool {
+        std::mem::take(&mut self.sram_written)
+    }
+    /// Read a byte in the ROM, for boards that map the ROM themselves
+    pub(crate) fn read_rom(&self, index: usize) -> u8 {
+        self.data[mirror_address(index, self.data.len())]
+    }
+    /// Read a byte in SRAM, for boards that map SRAM themselves
+    pub(crate) fn read_sram(&self, index: usize) -> u8 {
+        if self.sram.is_empty() {
+            return 0;
+        }
+        self.sram[mirror_address(index, self.sram.len())]
+    }
+    /// Write a byte in SRAM, for boards that map SRAM themselves
+    pub(crate) fn write_sram(&mut self, index: usize, value: u8) {
+        if self.sram.is_empty() {
+            return;
+        }
+        let i = mirror_address(index, self.sram.len());
+        self.sram[i] = value;
+        self.sram_written = true;
+    }
+    /// Compute the checksum of the ROM, to compare with the one in the header
+    pub fn checksum(&self) -> u16 {
+        compute_checksum(&self.data)
+    }
+}
@@ -0,0 +1,820 @@
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_big_array::Array;
+use wdc65816::{HasAddressBus, Processor};
+
+use crate::{
+    cartridge::{Cartridge, CoprocessorBoard},
+    ppu::{MASTER_CYCLES_PER_DOT, SCANLINES},
+    utils::bit,
+};
+
+/// Size of the SA-1's internal RAM
+pub const IRAM_SIZE: usize = 0x800;
+/// Largest BW-RAM the SA-1 can address, which is 256KB
+const BWRAM_MASK: usize = 0x3_FFFF;
+/// The SA-1 runs at 10.74MHz, so each of its cycles is 2 master clocks
+const MASTER_CLOCKS_PER_CYCLE: i32 = 2;
+/// BW-RAM is slower, and takes 2 SA-1 cycles to access
+const BWRAM_MASTER_CLOCKS: i32 = 4;
+/// Length of a scanline for the H/V timer, in master clocks
+const TIMER_LINE_LENGTH: u32 = 1364;
+/// The arithmetic result is 40 bits
+const MR_MASK: u64 = (1 << 40) - 1;
+/// Value returned when reading the version register
+const VERSION: u8 = 0x23;
+
+// Flags in CFR, SFR, CIE and SIE
+const IRQ_FLAG: u8 = 0x80;
+const TIMER_IRQ_FLAG: u8 = 0x40;
+const DMA_IRQ_FLAG: u8 = 0x20;
+const NMI_FLAG: u8 = 0x10;
+const IRQ_VECTOR_SWITCH: u8 = 0x40;
+const NMI_VECTOR_SWITCH: u8 = 0x10;
+
+/// The registers and memory of the SA-1 that both CPUs can access
+#[derive(Clone, Serialize, Deserialize)]
+struct Sa1Registers {
+    /// Internal RAM, shared by both CPUs
+    iram: Box<Array<u8, IRAM_SIZE>>,
+    /// Whether the SA-1 CPU is held in reset
+    sa1_reset: bool,
+    /// Whether the SA-1 CPU was just released from reset, and should fetch its reset vector
+    sa1_restart: bool,
+    /// Whether the SA-1 CPU is paused
+    sa1_wait: bool,
+    /// Interrupt flags for the SA-1 CPU, and the message from the SNES CPU (CFR)
+    sa1_flags: u8,
+    /// Which interrupts are enabled on the SA-1 CPU (CIE)
+    sa1_interrupt_enable: u8,
+    /// Whether the SNES CPU has sent an NMI the SA-1 CPU hasn't taken yet
+    sa1_nmi_pending: bool,
+    /// Interrupt flags for the SNES CPU, which vectors are replaced, and the message from the SA-1 CPU (SFR)
+    snes_flags: u8,
+    /// Which interrupts are enabled on the SNES CPU (SIE)
+    snes_interrupt_enable: u8,
+    /// Vectors used by the SA-1 CPU
+    reset_vector: u16,
+    nmi_vector: u16,
+    irq_vector: u16,
+    /// Vectors that replace the SNES CPU's own
+    snes_nmi_vector: u16,
+    snes_irq_vector: u16,
+    /// Timer control (TMC)
+    timer_control: u8,
+    /// Dot and line the timer triggers an IRQ at
+    h_timer: u16,
+    v_timer: u16,
+    /// Current position of the timer, with the H counter in master clocks
+    h_counter: u32,
+    v_counter: u32,
+    /// Which 1MB ROM block is mapped to each of the four ROM areas (CXB, DXB, EXB and FXB)
+    rom_blocks: [u8; 4],
+    /// 8KB BW-RAM block mapped to $6000-$7FFF for the SNES CPU (BMAPS) and the SA-1 CPU (BMAP)
+    snes_bwram_block: u8,
+    sa1_bwram_block: u8,
+    /// Whether each CPU can write to the protected area of BW-RAM (SBWE and CBWE)
+    snes_bwram_write: bool,
+    sa1_bwram_write: bool,
+    /// Size of the protected area of BW-RAM (BWPA)
+    bwram_protect: u8,
+    /// Which 256 byte blocks of I-RAM each CPU can write to (SIWP and CIWP)
+    snes_iram_write: u8,
+    sa1_iram_write: u8,
+    /// DMA control (DCNT)
+    dma_control: u8,
+    /// Character conversion DMA parameters (CDMA)
+    char_conversion: u8,
+    dma_src: u32,
+    dma_dest: u32,
+    dma_len: u16,
+    /// Whether the SNES CPU is reading characters converted from a bitmap in BW-RAM
+    char_conversion_active: bool,
+    /// Whether the bitmap view of BW-RAM uses 2 bits per pixel instead of 4 (BBF)
+    bitmap_2bpp: bool,
+    /// Bitmap register file, which holds two rows of pixels for character conversion
+    brf: [u8; 16],
+    /// Row of the characters being converted through the bitmap register file
+    brf_line: usize,
+    /// Arithmetic control (MCNT)
+    math_control: u8,
+    /// Multiplicand/dividend and multiplier/divisor
+    ma: u16,
+    mb: u16,
+    /// 40 bit arithmetic result
+    mr: u64,
+    /// Whether the cumulative sum overflowed
+    math_overflow: bool,
+    /// Number of bits to advance the variable length data by (VBD)
+    vbit_len: u32,
+    /// Whether reading the variable length data advances it
+    vbit_auto: bool,
+    /// Address and bit of the variable length data
+    vbit_address: u32,
+    vbit: u32,
+}
+
+impl Default for Sa1Registers {
+    fn default() -> Self {
+        Sa1Registers {
+            iram: Box::new(Array([0; IRAM_SIZE])),
+            sa1_reset: true,
+            sa1_restart: false,
+            sa1_wait: false,
+            sa1_flags: 0,
+            sa1_interrupt_enable: 0,
+            sa1_nmi_pending: false,
+            snes_flags: 0,
+            snes_interrupt_enable: 0,
+            reset_vector: 0,
+            nmi_vector: 0,
+            irq_vector: 0,
+            snes_nmi_vector: 0,
+            snes_irq_vector: 0,
+            timer_control: 0,
+            h_timer: 0,
+            v_timer: 0,
+            h_counter: 0,
+            v_counter: 0,
+            rom_blocks: [0, 1, 2, 3],
+            snes_bwram_block: 0,
+            sa1_bwram_block: 0,
+            snes_bwram_write: false,
+            sa1_bwram_write: false,
+            bwram_protect: 0,
+            snes_iram_write: 0,
+            sa1_iram_write: 0,
+            dma_control: 0,
+            char_conversion: 0,
+            dma_src: 0,
+            dma_dest: 0,
+            dma_len: 0,
+            char_conversion_active: false,
+            bitmap_2bpp: false,
+            brf: [0; 16],
+            brf_line: 0,
+            math_control: 0,
+            ma: 0,
+            mb: 0,
+            mr: 0,
+            math_overflow: false,
+            vbit_len: 16,
+            vbit_auto: false,
+            vbit_address: 0,
+            vbit: 0,
+        }
+    }
+}
+
+/// Set the low or high byte of a word, depending on whether `address` is even or odd
+fn set_byte(word: &mut u16, address: usize, value: u8) {
+    let mut bytes = word.to_le_bytes();
+    bytes[address % 2] = value;
+    *word = u16::from_le_bytes(bytes);
+}
+
+/// Set one of the bytes of a 24 bit address, with `i` being 0 for the low byte
+fn set_address_byte(address: &mut u32, i: usize, value: u8) {
+    let mut bytes = address.to_le_bytes();
+    bytes[i] = value;
+    *address = u32::from_le_bytes(bytes) & 0xFF_FFFF;
+}
+
+impl Sa1Registers {
+    /// Index in the ROM of an address, going through the SA-1's memory mapping controller
+    fn rom_index(&self, address: usize) -> Option<usize> {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        let (block, offset) = match bank {
+            // LoROM areas, where $00-$1F, $20-$3F, $80-$9F and $A0-$BF each use one of the four blocks
+            0x00..=0x3F | 0x80..=0xBF if offset >= 0x8000 => {
+                let area = ((bank >> 5) & 0x01) | ((bank >> 6) & 0x02);
+                let register = self.rom_blocks[area];
+                // Unless bit 7 is set, each area is fixed to its own block
+                let block = if bit(register, 7) {
+                    register as usize & 0x07
+                } else {
+                    area
+                };
+                (block, ((bank & 0x1F) << 15) | (offset & 0x7FFF))
+            }
+            // HiROM areas, where $C0-$CF, $D0-$DF, $E0-$EF and $F0-$FF each use one of the four blocks
+            0xC0..=0xFF => (
+                self.rom_blocks[(bank >> 4) & 0x03] as usize & 0x07,
+                ((bank & 0x0F) << 16) | offset,
+            ),
+            _ => return None,
+        };
+        Some(block * 0x10_0000 + offset)
+    }
+    fn read_rom(&self, cartridge: &Cartridge, address: usize) -> u8 {
+        self.rom_index(address).map_or(0, |i| cartridge.read_rom(i))
+    }
+    /// Index in BW-RAM of an address in the $6000-$7FFF window, given the block mapped to it
+    fn bwram_window_index(block: u8, address: usize) -> usize {
+        block as usize * 0x2000 + (address & 0x1FFF)
+    }
+    fn write_bwram(&self, cartridge: &mut Cartridge, index: usize, value: u8, write_enabled: bool) {
+        let index = index & BWRAM_MASK;
+        // Writes to the start of BW-RAM are ignored unless enabled
+        if write_enabled || index >= 0x100 << self.bwram_protect {
+            cartridge.write_sram(index, value);
+        }
+    }
+    /// Read a pixel in the bitmap view of BW-RAM, where each byte is a single 2 or 4 bit pixel
+    fn read_bitmap(&self, cartridge: &Cartridge, index: usize) -> u8 {
+        if self.bitmap_2bpp {
+            (cartridge.read_sram(index >> 2) >> ((index & 0x03) * 2)) & 0x03
+        } else {
+            (cartridge.read_sram(index >> 1) >> ((index & 0x01) * 4)) & 0x0F
+        }
+    }
+    fn write_bitmap(&self, cartridge: &mut Cartridge, index: usize, value: u8) {
+        let (byte_index, shift, mask) = if self.bitmap_2bpp {
+            (index >> 2, (index & 0x03) * 2, 0x03)
+        } else {
+            (index >> 1, (index & 0x01) * 4, 0x0F)
+        };
+        let byte = cartridge.read_sram(byte_index) & !(mask << shift);
+        self.write_bwram(
+            cartridge,
+            byte_index,
+            byte | ((value & mask) << shift),
+            self.sa1_bwram_write,
+        );
+    }
+    /// Write to I-RAM, if the 256 byte block being written to is writable
+    fn write_iram(&mut self, index: usize, value: u8, write_enable: u8) {
+        let index = index % IRAM_SIZE;
+        if bit(write_enable, index >> 8) {
+            self.iram[index] = value;
+        }
+    }
+    /// Bits per pixel of the characters in character conversion DMA
+    fn char_bpp(&self) -> usize {
+        8 >> (self.char_conversion & 0x03).min(2)
+    }
+    /// Byte `offset` of the characters converted from the bitmap at the DMA source,
+    /// which is what the SNES CPU reads from BW-RAM during character conversion DMA
+    fn converted_char_byte(&self, cartridge: &Cartridge, offset: usize) -> u8 {
+        let bpp = self.char_bpp();
+        let chars_per_line = 1 << ((self.char_conversion >> 2) & 0x07).min(5);
+        let (char_index, byte) = (offset / (8 * bpp), offset % (8 * bpp));
+        // Each pair of bytes is two bitplanes of a row, and each 16 bytes is the next two bitplanes
+        let row = (byte & 0x0F) >> 1;
+        let plane = (byte >> 4) * 2 + (byte & 0x01);
+        let (x, y) = (
+            (char_index % chars_per_line) * 8,
+            (char_index / chars_per_line) * 8 + row,
+        );
+        let src = self.dma_src as usize & BWRAM_MASK;
+        (0..8).fold(0, |acc, i| {
+            let pixel = y * chars_per_line * 8 + x + i;
+            let value = cartridge.read_sram(src + pixel * bpp / 8) >> ((pixel * bpp) % 8);
+            acc | (((value >> plane) & 0x01) << (7 - i))
+        })
+    }
+    /// Read from BW-RAM as the SNES CPU, which sees converted characters during character conversion DMA
+    fn snes_read_bwram(&self, cartridge: &Cartridge, index: usize) -> u8 {
+        let index = index & BWRAM_MASK;
+        let src = self.dma_src as usize & BWRAM_MASK;
+        if self.char_conversion_active && index >= src {
+            self.converted_char_byte(cartridge, index - src)
+        } else {
+            cartridge.read_sram(index)
+        }
+    }
+    /// Convert the row of pixels just written to the bitmap register file into characters in I-RAM
+    fn convert_brf_row(&mut self) {
+        let cb = (self.char_conversion & 0x03).min(2) as usize;
+        let bpp = self.char_bpp();
+        let start = (self.brf_line & 0x01) * 8;
+        let brf: [u8; 8] = self.brf[start..start + 8].try_into().unwrap();
+        let mut index = self.dma_dest as usize & (IRAM_SIZE - 1);
+        index &= !((1 << (7 - cb)) - 1);
+        index += (self.brf_line & 0x08) * bpp;
+        index += (self.brf_line & 0x07) * 2;
+        (0..bpp).for_each(|byte| {
+            let value = (0..8).fold(0, |acc, i| acc | (((brf[i] >> byte) & 0x01) << (7 - i)));
+            self.iram[(index + ((byte & 0x06) << 3) + (byte & 0x01)) % IRAM_SIZE] = value;
+        });
+        self.brf_line = (self.brf_line + 1) & 0x0F;
+    }
+    /// Start a DMA, after the destination address is written
+    fn start_dma(&mut self, cartridge: &mut Cartridge) {
+        if !bit(self.dma_control, 7) {
+            return;
+        }
+        if bit(self.dma_control, 5) {
+            // Character conversion type 1, where the SNES CPU DMAs the converted characters out of BW-RAM
+            if bit(self.dma_control, 4) {
+                self.char_conversion_active = true;
+                self.snes_flags |= DMA_IRQ_FLAG;
+            }
+            self.brf_line = 0;
+            return;
+        }
+        (0..self.dma_len as usize).for_each(|i| {
+            let src = self.dma_src as usize + i;
+            let value = match self.dma_control & 0x03 {
+                0 => self.read_rom(cartridge, src),
+                1 => cartridge.read_sram(src & BWRAM_MASK),
+                _ => self.iram[src % IRAM_SIZE],
+            };
+            let dest = self.dma_dest as usize + i;
+            if bit(self.dma_control, 2) {
+                cartridge.write_sram(dest & BWRAM_MASK, value);
+            } else {
+                self.iram[dest % IRAM_SIZE] = value;
+            }
+        });
+        self.sa1_flags |= DMA_IRQ_FLAG;
+    }
+    /// Write one of the DMA registers, which both CPUs can write to
+    fn write_dma(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) {
+        match address {
+            0x2231 => {
+                self.char_conversion = value;
+                // End character conversion
+                if bit(value, 7) {
+                    self.char_conversion_active = false;
+                }
+            }
+            0x2232..=0x2234 => set_address_byte(&mut self.dma_src, address - 0x2232, value),
+            0x2235 => set_address_byte(&mut self.dma_dest, 0, value),
+            0x2236 => {
+                set_address_byte(&mut self.dma_dest, 1, value);
+                // Writing the middle byte starts DMA to I-RAM
+                if !bit(self.dma_control, 2) {
+                    self.start_dma(cartridge);
+                }
+            }
+            0x2237 => {
+                set_address_byte(&mut self.dma_dest, 2, value);
+                // Writing the high byte starts DMA to BW-RAM
+                if bit(self.dma_control, 2) {
+                    self.start_dma(cartridge);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Run the arithmetic unit, after the high byte of MB is written
+    fn calculate(&mut self) {
+        let (a, b) = (self.ma as i16 as i64, self.mb as i16 as i64);
+        match self.math_control & 0x03 {
+            // Multiplication, whose result is sign extended to 40 bits
+            0 => self.mr = (a * b) as u64 & MR_MASK,
+            // Division, with a signed dividend and unsigned divisor
+            1 => {
+                let divisor = self.mb as i64;
+                self.mr = if divisor == 0 {
+                    0
+                } else {
+                    let quotient = a.div_euclid(divisor) as u16 as u64;
+                    let remainder = a.rem_euclid(divisor) as u16 as u64;
+                    (remainder << 16) | quotient
+                };
+                self.ma = 0;
+            }
+            // Cumulative sum of products
+            _ => {
+                let sum = self.mr + ((a * b) as u64 & MR_MASK);
+                // Overflow is the carry out of the 40 bit sum
+                self.math_overflow = sum > MR_MASK;
+                self.mr = sum & MR_MASK;
+            }
+        }
+        self.mb = 0;
+    }
+    /// Read the variable length data in the ROM, as a 16 bit value starting at the current bit
+    fn read_vbit(&self, cartridge: &Cartridge) -> u16 {
+        let data = (0..3).fold(0u32, |acc, i| {
+            acc | ((self.read_rom(cartridge, (self.vbit_address + i) as usize & 0xFF_FFFF) as u32)
+                << (8 * i))
+        });
+        (data >> self.vbit) as u16
+    }
+    fn advance_vbit(&mut self) {
+        self.vbit += self.vbit_len;
+        self.vbit_address = (self.vbit_address + (self.vbit >> 3)) & 0xFF_FFFF;
+        self.vbit &= 0x07;
+    }
+    /// Advance the H/V timer, and set the timer IRQ flag if it passes the position it's set to
+    fn advance_timer(&mut self, master_clocks: u32) {
+        // The linear timer counts 2048 clocks per line, the H/V timer counts dots and lines like the PPU
+        let (line_length, lines) = if bit(self.timer_control, 7) {
+            (0x800, 0x200)
+        } else {
+            (TIMER_LINE_LENGTH, SCANLINES as u32)
+        };
+        let frame_length = line_length * lines;
+        let position = (self.v_counter % lines) * line_length + self.h_counter % line_length;
+        // Number of clocks until the counter is next at `target`, which repeats every `period` clocks
+        let clocks_until =
+            |target: u32, period: u32| (target + period - position % period - 1) % period + 1;
+        let h_target = self.h_timer as u32 * MASTER_CYCLES_PER_DOT as u32;
+        let v_target = self.v_timer as u32 * line_length;
+        let h_valid = h_target < line_length;
+        let v_valid = (self.v_timer as u32) < lines;
+        let next_irq = match self.timer_control & 0x03 {
+            0 => None,
+            1 => h_valid.then(|| clocks_until(h_target, line_length)),
+            2 => v_valid.then(|| clocks_until(v_target, frame_length)),
+            _ => (h_valid && v_valid).then(|| clocks_until(v_target + h_target, frame_length)),
+        };
+        if next_irq.is_some_and(|c| c <= master_clocks) {
+            self.sa1_flags |= TIMER_IRQ_FLAG;
+        }
+        let position = ((position as u64 + master_clocks as u64) % frame_length as u64) as u32;
+        self.h_counter = position % line_length;
+        self.v_counter = position / line_length;
+    }
+    /// Whether an interrupt the SA-1 CPU has enabled is pending
+    fn sa1_irq(&self) -> bool {
+        self.sa1_flags & self.sa1_interrupt_enable & (IRQ_FLAG | TIMER_IRQ_FLAG | DMA_IRQ_FLAG) != 0
+    }
+    /// Whether an interrupt the SNES CPU has enabled is pending
+    fn snes_irq(&self) -> bool {
+        self.snes_flags & self.snes_interrupt_enable & (IRQ_FLAG | DMA_IRQ_FLAG) != 0
+    }
+    fn snes_read_io(&self, address: usize) -> u8 {
+        match address {
+            0x2300 => self.snes_flags,
+            0x230E => VERSION,
+            _ => {
+                debug!("SNES read from SA-1 register {:04X}", address);
+                0
+            }
+        }
+    }
+    fn snes_write_io(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) {
+        match address {
+            // CCNT
+            0x2200 => {
+                let reset = bit(value, 5);
+                if self.sa1_reset && !reset {
+                    self.sa1_restart = true;
+                }
+                self.sa1_reset = reset;
+                self.sa1_wait = bit(value, 6);
+                if bit(value, 7) {
+                    self.sa1_flags |= IRQ_FLAG;
+                }
+                if bit(value, 4) {
+                    self.sa1_flags |= NMI_FLAG;
+                    self.sa1_nmi_pending = self.sa1_interrupt_enable & NMI_FLAG != 0;
+                }
+                self.sa1_flags = (self.sa1_flags & 0xF0) | (value & 0x0F);
+            }
+            0x2201 => self.snes_interrupt_enable = value,
+            0x2202 => self.snes_flags &= !(value & (IRQ_FLAG | DMA_IRQ_FLAG)),
+            0x2203..=0x2204 => set_byte(&mut self.reset_vector, address - 0x2203, value),
+            0x2205..=0x2206 => set_byte(&mut self.nmi_vector, address - 0x2205, value),
+            0x2207..=0x2208 => set_byte(&mut self.irq_vector, address - 0x2207, value),
+            0x2220..=0x2223 => self.rom_blocks[address - 0x2220] = value,
+            0x2224 => self.snes_bwram_block = value & 0x1F,
+            0x2226 => self.snes_bwram_write = bit(value, 7),
+            0x2228 => self.bwram_protect = value & 0x0F,
+            0x2229 => self.snes_iram_write = value,
+            0x2231..=0x2237 => self.write_dma(cartridge, address, value),
+            _ => debug!("SNES write to SA-1 register {:04X} {:02X}", address, value),
+        }
+    }
+    fn sa1_read_io(&mut self, cartridge: &Cartridge, address: usize) -> u8 {
+        match address {
+            0x2301 => self.sa1_flags,
+            0x2302 => ((self.h_counter / MASTER_CYCLES_PER_DOT as u32) & 0xFF) as u8,
+            0x2303 => ((self.h_counter / MASTER_CYCLES_PER_DOT as u32) >> 8) as u8,
+            0x2304 => (self.v_counter & 0xFF) as u8,
+            0x2305 => (self.v_counter >> 8) as u8,
+            0x2306..=0x230A => self.mr.to_le_bytes()[address - 0x2306],
+            0x230B => u8::from(self.math_overflow) << 7,
+            0x230C => self.read_vbit(cartridge).to_le_bytes()[0],
+            0x230D => {
+                let value = self.read_vbit(cartridge).to_le_bytes()[1];
+                if self.vbit_auto {
+                    self.advance_vbit();
+                }
+                value
+            }
+            0x230E => VERSION,
+            _ => {
+                debug!("SA-1 read from register {:04X}", address);
+                0
+            }
+        }
+    }
+    fn sa1_write_io(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) {
+        match address {
+            // SCNT
+            0x2209 => {
+                if bit(value, 7) {
+                    self.snes_flags |= IRQ_FLAG;
+                }
+                self.snes_flags = (self.snes_flags & (IRQ_FLAG | DMA_IRQ_FLAG))
+                    | (value & (IRQ_VECTOR_SWITCH | NMI_VECTOR_SWITCH | 0x0F));
+            }
+            0x220A => self.sa1_interrupt_enable = value,
+            0x220B => self.sa1_flags &= !(value & 0xF0),
+            0x220C..=0x220D => set_byte(&mut self.snes_nmi_vector, address - 0x220C, value),
+            0x220E..=0x220F => set_byte(&mut self.snes_irq_vector, address - 0x220E, value),
+            0x2210 => self.timer_control = value,
+            0x2211 => {
+                self.h_counter = 0;
+                self.v_counter = 0;
+            }
+            0x2212..=0x2213 => {
+                set_byte(&mut self.h_timer, address - 0x2212, value);
+                self.h_timer &= 0x1FF;
+            }
+            0x2214..=0x2215 => {
+                set_byte(&mut self.v_timer, address - 0x2214, value);
+                self.v_timer &= 0x1FF;
+            }
+            0x2225 => self.sa1_bwram_block = value,
+            0x2227 => self.sa1_bwram_write = bit(value, 7),
+            0x222A => self.sa1_iram_write = value,
+            0x2230 => {
+                self.dma_control = value;
+                self.brf_line = 0;
+            }
+            0x2231..=0x2237 => self.write_dma(cartridge, address, value),
+            0x2238..=0x2239 => set_byte(&mut self.dma_len, address - 0x2238, value),
+            0x223F => self.bitmap_2bpp = bit(value, 7),
+            0x2240..=0x224F => {
+                self.brf[address - 0x2240] = value;
+                // Each time a row of 8 pixels is written, convert it
+                if address & 0x07 == 0x07 && self.dma_control & 0xB0 == 0xA0 {
+                    self.convert_brf_row();
+                }
+            }
+            0x2250 => {
+                self.math_control = value & 0x03;
+                // Starting a cumulative sum clears the result
+                if bit(value, 1) {
+                    self.mr = 0;
+                    self.math_overflow = false;
+                }
+            }
+            0x2251..=0x2252 => set_byte(&mut self.ma, address - 0x2251, value),
+            0x2253 => set_byte(&mut self.mb, 0, value),
+            0x2254 => {
+                set_byte(&mut self.mb, 1, value);
+                self.calculate();
+            }
+            0x2258 => {
+                self.vbit_len = match value & 0x0F {
+                    0 => 16,
+                    n => n as u32,
+                };
+                self.vbit_auto = bit(value, 7);
+                // In fixed mode, writing the length advances the data
+                if !self.vbit_auto {
+                    self.advance_vbit();
+                }
+            }
+            0x2259..=0x225B => {
+                set_address_byte(&mut self.vbit_address, address - 0x2259, value);
+                if address == 0x225B {
+                    self.vbit = 0;
+                }
+            }
+            _ => debug!("SA-1 write to register {:04X} {:02X}", address, value),
+        }
+    }
+    /// Read a byte as the SNES CPU, or return `None` if the address isn't mapped to the SA-1 board
+    fn snes_read(&self, cartridge: &Cartridge, address: usize) -> Option<u8> {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        match bank {
+            0x00..=0x3F | 0x80..=0xBF => match offset {
+                0x2200..0x2400 => Some(self.snes_read_io(offset)),
+                0x3000..0x3800 => Some(self.iram[offset - 0x3000]),
+                0x6000..0x8000 => Some(self.snes_read_bwram(
+                    cartridge,
+                    Sa1Registers::bwram_window_index(self.snes_bwram_block, offset),
+                )),
+                // Replaced NMI and IRQ vectors
+                0xFFEA..=0xFFEB if bank == 0 && self.snes_flags & NMI_VECTOR_SWITCH != 0 => {
+                    Some(self.snes_nmi_vector.to_le_bytes()[offset - 0xFFEA])
+                }
+                0xFFEE..=0xFFEF if bank == 0 && self.snes_flags & IRQ_VECTOR_SWITCH != 0 => {
+                    Some(self.snes_irq_vector.to_le_bytes()[offset - 0xFFEE])
+                }
+                0x8000..=0xFFFF => Some(self.read_rom(cartridge, address)),
+                _ => None,
+            },
+            0x40..=0x4F => Some(self.snes_read_bwram(cartridge, address & 0xF_FFFF)),
+            0xC0..=0xFF => Some(self.read_rom(cartridge, address)),
+            _ => None,
+        }
+    }
+    /// Write a byte as the SNES CPU, returning whether the address is mapped to the SA-1 board
+    fn snes_write(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) -> bool {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        match bank {
+            0x00..=0x3F | 0x80..=0xBF => match offset {
+                0x2200..0x2400 => self.snes_write_io(cartridge, offset, value),
+                0x3000..0x3800 => self.write_iram(offset - 0x3000, value, self.snes_iram_write),
+                0x6000..0x8000 => self.write_bwram(
+                    cartridge,
+                    Sa1Registers::bwram_window_index(self.snes_bwram_block, offset),
+                    value,
+                    self.snes_bwram_write,
+                ),
+                0x8000..=0xFFFF => {}
+                _ => return false,
+            },
+            0x40..=0x4F => {
+                self.write_bwram(cartridge, address & 0xF_FFFF, value, self.snes_bwram_write)
+            }
+            0xC0..=0xFF => {}
+            _ => return false,
+        }
+        true
+    }
+    /// Read a byte as the SA-1 CPU, along with the number of master clocks it takes
+    fn sa1_read(&mut self, cartridge: &Cartridge, address: usize) -> (u8, i32) {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        match bank {
+            0x00..=0x3F | 0x80..=0xBF => match offset {
+                0x0000..0x0800 => (self.iram[offset], MASTER_CLOCKS_PER_CYCLE),
+                0x2200..0x2400 => (self.sa1_read_io(cartridge, offset), MASTER_CLOCKS_PER_CYCLE),
+                0x3000..0x3800 => (self.iram[offset - 0x3000], MASTER_CLOCKS_PER_CYCLE),
+                0x6000..0x8000 => {
+                    let block = self.sa1_bwram_block;
+                    let value = if bit(block, 7) {
+                        self.read_bitmap(
+                            cartridge,
+                            Sa1Registers::bwram_window_index(block & 0x7F, offset),
+                        )
+                    } else {
+                        cartridge.read_sram(
+                            Sa1Registers::bwram_window_index(block & 0x1F, offset) & BWRAM_MASK,
+                        )
+                    };
+                    (value, BWRAM_MASTER_CLOCKS)
+                }
+                // The SA-1 CPU's own vectors
+                0xFFEA..=0xFFEB | 0xFFFA..=0xFFFB if bank == 0 => (
+                    self.nmi_vector.to_le_bytes()[offset & 0x01],
+                    MASTER_CLOCKS_PER_CYCLE,
+                ),
+                0xFFEE..=0xFFEF | 0xFFFE..=0xFFFF if bank == 0 => (
+                    self.irq_vector.to_le_bytes()[offset & 0x01],
+                    MASTER_CLOCKS_PER_CYCLE,
+                ),
+                0xFFFC..=0xFFFD if bank == 0 => (
+                    self.reset_vector.to_le_bytes()[offset & 0x01],
+                    MASTER_CLOCKS_PER_CYCLE,
+                ),
+                0x8000..=0xFFFF => (self.read_rom(cartridge, address), MASTER_CLOCKS_PER_CYCLE),
+                _ => (0, MASTER_CLOCKS_PER_CYCLE),
+            },
+            0x40..=0x4F => (
+                cartridge.read_sram(address & BWRAM_MASK),
+                BWRAM_MASTER_CLOCKS,
+            ),
+            0x60..=0x6F => (
+                self.read_bitmap(cartridge, address & 0xF_FFFF),
+                BWRAM_MASTER_CLOCKS,
+            ),
+            0xC0..=0xFF => (self.read_rom(cartridge, address), MASTER_CLOCKS_PER_CYCLE),
+            _ => (0, MASTER_CLOCKS_PER_CYCLE),
+        }
+    }
+    /// Write a byte as the SA-1 CPU, returning the number of master clocks it takes
+    fn sa1_write(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) -> i32 {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        match bank {
+            0x00..=0x3F | 0x80..=0xBF => match offset {
+                0x0000..0x0800 => self.write_iram(offset, value, self.sa1_iram_write),
+                0x2200..0x2400 => self.sa1_write_io(cartridge, offset, value),
+                0x3000..0x3800 => self.write_iram(offset - 0x3000, value, self.sa1_iram_write),
+                0x6000..0x8000 => {
+                    let block = self.sa1_bwram_block;
+                    if bit(block, 7) {
+                        self.write_bitmap(
+                            cartridge,
+                            Sa1Registers::bwram_window_index(block & 0x7F, offset),
+                            value,
+                        );
+                    } else {
+                        self.write_bwram(
+                            cartridge,
+                            Sa1Registers::bwram_window_index(block & 0x1F, offset),
+                            value,
+                            self.sa1_bwram_write,
+                        );
+                    }
+                    return BWRAM_MASTER_CLOCKS;
+                }
+                _ => {}
+            },
+            0x40..=0x4F => {
+                self.write_bwram(cartridge, address, value, self.sa1_bwram_write);
+                return BWRAM_MASTER_CLOCKS;
+            }
+            0x60..=0x6F => {
+                self.write_bitmap(cartridge, address & 0xF_FFFF, value);
+                return BWRAM_MASTER_CLOCKS;
+            }
+            _ => {}
+        }
+        MASTER_CLOCKS_PER_CYCLE
+    }
+}
+
+/// The SA-1 CPU's view of memory
+struct Sa1Bus<'a> {
+    registers: &'a mut Sa1Registers,
+    cartridge: &'a mut Cartridge,
+    /// Master clocks taken by the accesses so far
+    clocks: i32,
+}
+
+impl HasAddressBus for Sa1Bus<'_> {
+    fn io(&mut self) {
+        self.clocks += MASTER_CLOCKS_PER_CYCLE;
+    }
+    fn read(&mut self, address: usize) -> u8 {
+        let (value, clocks) = self.registers.sa1_read(self.cartridge, address);
+        self.clocks += clocks;
+        value
+    }
+    fn write(&mut self, address: usize, value: u8) {
+        self.clocks += self.registers.sa1_write(self.cartridge, address, value);
+    }
+}
+
+/// The SA-1, a second 65816 running at 10.74MHz along with RAM, DMA and an arithmetic unit.
+/// The SNES CPU starts and stops the SA-1 CPU, and the two communicate through I-RAM, BW-RAM and
+/// messages in the control registers.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Sa1 {
+    /// The SA-1 CPU
+    pub core: Processor,
+    registers: Sa1Registers,
+    /// Master clocks the SA-1 CPU is behind the SNES CPU
+    clocks: i32,
+}
+
+impl Sa1 {
+    /// The SA-1's internal RAM
+    pub fn iram(&self) -> &[u8] {
+        &self.registers.iram.0
+    }
+    /// Whether the SA-1 CPU is running, i.e. not held in reset or paused by the SNES CPU
+    pub fn is_running(&self) -> bool {
+        !self.registers.sa1_reset && !self.registers.sa1_wait
+    }
+}
+
+impl CoprocessorBoard for Sa1 {
+    fn read_byte(&mut self, cartridge: &mut Cartridge, address: usize) -> Option<u8> {
+        self.registers.snes_read(cartridge, address)
+    }
+    fn write_byte(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) -> bool {
+        self.registers.snes_write(cartridge, address, value)
+    }
+    fn advance(&mut self, cartridge: &mut Cartridge, master_clocks: u32) {
+        self.registers.advance_timer(master_clocks);
+        if std::mem::take(&mut self.registers.sa1_restart) {
+            let mut bus = Sa1Bus {
+                registers: &mut self.registers,
+                cartridge: &mut *cartridge,
+                clocks: 0,
+            };
+            self.core.reset(&mut bus);
+            self.clocks = 0;
+        }
+        if !self.is_running() {
+            return;
+        }
+        self.clocks += master_clocks as i32;
+        while self.clocks > 0 {
+            let mut bus = Sa1Bus {
+                registers: &mut self.registers,
+                cartridge: &mut *cartridge,
+                clocks: 0,
+            };
+            if std::mem::take(&mut bus.registers.sa1_nmi_pending) {
+                self.core.on_nmi(&mut bus);
+            } else if bus.registers.sa1_irq() {
+                self.core.on_irq(&mut bus);
+            }
+            self.core.step(&mut bus);
+            self.clocks -= bus.clocks.max(MASTER_CLOCKS_PER_CYCLE);
+        }
+    }
+    fn irq(&self) -> bool {
+        self.registers.snes_irq()
+    }
+    fn reset(&mut self, _cartridge: &mut Cartridge) {
+        *self = Sa1::default();
+    }
+}
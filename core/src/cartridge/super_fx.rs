@@ -0,0 +1,954 @@
+use serde::{Deserialize, Serialize};
+use serde_big_array::Array;
+
+use crate::{
+    cartridge::{Cartridge, CoprocessorBoard, mirror_address},
+    utils::bit,
+};
+
+/// Size of the GSU's instruction cache
+pub const CACHE_SIZE: usize = 0x200;
+/// The cache is filled 16 bytes at a time
+const CACHE_LINE_SIZE: usize = 0x10;
+/// Size of the Game Pak RAM, for cartridges that don't say how much they have
+const DEFAULT_RAM_SIZE: usize = 0x1_0000;
+/// Value returned when reading the version register
+const VERSION: u8 = 0x04;
+/// GSU cycles taken to fetch a byte from the cache, and from ROM or RAM
+const CACHE_CYCLES: u32 = 1;
+const MEMORY_CYCLES: u32 = 3;
+/// Values the SNES CPU reads from ROM while the GSU has access to it,
+/// which point the interrupt vectors at WRAM
+const ROM_LOCKED_VALUES: [u8; 16] = [
+    0x00, 0x01, 0x00, 0x01, 0x04, 0x01, 0x00, 0x01, 0x00, 0x01, 0x08, 0x01, 0x00, 0x01, 0x0C, 0x01,
+];
+
+// Flags in SCMR, CFGR and POR
+const RAM_ACCESS: u8 = 0x08;
+const ROM_ACCESS: u8 = 0x10;
+const IRQ_MASK: u8 = 0x80;
+const PLOT_OPAQUE: u8 = 0x01;
+const PLOT_DITHER: u8 = 0x02;
+const PLOT_HIGH_NIBBLE: u8 = 0x04;
+const PLOT_FREEZE_HIGH: u8 = 0x08;
+const PLOT_OBJ: u8 = 0x10;
+
+/// A row of 8 pixels plotted to the same character, waiting to be written to RAM
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct PixelCache {
+    /// Character row the pixels are in, as `(y << 5) + (x >> 3)`
+    offset: u16,
+    /// Which of the pixels have been plotted, with bit 7 being the leftmost
+    bitpend: u8,
+    data: [u8; 8],
+}
+
+/// A byte the GSU has stored, which is written to RAM while the GSU carries on with the next instructions
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct RamWrite {
+    index: usize,
+    value: u8,
+    /// Number of cycles into the instruction being run that the write finishes at
+    cycles: u32,
+}
+
+/// The Super FX, a RISC CPU (the GSU) with its own instruction cache which draws into bitplanes in Game Pak RAM.
+/// The SNES CPU loads a program address into R15 to start the GSU, which runs until it reaches a STOP
+/// instruction and then raises an IRQ.
+/// Both the GSU-1 and GSU-2 are emulated, which differ only in how much ROM they can address.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SuperFx {
+    /// General registers, with R15 being the program counter
+    r: [u16; 16],
+    /// Source and destination registers of the next instruction, set by the FROM, TO and WITH prefixes
+    sreg: usize,
+    dreg: usize,
+    /// Prefix flags in SFR, which select a variant of the next instruction
+    alt1: bool,
+    alt2: bool,
+    /// Whether the last instruction was WITH, which turns TO and FROM into MOVE and MOVES
+    with: bool,
+    // Status flags in SFR
+    zero: bool,
+    carry: bool,
+    sign: bool,
+    overflow: bool,
+    /// Whether the GSU is running (G in SFR)
+    go: bool,
+    /// Whether the GSU has stopped and is interrupting the SNES CPU
+    irq: bool,
+    /// The byte after the instruction being run, which has already been fetched
+    /// This is why the instruction after a jump or branch always runs
+    pipe: u8,
+    /// Whether the instruction being run wrote R15
+    r15_written: bool,
+    /// Program bank (PBR), ROM bank for R14 (ROMBR) and RAM bank for loads and stores (RAMBR)
+    program_bank: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    /// Address the cache starts at (CBR)
+    cache_base: u16,
+    /// Config (CFGR), screen base (SCBR), clock select (CLSR) and screen mode (SCMR) registers
+    config: u8,
+    screen_base: u8,
+    clock_select: u8,
+    screen_mode: u8,
+    /// Plot option (POR) and colour (COLR) registers
+    plot_option: u8,
+    color: u8,
+    /// The byte at ROMBR:R14, which is fetched whenever R14 is written
+    rom_buffer: u8,
+    /// Address of the last RAM access, which SBK writes back to
+    ram_address: u16,
+    /// The RAM write in progress, which the GSU has to wait for before it can access RAM again
+    ram_buffer: Option<RamWrite>,
+    cache: Box<Array<u8, CACHE_SIZE>>,
+    /// Which lines of the cache have been filled
+    cache_valid: [bool; CACHE_SIZE / CACHE_LINE_SIZE],
+    /// The row being plotted to, and the previous one which is written to RAM when the next row starts
+    pixel_caches: [PixelCache; 2],
+    /// Game Pak RAM, unless the cartridge has SRAM which is used instead
+    ram: Vec<u8>,
+    /// Master clocks the GSU is behind the SNES CPU
+    clocks: i32,
+    /// GSU cycles taken by the instruction being run
+    #[serde(skip)]
+    cycles: u32,
+}
+
+impl Default for SuperFx {
+    fn default() -> Self {
+        SuperFx::new(0)
+    }
+}
+
+impl SuperFx {
+    /// Create a GSU with `ram_size` bytes of Game Pak RAM, or using the cartridge's SRAM if `ram_size` is 0
+    pub fn new(ram_size: usize) -> SuperFx {
+        SuperFx {
+            r: [0; 16],
+            sreg: 0,
+            dreg: 0,
+            alt1: false,
+            alt2: false,
+            with: false,
+            zero: false,
+            carry: false,
+            sign: false,
+            overflow: false,
+            go: false,
+            irq: false,
+            pipe: 0x01,
+            r15_written: false,
+            program_bank: 0,
+            rom_bank: 0,
+            ram_bank: 0,
+            cache_base: 0,
+            config: 0,
+            screen_base: 0,
+            clock_select: 0,
+            screen_mode: 0,
+            plot_option: 0,
+            color: 0,
+            rom_buffer: 0,
+            ram_address: 0,
+            ram_buffer: None,
+            cache: Box::new(Array([0; CACHE_SIZE])),
+            cache_valid: [false; CACHE_SIZE / CACHE_LINE_SIZE],
+            pixel_caches: [PixelCache::default(); 2],
+            ram: vec![0; ram_size],
+            clocks: 0,
+            cycles: 0,
+        }
+    }
+    /// Create the GSU for a cartridge, which uses its SRAM if it has any
+    /// and otherwise has as much RAM as the extended header says
+    pub fn for_cartridge(cartridge: &Cartridge) -> SuperFx {
+        if !cartridge.sram().is_empty() {
+            return SuperFx::new(0);
+        }
+        let size = cartridge
+            .header()
+            .extended
+            .map(|e| e.expansion_ram_size)
+            .filter(|s| *s > 0)
+            .unwrap_or(DEFAULT_RAM_SIZE);
+        SuperFx::new(size)
+    }
+    /// The GSU's general registers
+    pub fn registers(&self) -> &[u16; 16] {
+        &self.r
+    }
+    /// Whether the GSU is running
+    pub fn is_running(&self) -> bool {
+        self.go
+    }
+    /// Game Pak RAM, if the GSU has its own instead of using the cartridge's SRAM
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn read_ram(&self, cartridge: &Cartridge, index: usize) -> u8 {
+        if self.ram.is_empty() {
+            cartridge.read_sram(index)
+        } else {
+            self.ram[mirror_address(index, self.ram.len())]
+        }
+    }
+    fn write_ram(&mut self, cartridge: &mut Cartridge, index: usize, value: u8) {
+        if self.ram.is_empty() {
+            cartridge.write_sram(index, value);
+        } else {
+            let index = mirror_address(index, self.ram.len());
+            self.ram[index] = value;
+        }
+    }
+    /// Index in ROM of an address, which is LoROM in banks $00-$3F and HiROM in banks $40-$5F
+    fn rom_index(address: usize) -> usize {
+        let bank = (address >> 16) & 0x7F;
+        let offset = address & 0xFFFF;
+        if bank < 0x40 {
+            ((bank & 0x3F) << 15) | (offset & 0x7FFF)
+        } else {
+            ((bank & 0x1F) << 16) | offset
+        }
+    }
+    /// Read a byte in the GSU's address space, where banks $70-$71 are RAM and the rest is ROM
+    fn read(&self, cartridge: &Cartridge, bank: u8, address: u16) -> u8 {
+        match bank {
+            0x70..=0x7F => {
+                self.read_ram(cartridge, ((bank as usize & 0x01) << 16) | address as usize)
+            }
+            _ => cartridge.read_rom(SuperFx::rom_index(
+                ((bank as usize) << 16) | address as usize,
+            )),
+        }
+    }
+    fn ram_index(&self, address: u16) -> usize {
+        ((self.ram_bank as usize & 0x01) << 16) | address as usize
+    }
+    /// Wait for the RAM write in progress to finish
+    fn flush_ram_buffer(&mut self, cartridge: &mut Cartridge) {
+        if let Some(write) = self.ram_buffer.take() {
+            self.cycles = self.cycles.max(write.cycles);
+            self.write_ram(cartridge, write.index, write.value);
+        }
+    }
+    /// Finish the RAM write in progress if it finished during the instruction just run,
+    /// and otherwise count the cycles it still needs from the start of the next one
+    fn advance_ram_buffer(&mut self, cartridge: &mut Cartridge) {
+        match &mut self.ram_buffer {
+            Some(write) if write.cycles > self.cycles => write.cycles -= self.cycles,
+            Some(_) => self.flush_ram_buffer(cartridge),
+            None => {}
+        }
+    }
+    fn load_byte(&mut self, cartridge: &mut Cartridge, address: u16) -> u8 {
+        self.flush_ram_buffer(cartridge);
+        self.ram_address = address;
+        self.cycles += MEMORY_CYCLES;
+        self.read_ram(cartridge, self.ram_index(address))
+    }
+    /// Load a word from RAM, whose high byte is always at the address with bit 0 flipped
+    fn load_word(&mut self, cartridge: &mut Cartridge, address: u16) -> u16 {
+        let low = self.load_byte(cartridge, address);
+        let high = self.read_ram(cartridge, self.ram_index(address ^ 1));
+        u16::from_le_bytes([low, high])
+    }
+    /// Start writing a byte to RAM, after waiting for the previous write to finish
+    fn buffer_ram_write(&mut self, cartridge: &mut Cartridge, address: u16, value: u8) {
+        self.flush_ram_buffer(cartridge);
+        self.ram_buffer = Some(RamWrite {
+            index: self.ram_index(address),
+            value,
+            cycles: self.cycles + MEMORY_CYCLES,
+        });
+    }
+    fn store_byte(&mut self, cartridge: &mut Cartridge, address: u16, value: u8) {
+        self.ram_address = address;
+        self.buffer_ram_write(cartridge, address, value);
+    }
+    /// Store a word, whose high byte has to wait for the low byte to be written
+    fn store_word(&mut self, cartridge: &mut Cartridge, address: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.store_byte(cartridge, address, low);
+        self.buffer_ram_write(cartridge, address ^ 1, high);
+    }
+
+    /// Empty the instruction cache
+    fn flush_cache(&mut self) {
+        self.cache_valid = [false; CACHE_SIZE / CACHE_LINE_SIZE];
+    }
+    /// Fetch the byte at PBR:R15, going through the cache if R15 is in it
+    fn fetch(&mut self, cartridge: &Cartridge) -> u8 {
+        let address = self.r[15];
+        let offset = address.wrapping_sub(self.cache_base) as usize;
+        if offset >= CACHE_SIZE {
+            self.cycles += MEMORY_CYCLES;
+            return self.read(cartridge, self.program_bank, address);
+        }
+        let line = offset / CACHE_LINE_SIZE;
+        if !self.cache_valid[line] {
+            let start = line * CACHE_LINE_SIZE;
+            (start..start + CACHE_LINE_SIZE).for_each(|i| {
+                let address = self.cache_base.wrapping_add(i as u16);
+                self.cache[i] = self.read(cartridge, self.program_bank, address);
+            });
+            self.cache_valid[line] = true;
+            self.cycles += MEMORY_CYCLES * CACHE_LINE_SIZE as u32;
+        }
+        self.cycles += CACHE_CYCLES;
+        self.cache[offset]
+    }
+    /// Read an immediate operand, which is the byte in the pipe
+    fn operand(&mut self, cartridge: &Cartridge) -> u8 {
+        let value = self.pipe;
+        self.r[15] = self.r[15].wrapping_add(1);
+        self.pipe = self.fetch(cartridge);
+        value
+    }
+    fn operand_word(&mut self, cartridge: &Cartridge) -> u16 {
+        let low = self.operand(cartridge);
+        u16::from_le_bytes([low, self.operand(cartridge)])
+    }
+    /// Write a register, which reloads the ROM buffer for R14 and jumps for R15
+    fn set_register(&mut self, cartridge: &Cartridge, n: usize, value: u16) {
+        self.r[n] = value;
+        match n {
+            14 => {
+                self.rom_buffer = self.read(cartridge, self.rom_bank, value);
+                self.cycles += MEMORY_CYCLES;
+            }
+            15 => self.r15_written = true,
+            _ => {}
+        }
+    }
+    /// Write the destination register and set the sign and zero flags from it
+    fn set_result(&mut self, cartridge: &Cartridge, value: u16) {
+        self.set_sign_zero(value);
+        self.set_register(cartridge, self.dreg, value);
+    }
+    fn set_sign_zero(&mut self, value: u16) {
+        self.sign = bit((value >> 8) as u8, 7);
+        self.zero = value == 0;
+    }
+    fn source(&self) -> u16 {
+        self.r[self.sreg]
+    }
+
+    fn sfr(&self) -> u16 {
+        [
+            (self.zero, 1),
+            (self.carry, 2),
+            (self.sign, 3),
+            (self.overflow, 4),
+            (self.go, 5),
+            (self.alt1, 8),
+            (self.alt2, 9),
+            (self.with, 12),
+            (self.irq, 15),
+        ]
+        .into_iter()
+        .fold(0, |acc, (flag, n)| acc | ((flag as u16) << n))
+    }
+    fn set_sfr(&mut self, value: u16) {
+        let flag = |n: usize| (value >> n) & 0x01 != 0;
+        self.zero = flag(1);
+        self.carry = flag(2);
+        self.sign = flag(3);
+        self.overflow = flag(4);
+        self.alt1 = flag(8);
+        self.alt2 = flag(9);
+        self.with = flag(12);
+        self.irq = flag(15);
+        let go = flag(5);
+        if go && !self.go {
+            self.start();
+        } else if !go && self.go {
+            // Stopping the GSU from the SNES CPU also empties the cache
+            self.go = false;
+            self.cache_base = 0;
+            self.flush_cache();
+        }
+    }
+    /// Start running the GSU from PBR:R15, once the instruction there has been fetched
+    fn start(&mut self) {
+        self.go = true;
+        self.r15_written = true;
+        self.pipe = 0x01;
+    }
+
+    /// The colour that would be set by COLOR or GETC, depending on the plot options
+    fn color_value(&self, value: u8) -> u8 {
+        if self.plot_option & PLOT_HIGH_NIBBLE != 0 {
+            (self.color & 0xF0) | (value >> 4)
+        } else if self.plot_option & PLOT_FREEZE_HIGH != 0 {
+            (self.color & 0xF0) | (value & 0x0F)
+        } else {
+            value
+        }
+    }
+    fn bits_per_pixel(&self) -> usize {
+        match self.screen_mode & 0x03 {
+            0 => 2,
+            3 => 8,
+            _ => 4,
+        }
+    }
+    /// Index in RAM of the first byte of the character row that the pixel at `x`, `y` is in
+    fn pixel_row_index(&self, x: u8, y: u8) -> usize {
+        let (x, y) = (x as usize, y as usize);
+        let height = ((self.screen_mode >> 4) & 0x02) | ((self.screen_mode >> 2) & 0x01);
+        // Number of the character, where characters are in columns of 16, 20 or 24, or in the OBJ layout
+        let char_number = match if self.plot_option & PLOT_OBJ != 0 {
+            3
+        } else {
+            height
+        } {
+            0 => ((x & 0xF8) << 1) + ((y & 0xF8) >> 3),
+            1 => ((x & 0xF8) << 1) + ((x & 0xF8) >> 1) + ((y & 0xF8) >> 3),
+            2 => ((x & 0xF8) << 1) + (x & 0xF8) + ((y & 0xF8) >> 3),
+            _ => ((y & 0x80) << 2) + ((x & 0x80) << 1) + ((y & 0x78) << 1) + ((x & 0x78) >> 3),
+        };
+        (self.screen_base as usize) * 0x400
+            + char_number * self.bits_per_pixel() * 8
+            + (y & 0x07) * 2
+    }
+    /// Write the pixels in a pixel cache to RAM, leaving pixels that weren't plotted unchanged
+    fn flush_pixel_cache(&mut self, cartridge: &mut Cartridge, n: usize) {
+        let cache = self.pixel_caches[n];
+        if cache.bitpend == 0 {
+            return;
+        }
+        self.flush_ram_buffer(cartridge);
+        let index = self.pixel_row_index((cache.offset << 3) as u8, (cache.offset >> 5) as u8);
+        (0..self.bits_per_pixel()).for_each(|plane| {
+            // Pairs of bitplanes are interleaved in each row, and each pair of bitplanes is 16 bytes apart
+            let byte_index = index + ((plane >> 1) << 4) + (plane & 0x01);
+            let mut value = (0..8).fold(0, |acc, x| acc | (((cache.data[x] >> plane) & 0x01) << x));
+            if cache.bitpend != 0xFF {
+                value &= cache.bitpend;
+                value |= self.read_ram(cartridge, byte_index) & !cache.bitpend;
+            }
+            self.cycles += MEMORY_CYCLES;
+            self.write_ram(cartridge, byte_index, value);
+        });
+        self.pixel_caches[n].bitpend = 0;
+    }
+    /// Move the current pixel cache into the secondary one, writing the secondary one to RAM first
+    fn rotate_pixel_caches(&mut self, cartridge: &mut Cartridge) {
+        self.flush_pixel_cache(cartridge, 1);
+        self.pixel_caches[1] = self.pixel_caches[0];
+        self.pixel_caches[0].bitpend = 0;
+    }
+    /// Plot a pixel at R1, R2 in COLR
+    fn plot(&mut self, cartridge: &mut Cartridge) {
+        let (x, y) = (self.r[1] as u8, self.r[2] as u8);
+        let eight_bit = self.screen_mode & 0x03 == 3;
+        let mut color = self.color;
+        if self.plot_option & PLOT_DITHER != 0 && !eight_bit {
+            if (x ^ y) & 0x01 != 0 {
+                color >>= 4;
+            }
+            color &= 0x0F;
+        }
+        let transparent = if eight_bit && self.plot_option & PLOT_FREEZE_HIGH == 0 {
+            color == 0
+        } else {
+            color & 0x0F == 0
+        };
+        if self.plot_option & PLOT_OPAQUE == 0 && transparent {
+            return;
+        }
+        let offset = ((y as u16) << 5) + (x as u16 >> 3);
+        if offset != self.pixel_caches[0].offset {
+            self.rotate_pixel_caches(cartridge);
+            self.pixel_caches[0].offset = offset;
+        }
+        let x = ((x & 0x07) ^ 0x07) as usize;
+        self.pixel_caches[0].data[x] = color;
+        self.pixel_caches[0].bitpend |= 1 << x;
+        if self.pixel_caches[0].bitpend == 0xFF {
+            self.rotate_pixel_caches(cartridge);
+        }
+    }
+    /// Read the colour of the pixel at R1, R2, after writing both pixel caches to RAM
+    fn read_pixel(&mut self, cartridge: &mut Cartridge) -> u8 {
+        self.flush_ram_buffer(cartridge);
+        self.flush_pixel_cache(cartridge, 1);
+        self.flush_pixel_cache(cartridge, 0);
+        let (x, y) = (self.r[1] as u8, self.r[2] as u8);
+        let index = self.pixel_row_index(x, y);
+        let shift = (x & 0x07) ^ 0x07;
+        (0..self.bits_per_pixel()).fold(0, |acc, plane| {
+            let byte = self.read_ram(cartridge, index + ((plane >> 1) << 4) + (plane & 0x01));
+            acc | (((byte >> shift) & 0x01) << plane)
+        })
+    }
+
+    fn add(&mut self, cartridge: &Cartridge, value: u16, with_carry: bool) {
+        let s = self.source();
+        let result = s as u32 + value as u32 + (with_carry && self.carry) as u32;
+        let r = result as u16;
+        self.carry = result > 0xFFFF;
+        self.overflow = !(s ^ value) & (value ^ r) & 0x8000 != 0;
+        self.set_result(cartridge, r);
+    }
+    /// Subtract `value` from the source register, storing the result unless this is a CMP
+    fn sub(&mut self, cartridge: &Cartridge, value: u16, with_carry: bool, store: bool) {
+        let s = self.source();
+        let result = s as i32 - value as i32 - (with_carry && !self.carry) as i32;
+        let r = result as u16;
+        self.carry = result >= 0;
+        self.overflow = (s ^ value) & (s ^ r) & 0x8000 != 0;
+        if store {
+            self.set_result(cartridge, r);
+        } else {
+            self.set_sign_zero(r);
+        }
+    }
+    fn branch(&mut self, cartridge: &Cartridge, condition: bool) {
+        let offset = self.operand(cartridge) as i8;
+        if condition {
+            self.r[15] = self.r[15].wrapping_add_signed(offset as i16);
+            self.r15_written = true;
+        }
+    }
+
+    /// Run a single instruction
+    fn step(&mut self, cartridge: &mut Cartridge) {
+        let opcode = self.pipe;
+        // If R15 was written, the byte in the pipe is still run but the next one comes from the new address
+        if !std::mem::take(&mut self.r15_written) {
+            self.r[15] = self.r[15].wrapping_add(1);
+        }
+        self.pipe = self.fetch(cartridge);
+        let n = (opcode & 0x0F) as usize;
+        let (alt1, alt2) = (self.alt1, self.alt2);
+        match opcode {
+            // STOP
+            0x00 => {
+                if self.config & IRQ_MASK == 0 {
+                    self.irq = true;
+                }
+                self.flush_ram_buffer(cartridge);
+                self.go = false;
+                self.pipe = 0x01;
+            }
+            // NOP
+            0x01 => {}
+            // CACHE
+            0x02 => {
+                if self.cache_base != self.r[15] & 0xFFF0 {
+                    self.cache_base = self.r[15] & 0xFFF0;
+                    self.flush_cache();
+                }
+            }
+            // LSR
+            0x03 => {
+                let s = self.source();
+                self.carry = bit(s as u8, 0);
+                self.set_result(cartridge, s >> 1);
+            }
+            // ROL
+            0x04 => {
+                let s = self.source();
+                let result = (s << 1) | self.carry as u16;
+                self.carry = bit((s >> 8) as u8, 7);
+                self.set_result(cartridge, result);
+            }
+            0x05 => self.branch(cartridge, true),
+            0x06 => self.branch(cartridge, self.sign == self.overflow),
+            0x07 => self.branch(cartridge, self.sign != self.overflow),
+            0x08 => self.branch(cartridge, !self.zero),
+            0x09 => self.branch(cartridge, self.zero),
+            0x0A => self.branch(cartridge, !self.sign),
+            0x0B => self.branch(cartridge, self.sign),
+            0x0C => self.branch(cartridge, !self.carry),
+            0x0D => self.branch(cartridge, self.carry),
+            0x0E => self.branch(cartridge, !self.overflow),
+            0x0F => self.branch(cartridge, self.overflow),
+            // MOVE Rn, Rs after WITH, and otherwise the TO prefix
+            0x10..=0x1F => {
+                if !self.with {
+                    self.dreg = n;
+                    return;
+                }
+                self.set_register(cartridge, n, self.source());
+            }
+            // WITH
+            0x20..=0x2F => {
+                self.sreg = n;
+                self.dreg = n;
+                self.with = true;
+                return;
+            }
+            // STW (Rn) and STB (Rn)
+            0x30..=0x3B => {
+                if alt1 {
+                    self.store_byte(cartridge, self.r[n], self.source() as u8);
+                } else {
+                    self.store_word(cartridge, self.r[n], self.source());
+                }
+            }
+            // LOOP
+            0x3C => {
+                let counter = self.r[12].wrapping_sub(1);
+                self.r[12] = counter;
+                self.set_sign_zero(counter);
+                if counter != 0 {
+                    self.set_register(cartridge, 15, self.r[13]);
+                }
+            }
+            // ALT1, ALT2 and ALT3
+            0x3D..=0x3F => {
+                self.alt1 = opcode != 0x3E;
+                self.alt2 = opcode != 0x3D;
+                return;
+            }
+            // LDW (Rn) and LDB (Rn)
+            0x40..=0x4B => {
+                let value = if alt1 {
+                    self.load_byte(cartridge, self.r[n]) as u16
+                } else {
+                    self.load_word(cartridge, self.r[n])
+                };
+                self.set_register(cartridge, self.dreg, value);
+            }
+            // PLOT and RPIX
+            0x4C => {
+                if alt1 {
+                    let value = self.read_pixel(cartridge);
+                    self.set_result(cartridge, value as u16);
+                } else {
+                    self.plot(cartridge);
+                    self.r[1] = self.r[1].wrapping_add(1);
+                }
+            }
+            // SWAP
+            0x4D => self.set_result(cartridge, self.source().rotate_left(8)),
+            // COLOR and CMODE
+            0x4E => {
+                if alt1 {
+                    self.plot_option = self.source() as u8 & 0x1F;
+                } else {
+                    self.color = self.color_value(self.source() as u8);
+                }
+            }
+            // NOT
+            0x4F => self.set_result(cartridge, !self.source()),
+            // ADD, ADC, ADD #n and ADC #n
+            0x50..=0x5F => {
+                let value = if alt2 { n as u16 } else { self.r[n] };
+                self.add(cartridge, value, alt1);
+            }
+            // SUB, SBC, SUB #n and CMP
+            0x60..=0x6F => match (alt1, alt2) {
+                (false, false) => self.sub(cartridge, self.r[n], false, true),
+                (true, false) => self.sub(cartridge, self.r[n], true, true),
+                (false, true) => self.sub(cartridge, n as u16, false, true),
+                (true, true) => self.sub(cartridge, self.r[n], false, false),
+            },
+            // MERGE
+            0x70 => {
+                let result = (self.r[7] & 0xFF00) | (self.r[8] >> 8);
+                self.set_register(cartridge, self.dreg, result);
+                self.sign = result & 0x8080 != 0;
+                self.overflow = result & 0xC0C0 != 0;
+                self.carry = result & 0xE0E0 != 0;
+                self.zero = result & 0xF0F0 != 0;
+            }
+            // AND, BIC, AND #n and BIC #n
+            0x71..=0x7F => {
+                let value = if alt2 { n as u16 } else { self.r[n] };
+                let value = if alt1 { !value } else { value };
+                self.set_result(cartridge, self.source() & value);
+            }
+            // MULT, UMULT, MULT #n and UMULT #n
+            0x80..=0x8F => {
+                let value = if alt2 { n as u16 } else { self.r[n] };
+                let result = if alt1 {
+                    (self.source() & 0xFF) * (value & 0xFF)
+                } else {
+                    ((self.source() as i8 as i16) * (value as i8 as i16)) as u16
+                };
+                self.cycles += 1;
+                self.set_result(cartridge, result);
+            }
+            // SBK
+            0x90 => self.store_word(cartridge, self.ram_address, self.source()),
+            // LINK #n
+            0x91..=0x94 => self.r[11] = self.r[15].wrapping_add(n as u16),
+            // SEX
+            0x95 => self.set_result(cartridge, self.source() as i8 as i16 as u16),
+            // ASR and DIV2
+            0x96 => {
+                let s = self.source();
+                self.carry = bit(s as u8, 0);
+                let result = if alt1 && s == 0xFFFF {
+                    0
+                } else {
+                    ((s as i16) >> 1) as u16
+                };
+                self.set_result(cartridge, result);
+            }
+            // ROR
+            0x97 => {
+                let s = self.source();
+                let result = (s >> 1) | ((self.carry as u16) << 15);
+                self.carry = bit(s as u8, 0);
+                self.set_result(cartridge, result);
+            }
+            // JMP Rn and LJMP Rn
+            0x98..=0x9D => {
+                if alt1 {
+                    self.program_bank = self.r[n] as u8 & 0x7F;
+                    self.cache_base = self.source() & 0xFFF0;
+                    self.flush_cache();
+                    self.set_register(cartridge, 15, self.source());
+                } else {
+                    self.set_register(cartridge, 15, self.r[n]);
+                }
+            }
+            // LOB
+            0x9E => {
+                let result = self.source() & 0xFF;
+                self.set_register(cartridge, self.dreg, result);
+                self.sign = bit(result as u8, 7);
+                self.zero = result == 0;
+            }
+            // FMULT and LMULT
+            0x9F => {
+                let result = (self.source() as i16 as i32) * (self.r[6] as i16 as i32);
+                if alt1 {
+                    self.set_register(cartridge, 4, result as u16);
+                }
+                self.carry = (result >> 15) & 0x01 != 0;
+                self.cycles += 4;
+                self.set_result(cartridge, (result >> 16) as u16);
+            }
+            // IBT Rn, #pp, LMS Rn, (yy) and SMS (yy), Rn
+            0xA0..=0xAF => {
+                let operand = self.operand(cartridge);
+                if alt2 {
+                    self.store_word(cartridge, (operand as u16) << 1, self.r[n]);
+                } else if alt1 {
+                    let value = self.load_word(cartridge, (operand as u16) << 1);
+                    self.set_register(cartridge, n, value);
+                } else {
+                    self.set_register(cartridge, n, operand as i8 as i16 as u16);
+                }
+            }
+            // MOVES Rd, Rn after WITH, and otherwise the FROM prefix
+            0xB0..=0xBF => {
+                if !self.with {
+                    self.sreg = n;
+                    return;
+                }
+                let value = self.r[n];
+                self.overflow = bit(value as u8, 7);
+                self.set_result(cartridge, value);
+            }
+            // HIB
+            0xC0 => {
+                let result = self.source() >> 8;
+                self.set_register(cartridge, self.dreg, result);
+                self.sign = bit(result as u8, 7);
+                self.zero = result == 0;
+            }
+            // OR, XOR, OR #n and XOR #n
+            0xC1..=0xCF => {
+                let value = if alt2 { n as u16 } else { self.r[n] };
+                let result = if alt1 {
+                    self.source() ^ value
+                } else {
+                    self.source() | value
+                };
+                self.set_result(cartridge, result);
+            }
+            // INC Rn
+            0xD0..=0xDE => {
+                let value = self.r[n].wrapping_add(1);
+                self.set_sign_zero(value);
+                self.set_register(cartridge, n, value);
+            }
+            // GETC, RAMB and ROMB
+            0xDF => match (alt1, alt2) {
+                (false, true) => self.ram_bank = self.source() as u8 & 0x01,
+                (true, true) => self.rom_bank = self.source() as u8 & 0x7F,
+                _ => self.color = self.color_value(self.rom_buffer),
+            },
+            // DEC Rn
+            0xE0..=0xEE => {
+                let value = self.r[n].wrapping_sub(1);
+                self.set_sign_zero(value);
+                self.set_register(cartridge, n, value);
+            }
+            // GETB, GETBH, GETBL and GETBS
+            0xEF => {
+                let b = self.rom_buffer as u16;
+                let value = match (alt1, alt2) {
+                    (false, false) => b,
+                    (true, false) => (b << 8) | (self.source() & 0x00FF),
+                    (false, true) => (self.source() & 0xFF00) | b,
+                    (true, true) => b as u8 as i8 as i16 as u16,
+                };
+                self.set_register(cartridge, self.dreg, value);
+            }
+            // IWT Rn, #xx, LM Rn, (xx) and SM (xx), Rn
+            0xF0..=0xFF => {
+                let operand = self.operand_word(cartridge);
+                if alt2 {
+                    self.store_word(cartridge, operand, self.r[n]);
+                } else if alt1 {
+                    let value = self.load_word(cartridge, operand);
+                    self.set_register(cartridge, n, value);
+                } else {
+                    self.set_register(cartridge, n, operand);
+                }
+            }
+        }
+        // Prefixes only apply to the instruction after them
+        self.alt1 = false;
+        self.alt2 = false;
+        self.with = false;
+        self.sreg = 0;
+        self.dreg = 0;
+    }
+
+    fn read_io(&mut self, offset: usize) -> u8 {
+        match offset {
+            0x3000..0x3020 => self.r[(offset - 0x3000) / 2].to_le_bytes()[offset & 0x01],
+            0x3030 => self.sfr() as u8,
+            0x3031 => {
+                let value = (self.sfr() >> 8) as u8;
+                // Reading the high byte of SFR acknowledges the IRQ
+                self.irq = false;
+                value
+            }
+            0x3034 => self.program_bank,
+            0x3036 => self.rom_bank,
+            0x303B => VERSION,
+            0x303C => self.ram_bank,
+            0x303E => self.cache_base as u8,
+            0x303F => (self.cache_base >> 8) as u8,
+            0x3100..0x3300 => self.cache[(offset - 0x3100 + self.cache_base as usize) % CACHE_SIZE],
+            _ => 0,
+        }
+    }
+    fn write_io(&mut self, offset: usize, value: u8) {
+        match offset {
+            0x3000..0x3020 => {
+                let n = (offset - 0x3000) / 2;
+                let mut bytes = self.r[n].to_le_bytes();
+                bytes[offset & 0x01] = value;
+                self.r[n] = u16::from_le_bytes(bytes);
+                // Writing the high byte of R15 starts the GSU
+                if offset == 0x301F {
+                    self.start();
+                }
+            }
+            0x3030 => self.set_sfr((self.sfr() & 0xFF00) | value as u16),
+            0x3031 => self.set_sfr((self.sfr() & 0x00FF) | ((value as u16) << 8)),
+            0x3034 => {
+                self.program_bank = value & 0x7F;
+                self.flush_cache();
+            }
+            0x3037 => self.config = value,
+            0x3038 => self.screen_base = value,
+            0x3039 => self.clock_select = value,
+            0x303A => self.screen_mode = value,
+            0x3100..0x3300 => {
+                let index = (offset - 0x3100 + self.cache_base as usize) % CACHE_SIZE;
+                self.cache[index] = value;
+                // Lines are valid once their last byte has been written
+                if index % CACHE_LINE_SIZE == CACHE_LINE_SIZE - 1 {
+                    self.cache_valid[index / CACHE_LINE_SIZE] = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Read ROM as the SNES CPU, which can't see ROM while the GSU is running with access to it
+    fn snes_read_rom(&self, cartridge: &Cartridge, address: usize) -> u8 {
+        if self.go && self.screen_mode & ROM_ACCESS != 0 {
+            ROM_LOCKED_VALUES[address & 0x0F]
+        } else {
+            cartridge.read_rom(SuperFx::rom_index(address))
+        }
+    }
+    /// Whether the SNES CPU is locked out of Game Pak RAM
+    fn snes_ram_locked(&self) -> bool {
+        self.go && self.screen_mode & RAM_ACCESS != 0
+    }
+}
+
+impl CoprocessorBoard for SuperFx {
+    fn read_byte(&mut self, cartridge: &mut Cartridge, address: usize) -> Option<u8> {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        match bank & 0x7F {
+            0x00..=0x3F => match offset {
+                0x3000..0x3300 => Some(self.read_io(offset)),
+                0x6000..0x8000 if self.snes_ram_locked() => Some(0),
+                0x6000..0x8000 => Some(self.read_ram(cartridge, offset - 0x6000)),
+                0x8000..=0xFFFF => Some(self.snes_read_rom(cartridge, address & 0x7F_FFFF)),
+                _ => None,
+            },
+            0x40..=0x5F => Some(self.snes_read_rom(cartridge, address & 0x7F_FFFF)),
+            0x70..=0x71 if self.snes_ram_locked() => Some(0),
+            0x70..=0x71 => Some(self.read_ram(cartridge, address & 0x1_FFFF)),
+            _ => None,
+        }
+    }
+    fn write_byte(&mut self, cartridge: &mut Cartridge, address: usize, value: u8) -> bool {
+        let bank = (address >> 16) & 0xFF;
+        let offset = address & 0xFFFF;
+        let ram_index = match bank & 0x7F {
+            0x00..=0x3F => match offset {
+                0x3000..0x3300 => {
+                    self.write_io(offset, value);
+                    return true;
+                }
+                0x6000..0x8000 => offset - 0x6000,
+                0x8000..=0xFFFF => return true,
+                _ => return false,
+            },
+            0x40..=0x5F => return true,
+            0x70..=0x71 => address & 0x1_FFFF,
+            _ => return false,
+        };
+        if !self.snes_ram_locked() {
+            self.write_ram(cartridge, ram_index, value);
+        }
+        true
+    }
+    fn advance(&mut self, cartridge: &mut Cartridge, master_clocks: u32) {
+        if !self.go {
+            // The SNES CPU may have stopped the GSU in the middle of a write
+            self.flush_ram_buffer(cartridge);
+            return;
+        }
+        // The GSU runs at 21.48MHz if CLSR is set, and 10.74MHz otherwise
+        let clocks_per_cycle = if bit(self.clock_select, 0) { 1 } else { 2 };
+        self.clocks += master_clocks as i32;
+        while self.go && self.clocks > 0 {
+            self.cycles = 0;
+            self.step(cartridge);
+            self.advance_ram_buffer(cartridge);
+            self.clocks -= (self.cycles.max(1) * clocks_per_cycle) as i32;
+        }
+        if !self.go {
+            self.clocks = 0;
+        }
+    }
+    fn irq(&self) -> bool {
+        self.irq
+    }
+    fn reset(&mut self, _cartridge: &mut Cartridge) {
+        // Game Pak RAM keeps its contents
+        let ram = std::mem::take(&mut self.ram);
+        *self = SuperFx::new(0);
+        self.ram = ram;
+    }
+}
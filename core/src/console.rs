@@ -2,13 +2,16 @@ use derive_new::new;
 use log::*;
 use serde::{Deserialize, Serialize};
 use serde_big_array::Array;
+use spc700::HaltState;
 use wdc65816::{HasAddressBus, Processor};
 
 use crate::{
     Cartridge, Cpu, InputPort, Ppu,
-    apu::Apu,
+    apu::{Apu, Id666Tag, SpcFile},
+    cartridge::{Board, CartridgeError, CoprocessorBoard, MemoryMap},
     dma::{AddressAdjustMode as DmaAddressAdjustMode, Channel as DmaChannel},
     math::Math,
+    port_log::{PendingPortAccesses, PortLog, PortLogEntry, PortSide},
     utils::bit,
 };
 use paste::paste;
@@ -26,6 +29,10 @@ pub struct ExternalArchitecture {
     #[new(value = "[0; 4]")]
     pub apu_to_cpu_reg: [u8; 4],
     pub cartridge: Cartridge,
+    /// The enhancement chip on the cartridge board, if there is one
+    #[new(default)]
+    #[serde(default)]
+    pub board: Option<Board>,
     #[new(default)]
     pub ppu: Ppu,
     // Math module for multiplication and division
@@ -56,6 +63,13 @@ pub struct ExternalArchitecture {
     /// Current S-WRAM address
     #[new(value = "0")]
     pub wram_addr: usize,
+    /// Accesses to $2140-$2143 since they were last logged, when the port log is enabled
+    #[new(value = "None")]
+    #[serde(skip)]
+    pub port_accesses: PendingPortAccesses,
+    #[new(value = "None")]
+    #[serde(skip)]
+    pub port_log: Option<PortLog>,
 }
 
 // Todo: move somewhere
@@ -67,6 +81,13 @@ fn byte_from_bits(bits: [bool; 8]) -> u8 {
 }
 impl ExternalArchitecture {
     pub fn read_byte(&mut self, addr: usize) -> (u8, u32) {
+        if let Some(v) = self
+            .board
+            .as_mut()
+            .and_then(|b| b.read_byte(&mut self.cartridge, addr))
+        {
+            return (v, self.cartridge_access_clocks(addr));
+        }
         if (0x7E_0000..0x80_0000).contains(&addr) {
             (self.ram[addr - 0x7E_0000], 8)
         } else if (addr % 0x80_0000) < 0x40_0000 && addr & 0xFFFF < 0x8000 {
@@ -78,7 +99,12 @@ impl ExternalArchitecture {
                     (0, 6)
                 }
                 0x2100..0x2140 => (self.ppu.read_byte(a, self.open_bus_value), 6),
-                0x2140..0x2180 => (self.apu_to_cpu_reg[a % 4], 6),
+                0x2140..0x2180 => {
+                    let value = self.apu_to_cpu_reg[a % 4];
+                    let clock = self.total_master_clocks;
+                    PortLog::record(&mut self.port_accesses, clock, false, a % 4, value);
+                    (value, 6)
+                }
                 // Read S-WRAM
                 0x2180 => {
                     let v = self.ram[self.wram_addr % self.ram.len()];
@@ -228,6 +254,10 @@ impl ExternalArchitecture {
                         (0, 6)
                     }
                 }
+                // HiROM SRAM
+                0x6000..0x8000 if self.cartridge.is_sram_address(addr) => {
+                    (self.cartridge.read_byte(addr), 8)
+                }
                 0x6000..0x8000 => {
                     // Expansion
                     (0, 6)
@@ -241,18 +271,33 @@ impl ExternalArchitecture {
         } else {
             (
                 self.cartridge.read_byte(addr),
-                if addr > 0x80_0000 && self.fast_rom_enabled {
-                    6
-                } else {
-                    8
-                },
+                self.cartridge_access_clocks(addr),
             )
         }
     }
+    /// Number of master clocks an access to the cartridge at `addr` takes,
+    /// for addresses the cartridge or its board handles rather than the console
+    fn cartridge_access_clocks(&self, addr: usize) -> u32 {
+        if (addr % 0x80_0000) < 0x40_0000 && addr & 0xFFFF < 0x8000 {
+            // Registers in the system area are as fast as the console's, SRAM is slow
+            if addr & 0xFFFF < 0x6000 { 6 } else { 8 }
+        } else if addr > 0x80_0000 && self.fast_rom_enabled {
+            6
+        } else {
+            8
+        }
+    }
     // Writes a byte without advancing anything
     // May trigger a DMA
     // Returns the number of master cycles needed to access the memory
     pub fn write_byte(&mut self, addr: usize, value: u8) -> u32 {
+        if self
+            .board
+            .as_mut()
+            .is_some_and(|b| b.write_byte(&mut self.cartridge, addr, value))
+        {
+            return self.cartridge_access_clocks(addr);
+        }
         if (0x7E0000..0x800000).contains(&addr) {
             self.ram[addr - 0x7E0000] = value;
             8
@@ -266,6 +311,11 @@ impl ExternalArchitecture {
                         self.ram[a] = value;
                         6
                     }
+                    // HiROM SRAM
+                    (0x6000..0x8000) if self.cartridge.is_sram_address(addr) => {
+                        self.cartridge.write_byte(addr, value);
+                        8
+                    }
                     (0x2000..0x2100) => {
                         // Open bus?
                         6
@@ -276,6 +326,8 @@ impl ExternalArchitecture {
                         6
                     }
                     (0x2140..0x2180) => {
+                        let clock = self.total_master_clocks;
+                        PortLog::record(&mut self.port_accesses, clock, true, a % 4, value);
                         self.cpu_to_apu_reg[a % 4] = value;
                         6
                     }
@@ -448,6 +500,13 @@ impl ExternalArchitecture {
     pub fn advance(&mut self, master_clocks: u32) {
         self.total_master_clocks += master_clocks as u64;
         self.ppu.advance_master_clock(master_clocks);
+        if let Some(b) = &mut self.board {
+            b.advance(&mut self.cartridge, master_clocks);
+        }
+    }
+    /// Whether the cartridge board is asserting an IRQ
+    fn board_irq(&self) -> bool {
+        self.board.as_ref().is_some_and(|b| b.irq())
     }
 }
 impl HasAddressBus for ExternalArchitecture {
@@ -493,6 +552,7 @@ impl Console {
     rest_field! {ppu, Ppu}
     rest_field! {ram, Box<Array<u8, WRAM_SIZE>>}
     rest_field! {cartridge, Cartridge}
+    rest_field! {board, Option<Board>}
     rest_field! {dma_channels, [DmaChannel; 8]}
     rest_field! {total_master_clocks, u64}
     rest_field! {total_apu_clocks, u64}
@@ -514,20 +574,34 @@ impl Console {
     pub fn apu_opcode(&self) -> u8 {
         0
     }
-    pub fn with_cartridge(cartridge_data: &[u8]) -> Console {
+    pub fn with_cartridge(cartridge_data: &[u8]) -> Result<Console, CartridgeError> {
+        Console::with_cartridge_map(cartridge_data, None)
+    }
+    /// Create a console with a cartridge, using `memory_map` instead of detecting it if given
+    pub fn with_cartridge_map(
+        cartridge_data: &[u8],
+        memory_map: Option<MemoryMap>,
+    ) -> Result<Console, CartridgeError> {
+        let cartridge = Cartridge::from_data_with_map(cartridge_data, memory_map)?;
         let mut c = Console {
             cpu: Cpu::default(),
             apu: Apu::default(),
-            rest: ExternalArchitecture::new(Cartridge::from_data(cartridge_data)),
+            rest: ExternalArchitecture::new(cartridge),
         };
+        c.rest.board = Board::for_cartridge(&c.rest.cartridge);
+        if let Some(b) = &mut c.rest.board {
+            b.reset(&mut c.rest.cartridge);
+        }
         c.cpu.reset(&mut c.rest);
         debug!("Initialized PC to {:X}", c.cpu.core.pc);
-        c
+        Ok(c)
     }
     pub fn step_cpu(&mut self) {
         let vblank = self.ppu().is_in_vblank();
         let hblank = self.ppu().is_in_hblank() && !vblank;
+        let pcs = (self.pc(), self.apu.core.pc);
         self.cpu.step(&mut self.rest);
+        self.log_port_accesses(PortSide::Cpu, pcs);
         if !vblank && self.ppu().is_in_vblank() {
             // Trigger NMI
             if self.rest.nmi_enabled {
@@ -540,7 +614,7 @@ impl Console {
                 d.hdma_line_counter = 0;
             });
         }
-        if self.ppu().trigger_irq {
+        if self.ppu().trigger_irq || self.rest.board_irq() {
             self.cpu.on_irq(&mut self.rest);
         }
         // the timing here is maybe a little bit off, but if we just exited vblank, set up the hblank DMA registers
@@ -625,8 +699,91 @@ impl Console {
             })
         }
     }
-    pub fn step_apu(&mut self) {
-        self.rest.apu_to_cpu_reg = self.apu.step(&mut self.rest.cpu_to_apu_reg);
+    /// Load the state saved in an SPC file into the APU
+    pub fn load_spc(&mut self, spc: &SpcFile) {
+        self.apu.load_spc(spc);
+        if self.rest.port_log.is_some() {
+            self.apu.rest.port_accesses = Some(Vec::new());
+        }
+        self.rest.cpu_to_apu_reg = self.apu.rest.cpu_to_apu_reg;
+    }
+    /// Save the APU's current state as an SPC file, tagged with the cartridge's title
+    pub fn save_spc(&self) -> SpcFile {
+        let tag = Id666Tag {
+            game_title: self.rest.cartridge.title().trim().to_string(),
+            ..Default::default()
+        };
+        let mut spc = SpcFile::from_apu(&self.apu, Some(tag));
+        // The CPU may have written to the ports since the APU last ran
+        spc.ram[0xF4..0xF8].copy_from_slice(&self.rest.cpu_to_apu_reg);
+        spc
+    }
+    /// Run a single step of the APU, returning why the SPC700 is halted, if it is
+    pub fn step_apu(&mut self) -> Option<HaltState> {
+        let pcs = (self.pc(), self.apu.core.pc);
+        let halted;
+        (self.rest.apu_to_cpu_reg, halted) = self.apu.step(&mut self.rest.cpu_to_apu_reg);
+        self.log_port_accesses(PortSide::Apu, pcs);
+        halted
+    }
+    /// Why the SPC700 is halted, if it is
+    pub fn apu_halted(&self) -> Option<HaltState> {
+        self.apu.halted()
+    }
+    /// Run a single SPC700 instruction.
+    /// If the APU is ahead of the CPU, the CPU is run until the APU is due to run first, so that they stay in sync.
+    pub fn step_apu_instruction(&mut self) {
+        while !self.apu_is_behind() {
+            self.step_cpu();
+        }
+        self.step_apu();
+    }
+    /// Start logging the accesses to the ports between the CPU and the APU, keeping the latest `capacity` entries
+    pub fn enable_port_log(&mut self, capacity: usize) {
+        self.rest.port_log = Some(PortLog::new(capacity));
+        self.rest.port_accesses = Some(Vec::new());
+        self.apu.rest.port_accesses = Some(Vec::new());
+    }
+    pub fn disable_port_log(&mut self) {
+        self.rest.port_log = None;
+        self.rest.port_accesses = None;
+        self.apu.rest.port_accesses = None;
+    }
+    pub fn port_log(&self) -> Option<&PortLog> {
+        self.rest.port_log.as_ref()
+    }
+    pub fn port_log_mut(&mut self) -> Option<&mut PortLog> {
+        self.rest.port_log.as_mut()
+    }
+    /// Move the accesses one of the processors made during its last step into the port log.
+    /// `pcs` are the CPU and APU program counters before the step.
+    fn log_port_accesses(&mut self, side: PortSide, pcs: (usize, u16)) {
+        let accesses = match side {
+            PortSide::Cpu => self.rest.port_accesses.as_mut(),
+            PortSide::Apu => self.apu.rest.port_accesses.as_mut(),
+        };
+        let Some(accesses) = accesses.filter(|a| !a.is_empty()) else {
+            return;
+        };
+        let accesses = std::mem::take(accesses);
+        if let Some(log) = &mut self.rest.port_log {
+            accesses.into_iter().for_each(|(clock, access)| {
+                log.push(PortLogEntry {
+                    master_clock: match side {
+                        PortSide::Cpu => clock,
+                        // Convert from APU clocks
+                        PortSide::Apu => {
+                            (clock as u128 * MASTER_CLOCK_SPEED_HZ as u128
+                                / APU_CLOCK_SPEED_HZ as u128) as u64
+                        }
+                    },
+                    side,
+                    access,
+                    cpu_pc: pcs.0,
+                    apu_pc: pcs.1,
+                })
+            });
+        }
     }
     // Returns whether the APU is "behind" the CPU, i.e. it has advanced fewer master cycles
     pub fn apu_is_behind(&self) -> bool {
@@ -655,9 +812,24 @@ impl Console {
     }
     /// Reset the console
     pub fn reset(&mut self) {
+        if let Some(b) = &mut self.rest.board {
+            b.reset(&mut self.rest.cartridge);
+        }
         self.cpu.reset(&mut self.rest);
         self.apu.reset();
     }
+    /// The contents of the cartridge's SRAM, i.e. to save as a .srm file
+    pub fn sram(&self) -> &[u8] {
+        self.rest.cartridge.sram()
+    }
+    /// Load the contents of the cartridge's SRAM, i.e. from a .srm file
+    pub fn load_sram(&mut self, sram: &[u8]) {
+        self.rest.cartridge.load_sram(sram);
+    }
+    /// Whether the game has written to SRAM since this was last called
+    pub fn take_sram_written(&mut self) -> bool {
+        self.rest.cartridge.take_sram_written()
+    }
     /// Read a byte in CPU space
     /// NOTE: Only reads from the cartridge
     /// TODO: Find a way to handle reading from everywhere without making self mutable
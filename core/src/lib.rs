@@ -1,3 +1,5 @@
+#[cfg(feature = "archive")]
+pub mod archive;
 pub mod cartridge;
 mod console;
 mod cpu;
@@ -5,6 +7,8 @@ mod input_port;
 mod math;
 
 pub mod dma;
+pub mod patch;
+pub mod port_log;
 pub mod ppu;
 pub mod utils;
 pub use cartridge::Cartridge;
@@ -0,0 +1,344 @@
+use std::fmt::Display;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const UPS_MAGIC: &[u8] = b"UPS1";
+/// Size of the footer of BPS and UPS patches, which holds the source, target and patch checksums
+const FOOTER_SIZE: usize = 12;
+/// Largest patched ROM a BPS or UPS patch can make, well above the largest SNES ROMs
+pub const MAX_TARGET_SIZE: usize = 0x100_0000;
+
+/// The format of a ROM patch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+    Ups,
+}
+
+impl PatchFormat {
+    /// Detect the format of a patch from its magic string
+    pub fn from_bytes(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else if patch.starts_with(UPS_MAGIC) {
+            Some(PatchFormat::Ups)
+        } else {
+            None
+        }
+    }
+    /// File extensions used for each format
+    pub const EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with the magic string of a known format
+    UnknownFormat,
+    /// The patch ends in the middle of a record
+    Truncated,
+    /// A record reads past the end of the source ROM or the target
+    OutOfBounds,
+    /// The ROM is not the one the patch was made for, given as the expected and actual sizes
+    SourceSize(usize, usize),
+    /// The ROM is not the one the patch was made for, given as the expected and actual checksums
+    SourceChecksum(u32, u32),
+    /// The patched ROM doesn't have the checksum the patch expects
+    TargetChecksum(u32, u32),
+    /// The patch itself is corrupt
+    PatchChecksum(u32, u32),
+    /// The patched ROM would be larger than `MAX_TARGET_SIZE`, given as its size
+    TargetSize(usize),
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use PatchError::*;
+        match self {
+            UnknownFormat => write!(f, "Patch is not an IPS, BPS or UPS patch"),
+            Truncated => write!(f, "Patch ends unexpectedly"),
+            OutOfBounds => write!(f, "Patch reads outside of the ROM"),
+            SourceSize(expected, actual) => write!(
+                f,
+                "Patch is for a ROM of {} bytes, but the ROM is {} bytes",
+                expected, actual
+            ),
+            SourceChecksum(expected, actual) => write!(
+                f,
+                "Patch is for a ROM with CRC32 {:08X}, but the ROM has CRC32 {:08X}",
+                expected, actual
+            ),
+            TargetChecksum(expected, actual) => write!(
+                f,
+                "Patched ROM should have CRC32 {:08X}, but has CRC32 {:08X}",
+                expected, actual
+            ),
+            PatchChecksum(expected, actual) => write!(
+                f,
+                "Patch should have CRC32 {:08X}, but has CRC32 {:08X}",
+                expected, actual
+            ),
+            TargetSize(size) => write!(
+                f,
+                "Patched ROM would be {} bytes, but can be at most {} bytes",
+                size, MAX_TARGET_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Compute the CRC32 of some data, as used by BPS and UPS patches
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(0xFFFF_FFFF, |crc, b| {
+        (0..8).fold(crc ^ *b as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Reads the values in a patch, failing if the patch ends early
+struct PatchReader<'a> {
+    patch: &'a [u8],
+    index: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(patch: &'a [u8], index: usize) -> Self {
+        PatchReader { patch, index }
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .patch
+            .get(self.index..self.index.checked_add(len).ok_or(PatchError::Truncated)?)
+            .ok_or(PatchError::Truncated)?;
+        self.index += len;
+        Ok(bytes)
+    }
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+    /// Read a big endian value, as used by IPS
+    fn big_endian(&mut self, len: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | *b as usize))
+    }
+    /// Read a variable length number, as used by BPS and UPS
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut value = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let b = self.byte()?;
+            value = ((b & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|v| value.checked_add(v))
+                .ok_or(PatchError::Truncated)?;
+            if b & 0x80 != 0 {
+                return Ok(value);
+            }
+            // Shifting further would lose bits
+            if shift > usize::MAX >> 7 {
+                return Err(PatchError::Truncated);
+            }
+            shift <<= 7;
+            value = value.checked_add(shift).ok_or(PatchError::Truncated)?;
+        }
+    }
+}
+
+/// Read the checksums in the footer of a BPS or UPS patch, and check the patch's own checksum
+fn read_footer(patch: &[u8]) -> Result<(u32, u32), PatchError> {
+    let footer = &patch[patch.len() - FOOTER_SIZE..];
+    let u32_at = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+    let actual = crc32(&patch[..patch.len() - 4]);
+    if u32_at(8) != actual {
+        return Err(PatchError::PatchChecksum(u32_at(8), actual));
+    }
+    Ok((u32_at(0), u32_at(4)))
+}
+
+/// Apply a patch to a ROM, detecting the format of the patch
+/// The ROM should not have a copier header
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::from_bytes(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+/// Apply an IPS patch, including RLE records and the optional truncation length after the EOF marker
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(IPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+    let mut target = rom.to_vec();
+    let mut r = PatchReader::new(patch, IPS_MAGIC.len());
+    loop {
+        if r.bytes(3)? == IPS_EOF {
+            break;
+        }
+        r.index -= 3;
+        let offset = r.big_endian(3)?;
+        let (len, data) = match r.big_endian(2)? {
+            // RLE record
+            0 => {
+                let len = r.big_endian(2)?;
+                (len, None)
+            }
+            len => (len, Some(r.bytes(len)?)),
+        };
+        if target.len() < offset + len {
+            target.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => target[offset..offset + len].copy_from_slice(data),
+            None => target[offset..offset + len].fill(r.byte()?),
+        }
+    }
+    // Truncate the ROM
+    if let Ok(len) = r.big_endian(3) {
+        target.truncate(len);
+    }
+    Ok(target)
+}
+
+/// Apply a BPS patch, checking the CRC32 of the ROM, the patched ROM and the patch
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(BPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+    if patch.len() < BPS_MAGIC.len() + FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let mut r = PatchReader::new(&patch[..patch.len() - FOOTER_SIZE], BPS_MAGIC.len());
+    let source_size = r.number()?;
+    let target_size = r.number()?;
+    let metadata_size = r.number()?;
+    r.bytes(metadata_size)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TargetSize(target_size));
+    }
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSize(source_size, rom.len()));
+    }
+    if crc32(rom) != source_crc {
+        return Err(PatchError::SourceChecksum(source_crc, crc32(rom)));
+    }
+    let mut target = Vec::with_capacity(target_size);
+    // Relative offsets used by the copy commands
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    let offset = |offset: usize, data: usize| -> Result<usize, PatchError> {
+        let delta = data >> 1;
+        if data & 1 == 1 {
+            offset.checked_sub(delta).ok_or(PatchError::OutOfBounds)
+        } else {
+            offset.checked_add(delta).ok_or(PatchError::OutOfBounds)
+        }
+    };
+    // Get the range of `len` bytes from `start`
+    let range = |start: usize, len: usize| -> Result<std::ops::Range<usize>, PatchError> {
+        Ok(start..start.checked_add(len).ok_or(PatchError::OutOfBounds)?)
+    };
+    while r.index < r.patch.len() {
+        let data = r.number()?;
+        let len = (data >> 2) + 1;
+        // Don't let the patch grow the target past the size it gave
+        if len > target_size - target.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+        match data & 0x03 {
+            // Source read
+            0 => {
+                let start = target.len();
+                target
+                    .extend_from_slice(rom.get(range(start, len)?).ok_or(PatchError::OutOfBounds)?);
+            }
+            // Target read
+            1 => target.extend_from_slice(r.bytes(len)?),
+            // Source copy
+            2 => {
+                source_offset = offset(source_offset, r.number()?)?;
+                target.extend_from_slice(
+                    rom.get(range(source_offset, len)?)
+                        .ok_or(PatchError::OutOfBounds)?,
+                );
+                source_offset += len;
+            }
+            // Target copy, which can overlap with the bytes it is writing
+            _ => {
+                target_offset = offset(target_offset, r.number()?)?;
+                for _ in 0..len {
+                    let b = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    target.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+    if crc32(&target) != target_crc {
+        return Err(PatchError::TargetChecksum(target_crc, crc32(&target)));
+    }
+    Ok(target)
+}
+
+/// Apply a UPS patch, checking the CRC32 of the ROM, the patched ROM and the patch
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(UPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+    if patch.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+        return Err(PatchError::Truncated);
+    }
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let mut r = PatchReader::new(&patch[..patch.len() - FOOTER_SIZE], UPS_MAGIC.len());
+    let source_size = r.number()?;
+    let target_size = r.number()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::TargetSize(target_size));
+    }
+    if source_size != rom.len() {
+        return Err(PatchError::SourceSize(source_size, rom.len()));
+    }
+    if crc32(rom) != source_crc {
+        return Err(PatchError::SourceChecksum(source_crc, crc32(rom)));
+    }
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut i = 0usize;
+    while r.index < r.patch.len() {
+        i = i.checked_add(r.number()?).ok_or(PatchError::OutOfBounds)?;
+        if i > target.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+        // XOR bytes until a 0
+        loop {
+            let b = r.byte()?;
+            if b == 0 {
+                i += 1;
+                break;
+            }
+            *target.get_mut(i).ok_or(PatchError::OutOfBounds)? ^= b;
+            i += 1;
+        }
+    }
+    if crc32(&target) != target_crc {
+        return Err(PatchError::TargetChecksum(target_crc, crc32(&target)));
+    }
+    Ok(target)
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+
+/// Which processor accessed a port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortSide {
+    /// The CPU, through $2140-$2143
+    Cpu,
+    /// The SPC700, through $F4-$F7
+    Apu,
+}
+
+/// A single access to one of the ports, before it has been timestamped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortAccess {
+    pub write: bool,
+    /// Index of the port, 0-3
+    pub port: u8,
+    pub value: u8,
+}
+
+/// An entry in the port log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortLogEntry {
+    /// Master clock the access happened on
+    pub master_clock: u64,
+    pub side: PortSide,
+    pub access: PortAccess,
+    /// Address of the CPU instruction being run, including the bank
+    pub cpu_pc: usize,
+    /// Address of the SPC700 instruction being run
+    pub apu_pc: u16,
+}
+
+/// Accesses a processor has made since they were last moved into the log,
+/// along with the clock each one started on, in the clocks of that processor
+pub type PendingPortAccesses = Option<Vec<(u64, PortAccess)>>;
+
+/// Ring buffer of the accesses made to the ports between the CPU and the APU
+#[derive(Debug, Clone)]
+pub struct PortLog {
+    entries: VecDeque<PortLogEntry>,
+    capacity: usize,
+}
+
+impl PortLog {
+    /// Create a log which holds up to `capacity` entries, discarding the oldest ones once full
+    pub fn new(capacity: usize) -> PortLog {
+        PortLog {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+    /// Add an access that started on `clock` to `pending`, if the log is enabled
+    pub(crate) fn record(
+        pending: &mut PendingPortAccesses,
+        clock: u64,
+        write: bool,
+        port: usize,
+        value: u8,
+    ) {
+        if let Some(accesses) = pending {
+            accesses.push((
+                clock,
+                PortAccess {
+                    write,
+                    port: port as u8,
+                    value,
+                },
+            ));
+        }
+    }
+    /// Add an entry, keeping the entries in order of when they happened
+    /// One processor can run slightly ahead of the other, so an entry can be older than the last few
+    pub fn push(&mut self, entry: PortLogEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        let index = self
+            .entries
+            .iter()
+            .rposition(|e| e.master_clock <= entry.master_clock)
+            .map_or(0, |i| i + 1);
+        self.entries.insert(index, entry);
+    }
+    /// The entries, from oldest to newest
+    pub fn entries(&self) -> &VecDeque<PortLogEntry> {
+        &self.entries
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,236 @@
+use spc700::{HaltState, HasAddressBus};
+use super_yane::{
+    Console,
+    apu::{Apu, ApuBreak},
+};
+
+#[test]
+fn test_apu_test_register() {
+    let mut apu = Apu::default();
+    let r = &mut apu.rest;
+    // Writing to TEST should not crash, and reads of TEST/CONTROL return 0
+    r.write(0xF0, 0x0A);
+    assert_eq!(r.read(0xF0), 0);
+    assert_eq!(r.read(0xF1), 0);
+    // Disable RAM writes
+    r.write(0xF0, 0x08);
+    r.write(0x0200, 0x12);
+    assert_eq!(r.read(0x0200), 0x00);
+    r.write(0xF0, 0x0A);
+    r.write(0x0200, 0x12);
+    assert_eq!(r.read(0x0200), 0x12);
+    // Wait states slow down memory accesses
+    let before = r.total_clocks;
+    r.read(0x0200);
+    assert_eq!(r.total_clocks - before, 2);
+    r.write(0xF0, 0x3A);
+    let before = r.total_clocks;
+    r.read(0x0200);
+    assert_eq!(r.total_clocks - before, 20);
+    // TEST can't be written while P is set
+    r.write(0xF0, 0x0A);
+    r.direct_page_high = true;
+    r.write(0xF0, 0x08);
+    r.write(0x0200, 0x34);
+    assert_eq!(r.read(0x0200), 0x34);
+    // P is taken from the SPC700 before each instruction
+    let mut apu = Apu::default();
+    apu.rest.ram[0x0200..0x0203].copy_from_slice(&[0xC5, 0xF0, 0x00]); // MOV !$00F0, A
+    apu.core.pc = 0x0200;
+    apu.core.a = 0x08;
+    apu.core.psw.p = true;
+    apu.step(&mut [0; 4]);
+    assert_eq!(apu.rest.test, 0x0A);
+}
+
+#[test]
+fn test_apu_timers() {
+    let mut apu = Apu::default();
+    let r = &mut apu.rest;
+    r.write(0xFA, 0x01);
+    // Timers don't run until enabled
+    r.advance_apu_clocks(0x1000);
+    assert_eq!(r.read(0xFD), 0);
+    r.write(0xF1, 0x01);
+    r.advance_apu_clocks(384 * 4);
+    assert!(r.timers[0].counter > 0);
+    // Halted by TEST
+    r.read(0xFD);
+    r.write(0xF0, 0x0B);
+    r.advance_apu_clocks(0x1000);
+    assert_eq!(r.read(0xFD), 0);
+    r.write(0xF0, 0x0A);
+    // Restart on a 0 to 1 edge only
+    r.advance_apu_clocks(384 * 4);
+    r.write(0xF1, 0x01);
+    assert!(r.timers[0].counter > 0);
+    r.write(0xF1, 0x00);
+    r.write(0xF1, 0x01);
+    assert_eq!(r.timers[0].counter, 0);
+    // Clearing the input ports
+    r.cpu_to_apu_reg = [1, 2, 3, 4];
+    r.write(0xF1, 0x20);
+    assert_eq!(r.cpu_to_apu_reg, [1, 2, 0, 0]);
+    r.write(0xF1, 0x10);
+    assert_eq!(r.cpu_to_apu_reg, [0, 0, 0, 0]);
+}
+
+#[test]
+fn test_apu_halt() {
+    [(0xEF, HaltState::Sleep), (0xFF, HaltState::Stop)]
+        .into_iter()
+        .for_each(|(opcode, state)| {
+            let mut apu = Apu::default();
+            apu.rest.ram[0x0200] = opcode;
+            apu.core.pc = 0x0200;
+            let mut ports = [0; 4];
+            assert_eq!(apu.step(&mut ports).1, Some(state));
+            // Time passes, but no more instructions are run
+            let (pc, clocks) = (apu.core.pc, apu.rest.total_clocks);
+            assert_eq!(apu.step(&mut ports).1, Some(state));
+            assert_eq!(apu.core.pc, pc);
+            assert!(apu.rest.total_clocks > clocks);
+            // Until the APU is reset
+            apu.reset();
+            assert_eq!(apu.halted(), None);
+        });
+}
+
+/// Upload `data` to `addr` through the IPL ROM and jump to it, acting as the CPU would
+fn ipl_upload(apu: &mut Apu, addr: u16, data: &[u8]) -> usize {
+    let mut ports = [0; 4];
+    let mut steps = 0;
+    let mut wait_for = |apu: &mut Apu, ports: &mut [u8; 4], value: u8| {
+        while apu.step(ports).0[0] != value {
+            steps += 1;
+        }
+    };
+    wait_for(apu, &mut ports, 0xAA);
+    ports = [0xCC, 0x01, addr as u8, (addr >> 8) as u8];
+    wait_for(apu, &mut ports, 0xCC);
+    data.iter().enumerate().for_each(|(i, b)| {
+        ports[1] = *b;
+        ports[0] = i as u8;
+        wait_for(apu, &mut ports, i as u8);
+    });
+    // Jump to the start of the block
+    let end = (data.len() as u8).wrapping_add(1);
+    ports = [end, 0x00, addr as u8, (addr >> 8) as u8];
+    wait_for(apu, &mut ports, end);
+    while apu.core.pc >= 0xFFC0 {
+        apu.step(&mut ports);
+        steps += 1;
+    }
+    steps
+}
+
+#[test]
+fn test_apu_fast_ipl() {
+    let data: Vec<u8> = (0..0x180).map(|i| (i * 7) as u8).collect();
+    let mut slow = Apu::default();
+    let mut fast = Apu::default();
+    fast.fast_ipl = true;
+    let slow_steps = ipl_upload(&mut slow, 0x0400, &data);
+    let fast_steps = ipl_upload(&mut fast, 0x0400, &data);
+    assert!(fast_steps < slow_steps);
+    // Both end up in the same state
+    assert_eq!(&fast.rest.ram[0x0400..0x0580], data.as_slice());
+    assert_eq!(fast.rest.ram[0..0x100], slow.rest.ram[0..0x100]);
+    assert_eq!(fast.core.pc, 0x0400);
+    assert_eq!(slow.core.pc, 0x0400);
+    let regs = |a: &Apu| {
+        (
+            a.core.a,
+            a.core.x,
+            a.core.y,
+            a.core.sp,
+            a.core.psw.to_byte(),
+        )
+    };
+    assert_eq!(regs(&fast), regs(&slow));
+    assert_eq!(fast.rest.apu_to_cpu_reg, slow.rest.apu_to_cpu_reg);
+
+    // Copying the block directly also ends up in the same state
+    let mut copied = Apu::default();
+    copied.upload(&[(0x0400, &data)], 0x0400);
+    assert_eq!(copied.rest.ram[0..0x0580], slow.rest.ram[0..0x0580]);
+    assert_eq!(copied.core.pc, 0x0400);
+    assert_eq!(regs(&copied), regs(&slow));
+    assert_eq!(copied.rest.apu_to_cpu_reg, slow.rest.apu_to_cpu_reg);
+}
+
+#[test]
+fn test_apu_breakpoints() {
+    let mut apu = Apu::default();
+    let mut ports = [0; 4];
+    // The IPL ROM clears the zero page starting at $EF, then writes $AA to port 0 at $FFC9
+    apu.breakpoints_mut().write.insert(0x00EF);
+    apu.breakpoints_mut().pc.insert(0xFFC9);
+    apu.breakpoints_mut().read.insert(0x00F4);
+    let mut run = |apu: &mut Apu| {
+        (0..10_000)
+            .find_map(|_| {
+                apu.step(&mut ports);
+                apu.take_breakpoint_hit()
+            })
+            .unwrap()
+    };
+    assert_eq!(run(&mut apu), ApuBreak::Write(0x00EF));
+    // MOV (X), A
+    assert_eq!(apu.stop_location(ApuBreak::Write(0x00EF)), 0xFFC5);
+    assert_eq!(run(&mut apu), ApuBreak::Pc(0xFFC9));
+    assert_eq!(apu.core.pc, 0xFFC9);
+    assert_eq!(apu.stop_location(ApuBreak::Pc(0xFFC9)), 0xFFC9);
+    assert_eq!(run(&mut apu), ApuBreak::Read(0x00F4));
+    // CMP $F4, #$CC
+    assert_eq!(apu.stop_location(ApuBreak::Read(0x00F4)), 0xFFCF);
+    // Stepping past the breakpoint doesn't hit it again
+    apu.step(&mut ports);
+    assert_eq!(apu.take_breakpoint_hit(), None);
+    // Until the loop reads the port again
+    apu.step(&mut ports);
+    assert_eq!(apu.take_breakpoint_hit(), Some(ApuBreak::Read(0x00F4)));
+}
+
+#[test]
+fn test_step_apu_instruction() {
+    let mut c = Console::with_cartridge(include_bytes!("./roms/SPC700ADC.sfc")).unwrap();
+    assert_eq!(c.apu().core.pc, 0xFFC0);
+    c.step_apu_instruction();
+    // MOV X, #$EF
+    assert_eq!(c.apu().core.pc, 0xFFC2);
+    assert_eq!(c.apu().core.x, 0xEF);
+    c.step_apu_instruction();
+    assert_eq!(c.apu().core.pc, 0xFFC3);
+}
+
+#[test]
+fn test_console_apu_halt() {
+    let mut c = Console::with_cartridge(include_bytes!("./roms/SPC700ADC.sfc")).unwrap();
+    assert_eq!(c.step_apu(), None);
+    assert_eq!(c.apu_halted(), None);
+    // STOP
+    c.apu_mut().rest.ram[0x0200] = 0xFF;
+    c.apu_mut().core.pc = 0x0200;
+    assert_eq!(c.step_apu(), Some(HaltState::Stop));
+    // The console keeps running while the SPC700 is halted
+    c.advance_instructions(10);
+    assert_eq!(c.apu_halted(), Some(HaltState::Stop));
+}
+
+#[test]
+fn test_apu_write_ram() {
+    let mut apu = Apu::default();
+    let clocks = *apu.total_clocks();
+    apu.write_ram(0x0200, 0x12);
+    assert_eq!(apu.read_ram(0x0200), 0x12);
+    // Writing to the IO registers doesn't affect them
+    apu.write_ram(0x00F1, 0x07);
+    assert!(apu.rest.timers.iter().all(|t| !t.enabled));
+    assert_eq!(apu.ram()[0xF1], 0x07);
+    // The IPL ROM still hides the RAM under it
+    apu.write_ram(0xFFC0, 0x00);
+    assert_eq!(apu.read_ram(0xFFC0), 0xCD);
+    assert_eq!(apu.ram()[0xFFC0], 0x00);
+    assert_eq!(*apu.total_clocks(), clocks);
+}
@@ -0,0 +1,98 @@
+use std::io::{Cursor, Write};
+
+use flate2::{Compression, write::GzEncoder};
+use super_yane::{
+    Cartridge,
+    archive::{ArchiveError, ArchiveFormat, RomChoice, choose_rom},
+};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+/// Build a 32KB LoROM with a plausible header, for the given region
+fn rom(region: u8) -> Vec<u8> {
+    let mut data = vec![0; 0x8000];
+    data[0x7FC0..0x7FD5].copy_from_slice(b"ARCHIVE TEST         ");
+    // Map mode, chipset, ROM size, SRAM size and region
+    data[0x7FD5..0x7FDA].copy_from_slice(&[0x20, 0x00, 0x00, 0x00, region]);
+    data[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    data[0] = 0x78;
+    data
+}
+
+/// Build a zip containing each file
+fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(vec![]));
+    for (name, data) in files {
+        writer
+            .start_file(*name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_archive_format() {
+    assert_eq!(
+        ArchiveFormat::from_bytes(&zip(&[("game.sfc", &rom(0))])),
+        Some(ArchiveFormat::Zip)
+    );
+    assert_eq!(
+        ArchiveFormat::from_bytes(&gzip(&rom(0))),
+        Some(ArchiveFormat::Gzip)
+    );
+    assert_eq!(ArchiveFormat::from_bytes(&rom(0)), None);
+}
+
+#[test]
+fn test_choose_rom() {
+    // Files that aren't ROMs are ignored, including ones too small to be a ROM
+    let archive = zip(&[
+        ("readme.txt", b"Not a ROM\n"),
+        ("game.sfc", &rom(0)),
+        ("tiny.sfc", &[0x78; 0x100]),
+    ]);
+    let RomChoice::One(e) = choose_rom(&archive, "archive_one.zip").unwrap() else {
+        panic!("Should pick a single ROM");
+    };
+    assert_eq!(e.name, "game.sfc");
+    assert_eq!(
+        Cartridge::from_data(&e.data).unwrap().title(),
+        "ARCHIVE TEST"
+    );
+
+    // The file in a gzip is named after the gzip
+    let RomChoice::One(e) = choose_rom(&gzip(&rom(0)), "archive.sfc.gz").unwrap() else {
+        panic!("Should pick a single ROM");
+    };
+    assert_eq!(e.name, "archive.sfc");
+    assert_eq!(e.data.len(), 0x8000);
+
+    // Several ROMs, and a file without a ROM extension which is ignored
+    let archive = zip(&[
+        ("game (U).sfc", &rom(0)),
+        ("game (E).smc", &rom(1)),
+        ("game.bin", &rom(0)),
+    ]);
+    let RomChoice::Ambiguous(entries) = choose_rom(&archive, "archive_many.zip").unwrap() else {
+        panic!("Should be ambiguous");
+    };
+    let mut names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, ["game (E).smc", "game (U).sfc"]);
+
+    // Archives with nothing that could be a ROM
+    assert!(matches!(
+        choose_rom(&zip(&[("tiny.sfc", &[0; 0x100])]), "tiny.zip"),
+        Err(ArchiveError::NoRom)
+    ));
+    assert!(matches!(
+        choose_rom(&rom(0), "game.sfc"),
+        Err(ArchiveError::NoRom)
+    ));
+}
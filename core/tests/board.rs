@@ -0,0 +1,213 @@
+use super_yane::{
+    Console,
+    cartridge::{Board, CoprocessorBoard},
+};
+
+mod common;
+use common::coprocessor_rom;
+
+/// Map mode, chipset (SA-1 with RAM and battery), ROM size and SRAM size
+const SA1_HEADER: [u8; 4] = [0x23, 0x35, 0x0B, 0x03];
+/// Map mode, chipset (Super FX with RAM), ROM size and SRAM size
+const SUPER_FX_HEADER: [u8; 4] = [0x20, 0x14, 0x0A, 0x00];
+/// SNES code that starts the SA-1 at $8100
+const START_SA1: [u8; 16] = [
+    0x78, // SEI
+    0xA9, 0x00, 0x8D, 0x03, 0x22, // Set the SA-1's reset vector to $8100
+    0xA9, 0x81, 0x8D, 0x04, 0x22, // LDA #$81, STA $2204
+    0x9C, 0x00, 0x22, // Start the SA-1
+    0x80, 0xFE, // BRA -2
+];
+
+#[test]
+fn test_board_for_cartridge() {
+    let c = Console::with_cartridge(&coprocessor_rom(
+        0x8000,
+        "NO BOARD",
+        [0x20, 0x00, 0x08, 0x00],
+        &[],
+        &[],
+    ))
+    .unwrap();
+    assert!(c.board().is_none());
+    let c =
+        Console::with_cartridge(&coprocessor_rom(0x10000, "SA-1", SA1_HEADER, &[], &[])).unwrap();
+    assert!(matches!(c.board(), Some(Board::Sa1(_))));
+}
+
+#[test]
+fn test_board_intercepts_accesses() {
+    let snes_code = [
+        0x78, // SEI
+        0xA9, 0x01, 0x8D, 0x29, 0x22, // Enable writing to the first page of I-RAM
+        0xA9, 0x42, 0x8D, 0x00, 0x30, // STA $3000
+        0xA9, 0x00, // LDA #0
+        0xAD, 0x00, 0x30, // LDA $3000
+        0x8D, 0x10, 0x00, // Store it in WRAM, which the board doesn't see
+        0xAD, 0x0E, 0x23, // Read the SA-1's version
+        0x8D, 0x11, 0x00, // STA $0011
+        0x80, 0xFE, // BRA -2
+    ];
+    let mut c = Console::with_cartridge(&coprocessor_rom(
+        0x10000,
+        "SA-1",
+        SA1_HEADER,
+        &snes_code,
+        &[],
+    ))
+    .unwrap();
+    c.advance_instructions(20);
+    let Some(Board::Sa1(sa1)) = c.board() else {
+        panic!("Board should be an SA-1");
+    };
+    assert_eq!(sa1.iram()[0], 0x42);
+    assert_eq!(c.ram()[0x10], 0x42);
+    assert_eq!(c.ram()[0x11], 0x23);
+}
+
+#[test]
+fn test_board_access_speed() {
+    /// Master clocks taken to run a loop in bank $80, with or without FastROM
+    fn clocks(header: [u8; 4], fast_rom: bool) -> u64 {
+        let memsel = u8::from(fast_rom);
+        let snes_code = [
+            0x78, // SEI
+            0xA9, memsel, 0x8D, 0x0D, 0x42, // Set MEMSEL
+            0x5C, 0x0A, 0x80, 0x80, // JML $80:800A
+            0xEA, 0xEA, 0xEA, // NOP
+            0x80, 0xFB, // BRA -5
+        ];
+        let mut c =
+            Console::with_cartridge(&coprocessor_rom(0x10000, "SPEED", header, &snes_code, &[]))
+                .unwrap();
+        c.advance_instructions(100);
+        *c.total_master_clocks()
+    }
+    let plain_header = [0x30, 0x00, 0x08, 0x00];
+    assert!(clocks(plain_header, true) < clocks(plain_header, false));
+    // The board's ROM takes as long to read as the cartridge's would
+    assert_eq!(clocks(SA1_HEADER, true), clocks(plain_header, true));
+    assert_eq!(clocks(SA1_HEADER, false), clocks(plain_header, false));
+}
+
+#[test]
+fn test_board_advance() {
+    let gsu_code = [
+        0xD1, // INC R1
+        0x05, 0xFD, // BRA -3
+        0x01, // NOP, which runs before the branch is taken
+    ];
+    let mut c = Console::with_cartridge(&coprocessor_rom(
+        0x10000,
+        "SUPER FX",
+        SUPER_FX_HEADER,
+        &[0x78, 0x80, 0xFE],
+        &gsu_code,
+    ))
+    .unwrap();
+    // Start the GSU at $8100
+    let mut cartridge = c.cartridge().clone();
+    let board = c.board_mut().as_mut().unwrap();
+    board.write_byte(&mut cartridge, 0x00_301E, 0x00);
+    board.write_byte(&mut cartridge, 0x00_301F, 0x81);
+    let mut copy = board.clone();
+    let start = *c.total_master_clocks();
+    c.advance_instructions(100);
+    // The board is advanced by exactly as many master clocks as the console, however they are split up
+    let mut clocks = c.total_master_clocks() - start;
+    let mut step = 1;
+    while clocks > 0 {
+        let n = step.min(clocks);
+        copy.advance(&mut cartridge, n as u32);
+        clocks -= n;
+        step += 3;
+    }
+    let (Some(Board::SuperFx(gsu)), Board::SuperFx(copy)) = (c.board(), &copy) else {
+        panic!("Board should be a Super FX");
+    };
+    assert!(gsu.is_running());
+    assert!(gsu.registers()[1] > 0);
+    assert_eq!(gsu.registers(), copy.registers());
+}
+
+/// Build a Super FX ROM whose GSU code stops straight away, raising an IRQ on the SNES CPU
+fn super_fx_irq_rom(enable_irqs: bool) -> Vec<u8> {
+    let cli = if enable_irqs { 0x58 } else { 0xEA };
+    let snes_code = [
+        0x78, // SEI
+        0xA9, 0x08, 0x8D, 0x3A, 0x30, // Give the GSU access to RAM
+        0xA9, 0x00, 0x8D, 0x1E, 0x30, // Start the GSU at $8100
+        0xA9, 0x81, 0x8D, 0x1F, 0x30, // LDA #$81, STA $301F
+        cli,  // CLI, or NOP to leave IRQs disabled
+        0x80, 0xFE, // BRA -2
+    ];
+    let mut rom = coprocessor_rom(
+        0x10000,
+        "SUPER FX",
+        SUPER_FX_HEADER,
+        &snes_code,
+        &[0x00, 0x01],
+    );
+    let irq_handler = [
+        0xA9, 0x55, 0x8D, 0x10, 0x00, // LDA #$55, STA $0010
+        0xAD, 0x31, 0x30, // Acknowledge the IRQ
+        0x40, // RTI
+    ];
+    rom[0x40..0x40 + irq_handler.len()].copy_from_slice(&irq_handler);
+    rom[0x7FFE..0x8000].copy_from_slice(&[0x40, 0x80]);
+    rom
+}
+
+#[test]
+fn test_board_irq() {
+    let mut c = Console::with_cartridge(&super_fx_irq_rom(true)).unwrap();
+    c.advance_instructions(20);
+    assert_eq!(c.ram()[0x10], 0x55);
+    let Some(board) = c.board() else {
+        panic!("Console should have a board");
+    };
+    assert!(!board.irq());
+
+    // The board keeps asserting the IRQ until the CPU handles it
+    let mut c = Console::with_cartridge(&super_fx_irq_rom(false)).unwrap();
+    c.advance_instructions(20);
+    assert_eq!(c.ram()[0x10], 0x00);
+    assert!(c.board().as_ref().is_some_and(|b| b.irq()));
+}
+
+#[test]
+fn test_board_savestate() {
+    let sa1_code = [
+        0x78, // SEI
+        0xA9, 0xFF, 0x8D, 0x2A, 0x22, // Enable writing to I-RAM
+        0xEE, 0x00, 0x30, // INC $3000
+        0x80, 0xFB, // BRA -5
+    ];
+    let mut c = Console::with_cartridge(&coprocessor_rom(
+        0x10000, "SA-1", SA1_HEADER, &START_SA1, &sa1_code,
+    ))
+    .unwrap();
+    c.advance_instructions(50);
+    // A console is too big to deserialize on the test thread's stack
+    std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(move || {
+            let state = serde_brief::to_vec(&c).unwrap();
+            let mut restored: Console = serde_brief::from_slice(&state).unwrap();
+            let iram = |c: &Console| match c.board() {
+                Some(Board::Sa1(sa1)) => sa1.iram().to_vec(),
+                _ => panic!("Board should be an SA-1"),
+            };
+            let counter = iram(&c)[0];
+            assert_ne!(counter, 0);
+            assert_eq!(iram(&c), iram(&restored));
+            // Both carry on the same way
+            c.advance_instructions(50);
+            restored.advance_instructions(50);
+            assert_ne!(iram(&c)[0], counter);
+            assert_eq!(iram(&c), iram(&restored));
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
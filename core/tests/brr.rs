@@ -0,0 +1,49 @@
+use super_yane::apu::brr::{
+    BrrSample, decode_block, decode_sample_directory, decode_sample_directory_entry,
+};
+
+#[test]
+fn test_brr_decode_block() {
+    // Shift 12, filter 0, every nibble is 1
+    let (header, samples) = decode_block(
+        &[0xC0, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11],
+        0,
+        0,
+    );
+    assert!(!header.end_flag);
+    assert_eq!(samples, [0x800; 16]);
+    // Shift 12, filter 0, every nibble is -1, end and loop
+    let (header, samples) = decode_block(
+        &[0xC3, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        0,
+        0,
+    );
+    assert!(header.end_flag && header.loop_flag);
+    assert_eq!(samples, [-0x800; 16]);
+}
+
+#[test]
+fn test_brr_sample_directory() {
+    let mut ram = vec![0u8; 0x10000];
+    // Sample directory at $0200, with the first two entries pointing to the same sample
+    [0x00, 0x03, 0x09, 0x03, 0x00, 0x03, 0x09, 0x03]
+        .iter()
+        .enumerate()
+        .for_each(|(i, b)| ram[0x200 + i] = *b);
+    ram[0x300..0x309].copy_from_slice(&[0xC0, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11]);
+    ram[0x309..0x312].copy_from_slice(&[0xC3, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+    let samples = decode_sample_directory(&ram, 0x200);
+    assert!(samples.iter().all(|s| s.index != 1));
+    let s: &BrrSample = samples.iter().find(|s| s.index == 0).unwrap();
+    assert_eq!(s.start_addr, 0x300);
+    assert_eq!(s.loop_addr, 0x309);
+    assert_eq!(s.samples.len(), 32);
+    assert_eq!(s.samples[0], 0x800);
+    assert_eq!(s.samples[16], -0x800);
+    assert_eq!(s.loop_point, Some(16));
+    // A single entry can be decoded without decoding the rest
+    let entry = decode_sample_directory_entry(&ram, 0x200, 1).unwrap();
+    assert_eq!(entry.index, 1);
+    assert_eq!(entry.samples, s.samples);
+}
@@ -0,0 +1,314 @@
+use super_yane::{
+    Cartridge, Console,
+    cartridge::{
+        CartridgeError, Chipset, Coprocessor, MemoryMap, Region, decode_jis_x_0201,
+        detect_memory_map, mirror_address,
+    },
+};
+
+/// Build a LoROM with a header at $7FB0
+fn lorom_with_header(header: &[(usize, u8)]) -> Vec<u8> {
+    let mut data = vec![0; 0x10000];
+    data[0x7FC0..0x7FD5].copy_from_slice(b"TEST ROM             ");
+    // Reset to an SEI at $8000
+    data[0x7FFD] = 0x80;
+    data[0x0000] = 0x78;
+    header
+        .iter()
+        .for_each(|(addr, value)| data[addr - 0xFFB0 + 0x7FB0] = *value);
+    data
+}
+
+/// Build a ROM of `len` bytes with a minimal header where `memory_map` would put it,
+/// whose reset vector points to an SEI at $8000
+fn rom_with_map(len: usize, memory_map: MemoryMap, map_mode: u8) -> Vec<u8> {
+    let mut data = vec![0; len];
+    let index = memory_map.header_index();
+    data[index..index + 8].copy_from_slice(b"TEST ROM");
+    data[index + 0x15] = map_mode;
+    // Consistent checksum, but not the right one
+    data[index + 0x1C..index + 0x20].copy_from_slice(&[0x34, 0x12, 0xCB, 0xED]);
+    data[index + 0x3C..index + 0x3E].copy_from_slice(&[0x00, 0x80]);
+    data[memory_map.transform_address(0x8000)] = 0x78;
+    data
+}
+
+#[test]
+fn test_memory_map_detection() {
+    let hirom = rom_with_map(0x40000, MemoryMap::HiRom, 0x21);
+    assert_eq!(
+        detect_memory_map(&hirom).map(|(m, _)| m),
+        Some(MemoryMap::HiRom)
+    );
+    let c = Cartridge::from_data(&hirom).unwrap();
+    assert_eq!(c.memory_map(), MemoryMap::HiRom);
+    assert_eq!(c.read_byte(0x00_8000), 0x78);
+
+    // Copier headers are removed
+    let with_copier_header = [vec![0; 512], hirom.clone()].concat();
+    let c = Cartridge::from_data(&with_copier_header).unwrap();
+    assert_eq!(c.memory_map(), MemoryMap::HiRom);
+    assert_eq!(c.data, hirom);
+
+    // The memory map can be forced
+    let c = Cartridge::from_data_with_map(&hirom, Some(MemoryMap::LoRom)).unwrap();
+    assert_eq!(c.memory_map(), MemoryMap::LoRom);
+
+    let exlorom = rom_with_map(0x60_0000, MemoryMap::ExLoRom, 0x32);
+    let c = Cartridge::from_data(&exlorom).unwrap();
+    assert_eq!(c.memory_map(), MemoryMap::ExLoRom);
+    assert_eq!(c.read_byte(0x00_8000), 0x78);
+    assert_eq!(c.title(), "TEST ROM");
+
+    let exhirom = rom_with_map(0x60_0000, MemoryMap::ExHiRom, 0x35);
+    let c = Cartridge::from_data(&exhirom).unwrap();
+    assert_eq!(c.memory_map(), MemoryMap::ExHiRom);
+    assert_eq!(c.read_byte(0x00_8000), 0x78);
+
+    // Too small to have a header
+    assert_eq!(detect_memory_map(&[0; 0x100]), None);
+}
+
+#[test]
+fn test_cartridge_errors() {
+    assert_eq!(
+        Cartridge::from_data(&[]).err(),
+        Some(CartridgeError::TooSmall(0))
+    );
+    // The copier header doesn't count towards the size
+    assert_eq!(
+        Cartridge::from_data(&[0; 0x200 + 0x400]).err(),
+        Some(CartridgeError::TooSmall(0x400))
+    );
+    // Detecting the memory map of something too small to have a header doesn't panic
+    assert_eq!(detect_memory_map(&[]), None);
+    assert_eq!(detect_memory_map(&[0; 0x7FFF]), None);
+    assert_eq!(
+        Cartridge::from_data(&[0xFF; 0x10000]).err(),
+        Some(CartridgeError::UnknownMapping)
+    );
+    assert_eq!(
+        Cartridge::from_data(&lorom_with_header(&[(0xFFD8, 0xFF)])).err(),
+        Some(CartridgeError::BadSramSize(0xFF))
+    );
+    // Forcing the memory map skips detection, but not the other checks
+    let mut unknown = vec![0xFF; 0x10000];
+    unknown[0xFFD8] = 0x00;
+    assert_eq!(
+        Cartridge::from_data(&unknown).err(),
+        Some(CartridgeError::UnknownMapping)
+    );
+    assert!(Cartridge::from_data_with_map(&unknown, Some(MemoryMap::HiRom)).is_ok());
+    assert_eq!(
+        Cartridge::from_data_with_map(&[0; 0x100], Some(MemoryMap::HiRom)).err(),
+        Some(CartridgeError::TooSmall(0x100))
+    );
+}
+
+#[test]
+fn test_cartridge_header() {
+    let c = Cartridge::from_data(include_bytes!("./roms/SPC700ADC.sfc")).unwrap();
+    let h = c.header();
+    assert_eq!(h.title, "SPC700 CPU TEST ADC");
+    assert_eq!(c.title(), h.title);
+    assert_eq!(h.memory_map(), Some(MemoryMap::LoRom));
+    assert!(!h.fast_rom);
+    assert_eq!(h.chipset.coprocessor, None);
+    assert_eq!(h.rom_size, 2048);
+    assert_eq!(h.sram_size, 0);
+    assert_eq!(h.region, Region::Japan);
+    assert_eq!(h.extended, None);
+
+    let c = Cartridge::from_data(&lorom_with_header(&[
+        // FastROM HiROM
+        (0xFFD5, 0x31),
+        // SA-1 with RAM and battery
+        (0xFFD6, 0x35),
+        (0xFFD7, 0x0C),
+        (0xFFD8, 0x03),
+        (0xFFD9, 0x01),
+        (0xFFDA, 0x33),
+        (0xFFDB, 0x02),
+        (0xFFDC, 0x34),
+        (0xFFDD, 0x12),
+        (0xFFDE, 0xCB),
+        (0xFFDF, 0xED),
+        (0xFFB0, b'0'),
+        (0xFFB1, b'1'),
+        (0xFFB2, b'A'),
+        (0xFFB3, b'B'),
+        (0xFFB4, b'C'),
+        (0xFFB5, b'E'),
+        (0xFFBD, 0x05),
+    ]))
+    .unwrap();
+    let h = c.header();
+    assert_eq!(h.title, "TEST ROM");
+    assert_eq!(h.memory_map(), Some(MemoryMap::HiRom));
+    assert!(h.fast_rom);
+    assert_eq!(
+        h.chipset,
+        Chipset {
+            value: 0x35,
+            has_ram: true,
+            has_battery: true,
+            coprocessor: Some(Coprocessor::Sa1)
+        }
+    );
+    assert_eq!(h.rom_size, 4 * 1024 * 1024);
+    assert_eq!(h.sram_size, 8 * 1024);
+    assert_eq!(h.region, Region::NorthAmerica);
+    assert_eq!(h.version, 2);
+    assert_eq!(h.checksum, 0xEDCB);
+    assert_eq!(h.checksum_complement, 0x1234);
+    assert!(h.checksum_is_consistent());
+    let e = h.extended.unwrap();
+    assert_eq!(e.maker_code, "01");
+    assert_eq!(e.game_code, "ABCE");
+    assert_eq!(e.expansion_ram_size, 32 * 1024);
+
+    // Custom chips are told apart by the subtype
+    let c = Cartridge::from_data(&lorom_with_header(&[
+        (0xFFD6, 0xF3),
+        (0xFFDA, 0x33),
+        (0xFFBF, 0x10),
+    ]))
+    .unwrap();
+    assert_eq!(c.header().chipset.coprocessor, Some(Coprocessor::Cx4));
+
+    // The Super FX 2 uses chipset $1A for RAM with a battery
+    let chipset = Chipset::from_bytes(0x1A, 0);
+    assert_eq!(chipset.coprocessor, Some(Coprocessor::SuperFx));
+    assert!(chipset.has_ram && chipset.has_battery);
+    // Chipset $x9 has a real time clock as well, and keeps its RAM with a battery
+    let chipset = Chipset::from_bytes(0xF9, 0x00);
+    assert_eq!(chipset.coprocessor, Some(Coprocessor::Spc7110));
+    assert!(chipset.has_ram && chipset.has_battery);
+}
+
+#[test]
+fn test_sram() {
+    // 2KB of SRAM
+    let mut c = Cartridge::from_data(&lorom_with_header(&[(0xFFD8, 0x01)])).unwrap();
+    assert_eq!(c.sram().len(), 0x800);
+    assert!(!c.take_sram_written());
+    c.write_byte(0x70_0001, 0x12);
+    assert_eq!(c.sram()[1], 0x12);
+    assert!(c.take_sram_written());
+    assert!(!c.take_sram_written());
+    // Writes to ROM don't count
+    c.write_byte(0x00_8000, 0x34);
+    assert!(!c.take_sram_written());
+
+    // Loading SRAM of the wrong size pads it with zeros
+    c.load_sram(&[0xAB; 0x10]);
+    assert_eq!(c.sram().len(), 0x800);
+    assert_eq!(c.sram()[0x0F], 0xAB);
+    assert_eq!(c.sram()[0x10], 0x00);
+    assert!(!c.take_sram_written());
+
+    let mut console = Console::with_cartridge(&lorom_with_header(&[(0xFFD8, 0x01)])).unwrap();
+    let sram: Vec<u8> = (0..0x800).map(|i| i as u8).collect();
+    console.load_sram(&sram);
+    assert_eq!(console.sram(), sram.as_slice());
+    assert!(!console.take_sram_written());
+}
+
+/// Build a ROM of `len` bytes where the first byte of each 32KB chunk is the chunk's index
+fn rom_with_chunks(len: usize, memory_map: MemoryMap, sram_size: u8) -> Cartridge {
+    let mut data = vec![0; len];
+    (0..len / 0x8000).for_each(|i| data[i * 0x8000] = i as u8);
+    data[memory_map.transform_address(0xFFD8)] = sram_size;
+    Cartridge::from_data_with_map(&data, Some(memory_map)).unwrap()
+}
+
+#[test]
+fn test_mirror_address() {
+    // Powers of two
+    assert_eq!(mirror_address(0x12_3456, 0x10_0000), 0x02_3456);
+    assert_eq!(mirror_address(0x0F_FFFF, 0x10_0000), 0x0F_FFFF);
+    // 1.5MB is 1MB followed by 512KB mirrored twice
+    assert_eq!(mirror_address(0x10_0000, 0x18_0000), 0x10_0000);
+    assert_eq!(mirror_address(0x18_0000, 0x18_0000), 0x10_0000);
+    assert_eq!(mirror_address(0x1F_FFFF, 0x18_0000), 0x17_FFFF);
+    // 2.5MB is 2MB followed by 512KB mirrored four times
+    assert_eq!(mirror_address(0x28_0000, 0x28_0000), 0x20_0000);
+    assert_eq!(mirror_address(0x3F_0000, 0x28_0000), 0x27_0000);
+    // 3MB is 2MB followed by 1MB mirrored twice
+    assert_eq!(mirror_address(0x30_0000, 0x30_0000), 0x20_0000);
+    assert_eq!(mirror_address(0x3F_8000, 0x30_0000), 0x2F_8000);
+    // Small ROMs mirror over the whole address space
+    assert_eq!(mirror_address(0x3F_8000, 0x8000), 0);
+    assert_eq!(mirror_address(0x1234, 0), 0);
+}
+
+#[test]
+fn test_rom_mirroring() {
+    // 1.5MB HiROM
+    let c = rom_with_chunks(0x18_0000, MemoryMap::HiRom, 0);
+    assert_eq!(c.read_byte(0xC8_0000), 0x10);
+    assert_eq!(c.read_byte(0xD0_0000), 0x20);
+    assert_eq!(c.read_byte(0xD8_0000), 0x20);
+    assert_eq!(c.read_byte(0xDF_8000), 0x2F);
+    // Banks $40-$7D mirror banks $C0-$FD
+    assert_eq!(c.read_byte(0x58_0000), 0x20);
+    // 2.5MB LoROM
+    let c = rom_with_chunks(0x28_0000, MemoryMap::LoRom, 0);
+    assert_eq!(c.read_byte(0x4F_8000), 0x4F);
+    assert_eq!(c.read_byte(0x50_8000), 0x40);
+    assert_eq!(c.read_byte(0x58_8000), 0x48);
+    assert_eq!(c.read_byte(0x60_8000), 0x40);
+    assert_eq!(c.read_byte(0xDF_8000), 0x4F);
+    // 3MB LoROM
+    let c = rom_with_chunks(0x30_0000, MemoryMap::LoRom, 0);
+    assert_eq!(c.read_byte(0x60_8000), 0x40);
+    assert_eq!(c.read_byte(0x7D_8000), 0x5D);
+    assert_eq!(c.read_byte(0xFF_8000), 0x5F);
+    // 32KB LoROM
+    let c = rom_with_chunks(0x8000, MemoryMap::LoRom, 0);
+    assert_eq!(c.read_byte(0x80_8000), 0x00);
+    assert_eq!(c.read_byte(0x3F_FFD8), 0x00);
+}
+
+#[test]
+fn test_sram_mirroring() {
+    // 2KB HiROM SRAM, which is mirrored in every bank
+    let mut c = rom_with_chunks(0x10_0000, MemoryMap::HiRom, 0x01);
+    c.write_byte(0x30_6001, 0x12);
+    assert_eq!(c.sram()[1], 0x12);
+    assert_eq!(c.read_byte(0x30_6801), 0x12);
+    assert_eq!(c.read_byte(0x3F_7001), 0x12);
+    assert_eq!(c.read_byte(0xB0_6001), 0x12);
+    c.write_byte(0xBF_7802, 0x34);
+    assert_eq!(c.sram()[2], 0x34);
+    // Outside of SRAM
+    assert!(!c.is_sram_address(0x20_6000));
+    assert!(!c.is_sram_address(0x30_8000));
+
+    // 64KB LoROM SRAM, which is split across banks $70 and $71
+    let mut c = rom_with_chunks(0x10_0000, MemoryMap::LoRom, 0x06);
+    c.write_byte(0x70_0001, 0x12);
+    c.write_byte(0x71_0001, 0x34);
+    assert_eq!(c.sram()[0x0001], 0x12);
+    assert_eq!(c.sram()[0x8001], 0x34);
+    assert_eq!(c.read_byte(0x72_0001), 0x12);
+    assert_eq!(c.read_byte(0xF1_0001), 0x34);
+
+    // No SRAM
+    let mut c = rom_with_chunks(0x10_0000, MemoryMap::LoRom, 0x00);
+    assert_eq!(c.sram().len(), 0);
+    assert!(!c.is_sram_address(0x70_0000));
+    c.write_byte(0x70_0000, 0x12);
+    assert!(!c.take_sram_written());
+    // Which leaves ROM in its place
+    assert_eq!(c.read_byte(0x70_0000), 0x10);
+}
+
+#[test]
+fn test_jis_x_0201() {
+    assert_eq!(decode_jis_x_0201(b"MARIO  "), "MARIO");
+    assert_eq!(
+        decode_jis_x_0201(&[0xBD, 0xB0, 0xCA, 0xDF, 0xB0, 0x20, 0x20]),
+        "ｽｰﾊﾟｰ"
+    );
+}
@@ -7,7 +7,7 @@ macro_rules! rom_test {
         paste! {
         #[test]
         fn [<test_$name>] () {
-            let mut c = Console::with_cartridge(include_bytes!($file));
+            let mut c = Console::with_cartridge(include_bytes!($file)).unwrap();
             (0..($num_frames)).for_each(|_| {
                 loop {
                     let v = c.ppu().is_in_vblank();
@@ -50,3 +50,23 @@ macro_rules! spc_rom_test {
         rom_test! {$name, concat!("./roms/SPC700", stringify!([<$name:upper>]), ".sfc"), $num_frame}
     };
 }
+
+/// Build a LoROM-style ROM of `len` bytes for testing a coprocessor, which runs `snes_code` at $00:8000 and has `coprocessor_code` at $00:8100.
+/// `header` is the map mode, chipset, ROM size and SRAM size.
+#[allow(dead_code)]
+pub fn coprocessor_rom(
+    len: usize,
+    title: &str,
+    header: [u8; 4],
+    snes_code: &[u8],
+    coprocessor_code: &[u8],
+) -> Vec<u8> {
+    let mut data = vec![0; len];
+    data[0x7FC0..0x7FD5].copy_from_slice(format!("{:21}", title).as_bytes());
+    data[0x7FD5..0x7FD9].copy_from_slice(&header);
+    data[0x7FDC..0x7FE0].copy_from_slice(&[0x34, 0x12, 0xCB, 0xED]);
+    data[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    data[..snes_code.len()].copy_from_slice(snes_code);
+    data[0x100..0x100 + coprocessor_code.len()].copy_from_slice(coprocessor_code);
+    data
+}
@@ -0,0 +1,141 @@
+use super_yane::apu::{Apu, ApuMemory, Dsp, OutputFilter, STEPS_PER_SAMPLE};
+
+/// Set up an APU with voice 0 and voice 1 playing a looping sample at full volume
+fn playing_apu() -> Apu {
+    let mut apu = Apu::default();
+    let r = &mut apu.rest;
+    // Sample directory at $0200, with a single looping block at $0300
+    r.ram[0x200..0x204].copy_from_slice(&[0x00, 0x03, 0x00, 0x03]);
+    r.ram[0x300..0x309].copy_from_slice(&[0xB3, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17, 0x17]);
+    [(0x0C, 0x7F), (0x1C, 0x7F), (0x5D, 0x02), (0x6C, 0x20)]
+        .into_iter()
+        .for_each(|(reg, value)| r.dsp.write(reg, value));
+    (0..2).for_each(|v| {
+        [(0x00, 0x7F), (0x01, 0x7F), (0x03, 0x10), (0x07, 0x7F)]
+            .into_iter()
+            .for_each(|(reg, value)| r.dsp.write(0x10 * v + reg, value));
+    });
+    r.dsp.write(0x4C, 0x03);
+    apu
+}
+
+fn run(apu: &mut Apu, samples: usize) {
+    let r = &mut apu.rest;
+    (0..samples).for_each(|_| r.dsp.generate_sample(&mut r.ram[..]));
+}
+
+#[test]
+fn test_voice_mute_and_solo() {
+    let mut normal = playing_apu();
+    let mut muted = playing_apu();
+    muted.dsp_mut().voice_muted = [true; 8];
+    muted.dsp_mut().capture_voices = true;
+    run(&mut normal, 64);
+    run(&mut muted, 64);
+    // The output is silent, but the voices keep running
+    assert!(normal.sample_queue().iter().any(|s| *s != 0.0));
+    assert!(muted.sample_queue().iter().all(|s| *s == 0.0));
+    (0..0x80).for_each(|reg| assert_eq!(normal.dsp().read(reg), muted.dsp().read(reg)));
+    // Each voice is still captured on its own
+    let captured = muted.voice_sample_queues();
+    assert_eq!(captured[0].len(), 64);
+    assert!(captured[0].iter().any(|s| *s != 0.0));
+    assert!(captured[2].iter().all(|s| *s == 0.0));
+
+    // Soloing a voice silences the others
+    let mut solo = playing_apu();
+    solo.dsp_mut().voice_solo[1] = true;
+    assert!(!solo.dsp().is_voice_audible(0));
+    assert!(solo.dsp().is_voice_audible(1));
+    solo.dsp_mut().voice_muted[1] = true;
+    assert!(!solo.dsp().is_voice_audible(1));
+}
+
+#[test]
+fn test_output_filter() {
+    let mut raw = playing_apu();
+    let mut filtered = playing_apu();
+    filtered.dsp_mut().output_filter_enabled = true;
+    run(&mut raw, 256);
+    run(&mut filtered, 256);
+    let raw = raw.sample_queue();
+    let filtered = filtered.sample_queue();
+    // The filter is off by default, and softens the output when on
+    assert_ne!(raw, filtered);
+    let diff = |q: &std::collections::VecDeque<f32>| {
+        q.iter()
+            .zip(q.iter().skip(1))
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f32>()
+    };
+    assert!(diff(&filtered) < diff(&raw));
+
+    let mut f = OutputFilter::default();
+    // DC offsets are removed
+    let last = (0..4096).map(|_| f.apply(0.5)).last().unwrap();
+    assert!(last.abs() < 0.01);
+}
+
+#[test]
+fn test_dsp_steps() {
+    let mut apu = playing_apu();
+    run(&mut apu, 40);
+    apu.sample_queue();
+    let r = &mut apu.rest;
+    assert_eq!(r.dsp.step_index(), 0);
+    // Key on voice 7 with the fastest attack
+    r.dsp.write(0x75, 0x8F);
+    r.dsp.write(0x4C, 0x80);
+    let envx = |r: &ApuMemory| r.dsp.read(0x78);
+    // Voices are only keyed on near the end of the sample
+    (0..STEPS_PER_SAMPLE).for_each(|_| r.dsp.step(&mut r.ram[..]));
+    assert_eq!(envx(r), 0);
+    // Voice 7 clocks its envelope partway through the next sample, but ENVX is updated a few steps later
+    (0..25).for_each(|_| r.dsp.step(&mut r.ram[..]));
+    assert_eq!(envx(r), 0);
+    r.dsp.step(&mut r.ram[..]);
+    assert_ne!(envx(r), 0);
+    (26..STEPS_PER_SAMPLE).for_each(|_| r.dsp.step(&mut r.ram[..]));
+    // One sample is output for every full run of the steps
+    assert_eq!(apu.sample_queue().len(), 2);
+    let r = &mut apu.rest;
+    (0..4 * STEPS_PER_SAMPLE).for_each(|_| r.dsp.step(&mut r.ram[..]));
+    assert_eq!(apu.sample_queue().len(), 4);
+}
+
+#[test]
+fn test_voice_scopes() {
+    let mut apu = playing_apu();
+    run(&mut apu, 64);
+    // Nothing is kept by default
+    assert!(apu.dsp().voice_scopes().iter().all(|s| s.is_empty()));
+    apu.dsp_mut().scope_len = 32;
+    run(&mut apu, 64);
+    let scopes = apu.dsp().voice_scopes();
+    assert!(scopes.iter().all(|s| s.len() == 32));
+    assert_eq!(*scopes[0].back().unwrap(), apu.dsp().voices[0].output);
+    assert!(scopes[0].iter().any(|s| *s != 0));
+    assert!(scopes[2].iter().all(|s| *s == 0));
+}
+
+#[test]
+fn test_registers_rebuilt_from_state() {
+    // A DSP loaded from a savestate made before the registers were stored
+    let mut dsp = playing_apu().dsp().clone();
+    let written: Vec<u8> = (0..0x80).map(|reg| dsp.read(reg)).collect();
+    let mut old = Dsp::default();
+    old.volume = dsp.volume;
+    old.voices = dsp.voices.clone();
+    old.sample_dir = dsp.sample_dir;
+    old.echo_enabled = dsp.echo_enabled;
+    // Registers are read back from the state, except for KON, and GAIN once the voice is keyed on
+    (0..0x80)
+        .filter(|reg| *reg != 0x4C && reg & 0x0F != 0x07)
+        .for_each(|reg| assert_eq!(old.read(reg), written[reg], "register {reg:02X}"));
+    // Writing a register keeps the others
+    old.write(0x2C, 0x12);
+    dsp.write(0x2C, 0x12);
+    (0..0x80)
+        .filter(|reg| *reg != 0x4C && reg & 0x0F != 0x07)
+        .for_each(|reg| assert_eq!(old.read(reg), dsp.read(reg), "register {reg:02X}"));
+}
@@ -0,0 +1,42 @@
+use super_yane::apu::{Apu, pitch_to_note};
+
+#[test]
+fn test_midi_recording() {
+    let mut apu = Apu::default();
+    apu.start_midi_recording();
+    let r = &mut apu.rest;
+    // Voice 1 plays sample 3 an octave up, at volume 0x40
+    [
+        (0x10, 0x40),
+        (0x11, 0x40),
+        (0x12, 0x00),
+        (0x13, 0x20),
+        (0x14, 0x03),
+    ]
+    .into_iter()
+    .for_each(|(reg, value)| r.dsp.write(reg, value));
+    r.dsp.write(0x4C, 0x02);
+    // One second later, release it
+    (0..32_000).for_each(|_| r.dsp.generate_sample(&mut r.ram[..]));
+    r.dsp.write(0x5C, 0x02);
+
+    let smf = apu.stop_midi_recording().unwrap();
+    assert!(apu.stop_midi_recording().is_none());
+    assert_eq!(&smf[0..4], b"MThd");
+    assert_eq!(&smf[14..18], b"MTrk");
+    let track = &smf[22..];
+    let find = |message: &[u8]| track.windows(message.len()).any(|w| w == message);
+    // Program change, volume, note on, then note off after 960 ticks
+    assert!(find(&[0xC1, 0x03]));
+    assert!(find(&[0xB1, 0x07, 0x40]));
+    assert!(find(&[0x00, 0x91, 72, 0x7F]));
+    assert!(find(&[0x87, 0x40, 0x81, 72, 0x00]));
+    assert!(track.ends_with(&[0x00, 0xFF, 0x2F, 0x00]));
+}
+
+#[test]
+fn test_pitch_to_note() {
+    assert_eq!(pitch_to_note(0x1000), 60.0);
+    assert_eq!(pitch_to_note(0x2000), 72.0);
+    assert_eq!(pitch_to_note(0x0800), 48.0);
+}
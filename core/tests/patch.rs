@@ -0,0 +1,199 @@
+use super_yane::patch::{MAX_TARGET_SIZE, PatchError, PatchFormat, apply_patch, crc32};
+
+/// Encode a number the way BPS and UPS do
+fn number(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        let b = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(b | 0x80);
+            return bytes;
+        }
+        bytes.push(b);
+        value -= 1;
+    }
+}
+
+/// Add the checksums to the end of a BPS or UPS patch
+fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+}
+
+#[test]
+fn test_ips() {
+    let rom = [0u8; 8];
+    let patch = [
+        b"PATCH".as_slice(),
+        // Write 2 bytes at 1
+        &[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB],
+        // Write 4 0xCCs at 6, past the end of the ROM
+        &[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC],
+        b"EOF",
+    ]
+    .concat();
+    assert_eq!(PatchFormat::from_bytes(&patch), Some(PatchFormat::Ips));
+    assert_eq!(
+        apply_patch(&rom, &patch).unwrap(),
+        [0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC]
+    );
+    // Truncate
+    let truncated = [patch.as_slice(), &[0x00, 0x00, 0x03]].concat();
+    assert_eq!(apply_patch(&rom, &truncated).unwrap(), [0x00, 0xAA, 0xBB]);
+    // Missing the EOF marker
+    assert_eq!(
+        apply_patch(&rom, &patch[..patch.len() - 3]),
+        Err(PatchError::Truncated)
+    );
+}
+
+#[test]
+fn test_bps() {
+    let source: Vec<u8> = (0..16).collect();
+    let target: Vec<u8> = [
+        &source[0..4],
+        &[0xAA, 0xBB, 0xAA, 0xBB, 0xAA, 0xBB],
+        &source[8..12],
+    ]
+    .concat();
+    let patch = [
+        b"BPS1".as_slice(),
+        &number(source.len()),
+        &number(target.len()),
+        // Metadata
+        &number(2),
+        b"{}",
+        // Source read 4
+        &number(3 << 2),
+        // Target read 2
+        &number((1 << 2) | 1),
+        &[0xAA, 0xBB],
+        // Target copy 4 from 4, which overlaps with what is being written
+        &number((3 << 2) | 3),
+        &number(4 << 1),
+        // Source copy 4 from 8
+        &number((3 << 2) | 2),
+        &number(8 << 1),
+    ]
+    .concat();
+    let patch = with_footer(patch, &source, &target);
+    assert_eq!(PatchFormat::from_bytes(&patch), Some(PatchFormat::Bps));
+    assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+
+    // Wrong ROM
+    let mut wrong = source.clone();
+    wrong[0] = 0xFF;
+    assert_eq!(
+        apply_patch(&wrong, &patch),
+        Err(PatchError::SourceChecksum(crc32(&source), crc32(&wrong)))
+    );
+    assert_eq!(
+        apply_patch(&source[1..], &patch),
+        Err(PatchError::SourceSize(16, 15))
+    );
+    // Corrupt patch
+    let mut corrupt = patch.clone();
+    corrupt[12] ^= 0xFF;
+    assert!(matches!(
+        apply_patch(&source, &corrupt),
+        Err(PatchError::PatchChecksum(_, _))
+    ));
+}
+
+#[test]
+fn test_ups() {
+    let source: Vec<u8> = (0..8).collect();
+    let mut target = source.clone();
+    target[2] = 0xAA;
+    target[3] = 0xBB;
+    target.extend_from_slice(&[0x00, 0xCC]);
+    let patch = [
+        b"UPS1".as_slice(),
+        &number(source.len()),
+        &number(target.len()),
+        // Skip 2, then XOR 2 bytes
+        &number(2),
+        &[0x02 ^ 0xAA, 0x03 ^ 0xBB, 0x00],
+        // Skip 4 to 9, past the end of the ROM
+        &number(4),
+        &[0xCC, 0x00],
+    ]
+    .concat();
+    let patch = with_footer(patch, &source, &target);
+    assert_eq!(PatchFormat::from_bytes(&patch), Some(PatchFormat::Ups));
+    assert_eq!(apply_patch(&source, &patch).unwrap(), target);
+    assert_eq!(
+        apply_patch(&target, &patch),
+        Err(PatchError::SourceSize(8, 10))
+    );
+    assert_eq!(
+        apply_patch(&source, b"NOT A PATCH"),
+        Err(PatchError::UnknownFormat)
+    );
+}
+
+#[test]
+fn test_malformed_patches() {
+    let source: Vec<u8> = (0..8).collect();
+    let bps = |body: &[u8]| with_footer([b"BPS1".as_slice(), body].concat(), &source, &source);
+    let ups = |body: &[u8]| with_footer([b"UPS1".as_slice(), body].concat(), &source, &source);
+    // A number too large to fit
+    assert_eq!(
+        apply_patch(&source, &bps(&[0x7F; 11])),
+        Err(PatchError::Truncated)
+    );
+    assert_eq!(
+        apply_patch(&source, &ups(&[0x00; 11])),
+        Err(PatchError::Truncated)
+    );
+    // Metadata that would end past the end of memory
+    let metadata = [number(8), number(8), number(usize::MAX)].concat();
+    assert_eq!(
+        apply_patch(&source, &bps(&metadata)),
+        Err(PatchError::Truncated)
+    );
+    // A huge patched ROM
+    let huge = [number(8), number(MAX_TARGET_SIZE + 1), number(0)].concat();
+    assert_eq!(
+        apply_patch(&source, &bps(&huge)),
+        Err(PatchError::TargetSize(MAX_TARGET_SIZE + 1))
+    );
+    assert_eq!(
+        apply_patch(&source, &ups(&huge[..huge.len() - 1])),
+        Err(PatchError::TargetSize(MAX_TARGET_SIZE + 1))
+    );
+    // Writing more than the patched ROM's size
+    let long = [number(8), number(2), number(0), number(3 << 2)].concat();
+    assert_eq!(
+        apply_patch(&source, &bps(&long)),
+        Err(PatchError::OutOfBounds)
+    );
+    // Copies from offsets that overflow
+    let source_copy = [
+        number(8),
+        number(8),
+        number(0),
+        number(2),
+        number(usize::MAX & !1),
+    ]
+    .concat();
+    assert_eq!(
+        apply_patch(&source, &bps(&source_copy)),
+        Err(PatchError::OutOfBounds)
+    );
+    // Skipping past the end of memory
+    let skip = [number(8), number(8), number(usize::MAX), vec![0x00]].concat();
+    assert_eq!(
+        apply_patch(&source, &ups(&skip)),
+        Err(PatchError::OutOfBounds)
+    );
+}
@@ -0,0 +1,55 @@
+use super_yane::{
+    Console,
+    port_log::{PortAccess, PortSide},
+};
+
+#[test]
+fn test_port_log() {
+    let mut c = Console::with_cartridge(include_bytes!("./roms/SPC700ADC.sfc")).unwrap();
+    c.enable_port_log(0x100);
+    c.advance_instructions(20_000);
+    assert_eq!(c.port_log().unwrap().entries().len(), 0x100);
+
+    let mut c = Console::with_cartridge(include_bytes!("./roms/SPC700ADC.sfc")).unwrap();
+    c.enable_port_log(0x100000);
+    // The ROM starts uploading to the APU after it has set up the screen
+    c.advance_instructions(250_000);
+    let log = c.port_log().unwrap();
+    // The log is in order, and has accesses from both sides
+    let entries = log.entries();
+    assert!(
+        entries
+            .iter()
+            .zip(entries.iter().skip(1))
+            .all(|(a, b)| a.master_clock <= b.master_clock)
+    );
+    assert!(entries.iter().any(|e| e.side == PortSide::Cpu));
+    assert!(entries.iter().any(|e| e.side == PortSide::Apu));
+
+    // The first thing the APU does is write $AA to port 0 from the IPL ROM
+    let mut c = Console::with_cartridge(include_bytes!("./roms/SPC700ADC.sfc")).unwrap();
+    c.enable_port_log(0x100);
+    c.advance_instructions(1_000);
+    let first = c.port_log().unwrap().entries()[0];
+    let apu_first = c
+        .port_log()
+        .unwrap()
+        .entries()
+        .iter()
+        .find(|e| e.side == PortSide::Apu && e.access.write)
+        .copied()
+        .unwrap();
+    assert_eq!(
+        apu_first.access,
+        PortAccess {
+            write: true,
+            port: 0,
+            value: 0xAA
+        }
+    );
+    assert!(apu_first.apu_pc >= 0xFFC0);
+    assert!(first.master_clock <= *c.total_master_clocks());
+
+    c.disable_port_log();
+    assert!(c.port_log().is_none());
+}
@@ -0,0 +1,351 @@
+use super_yane::{
+    Cartridge, Console,
+    cartridge::{Board, CoprocessorBoard, Sa1},
+};
+
+mod common;
+use common::coprocessor_rom;
+
+/// Build an SA-1 ROM of `len` bytes with 8KB of BW-RAM, which runs `snes_code` at $00:8000 and has `sa1_code` at $00:8100
+fn sa1_rom(len: usize, snes_code: &[u8], sa1_code: &[u8]) -> Vec<u8> {
+    // Map mode, chipset (SA-1 with RAM and battery), ROM size and SRAM size
+    coprocessor_rom(
+        len,
+        "SA-1 TEST",
+        [0x23, 0x35, 0x0B, 0x03],
+        snes_code,
+        sa1_code,
+    )
+}
+
+/// SNES code that starts the SA-1 at $8100, with its IRQ vector at $8180
+const START_SA1: [u8; 26] = [
+    0x78, // SEI
+    0xA9, 0x00, 0x8D, 0x03, 0x22, // Set the SA-1's reset vector to $8100
+    0xA9, 0x81, 0x8D, 0x04, 0x22, // LDA #$81, STA $2204
+    0xA9, 0x80, 0x8D, 0x07, 0x22, // Set the SA-1's IRQ vector to $8180
+    0xA9, 0x81, 0x8D, 0x08, 0x22, // LDA #$81, STA $2208
+    0x9C, 0x00, 0x22, // Start the SA-1
+    0x80, 0xFE, // BRA -2
+];
+
+/// SA-1 code that writes `value` to `address`
+fn write(address: u16, value: u8) -> Vec<u8> {
+    let [low, high] = address.to_le_bytes();
+    vec![0xA9, value, 0x8D, low, high]
+}
+
+/// SA-1 code that copies the byte at `src` to `dest`
+fn copy(src: u16, dest: u16) -> Vec<u8> {
+    let ([src_low, src_high], [dest_low, dest_high]) = (src.to_le_bytes(), dest.to_le_bytes());
+    vec![0xAD, src_low, src_high, 0x8D, dest_low, dest_high]
+}
+
+/// Build a console that runs `sa1_code` on the SA-1 with writes to I-RAM enabled, and has `data` at $00:8200
+fn sa1_console(sa1_code: &[u8], data: &[u8]) -> Console {
+    let sa1_code = [
+        &[0x78], // SEI
+        write(0x222A, 0xFF).as_slice(),
+        sa1_code,
+        &[0x80, 0xFE], // BRA -2
+    ]
+    .concat();
+    let mut rom = sa1_rom(0x10000, &START_SA1, &sa1_code);
+    rom[0x200..0x200 + data.len()].copy_from_slice(data);
+    Console::with_cartridge(&rom).unwrap()
+}
+
+fn iram(c: &Console) -> &[u8] {
+    match c.board() {
+        Some(Board::Sa1(sa1)) => sa1.iram(),
+        _ => panic!("Board should be an SA-1"),
+    }
+}
+
+#[test]
+fn test_sa1_cpu() {
+    let snes_code = [
+        0x78, // SEI
+        0xA9, 0x00, 0x8D, 0x03, 0x22, // Set the SA-1's reset vector to $8100
+        0xA9, 0x81, 0x8D, 0x04, 0x22, // LDA #$81, STA $2204
+        0xA9, 0x80, 0x8D, 0x01, 0x22, // Enable IRQs from the SA-1
+        0x9C, 0x00, 0x22, // Start the SA-1
+        0xAD, 0x00, 0x23, // LDA $2300
+        0x8D, 0x10, 0x00, // STA $0010
+        0x80, 0xF8, // BRA -8
+    ];
+    let sa1_code = [
+        0x78, // SEI
+        0xA9, 0xFF, 0x8D, 0x2A, 0x22, // Enable writing to I-RAM
+        0xA9, 0x42, 0x8D, 0x00, 0x30, // STA $3000
+        0x9C, 0x50, 0x22, // Multiply $1234 by -2
+        0xA9, 0x34, 0x8D, 0x51, 0x22, // LDA #$34, STA $2251
+        0xA9, 0x12, 0x8D, 0x52, 0x22, // LDA #$12, STA $2252
+        0xA9, 0xFE, 0x8D, 0x53, 0x22, // LDA #$FE, STA $2253
+        0xA9, 0xFF, 0x8D, 0x54, 0x22, // LDA #$FF, STA $2254
+        0xAD, 0x06, 0x23, 0x8D, 0x01, 0x30, // Copy the result to I-RAM
+        0xAD, 0x07, 0x23, 0x8D, 0x02, 0x30, // LDA $2307, STA $3002
+        0xAD, 0x08, 0x23, 0x8D, 0x03, 0x30, // LDA $2308, STA $3003
+        0xAD, 0x09, 0x23, 0x8D, 0x04, 0x30, // LDA $2309, STA $3004
+        0xA9, 0x77, 0x8D, 0x05, 0x00, // I-RAM is also at $0000 for the SA-1
+        0xA9, 0x80, 0x8D, 0x27, 0x22, // Enable writing to BW-RAM
+        0xA9, 0x99, 0x8F, 0x10, 0x00, 0x40, // STA $400010
+        0xA9, 0x85, 0x8D, 0x09, 0x22, // Send an IRQ and message 5 to the SNES CPU
+        0x80, 0xFE, // BRA -2
+    ];
+    let mut c = Console::with_cartridge(&sa1_rom(0x10000, &snes_code, &sa1_code)).unwrap();
+    assert!(matches!(c.board(), Some(Board::Sa1(_))));
+    c.advance_instructions(100);
+    let Some(Board::Sa1(sa1)) = c.board() else {
+        panic!("Board should be an SA-1");
+    };
+    assert!(sa1.is_running());
+    assert_eq!(sa1.iram()[0], 0x42);
+    assert_eq!(sa1.iram()[1..5], [0x98, 0xDB, 0xFF, 0xFF]);
+    assert_eq!(sa1.iram()[5], 0x77);
+    assert!(sa1.irq());
+    // BW-RAM is the cartridge's SRAM
+    assert_eq!(c.sram()[0x10], 0x99);
+    assert!(c.take_sram_written());
+    // The SNES CPU sees the IRQ flag and the message
+    assert_eq!(c.ram()[0x10], 0x85);
+}
+
+#[test]
+fn test_sa1_mapping() {
+    let mut rom = sa1_rom(0x20_0000, &[0x78], &[]);
+    rom[0x10_0000] = 0x11;
+    rom[0x10_8000] = 0x22;
+    let mut cartridge = Cartridge::from_data(&rom).unwrap();
+    let mut sa1 = Sa1::default();
+    // Each LoROM area is fixed to its own 1MB block unless bit 7 of its register is set
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_8000), Some(0x78));
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x20_8000), Some(0x11));
+    sa1.write_byte(&mut cartridge, 0x00_2220, 0x01);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_8000), Some(0x78));
+    sa1.write_byte(&mut cartridge, 0x00_2220, 0x81);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_8000), Some(0x11));
+    // HiROM areas always use the registers
+    assert_eq!(sa1.read_byte(&mut cartridge, 0xD0_8000), Some(0x22));
+    // BW-RAM is only writable once enabled, and is mirrored into $6000-$7FFF
+    sa1.write_byte(&mut cartridge, 0x40_0002, 0x33);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x40_0002), Some(0x00));
+    sa1.write_byte(&mut cartridge, 0x00_2226, 0x80);
+    sa1.write_byte(&mut cartridge, 0x40_0002, 0x33);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_6002), Some(0x33));
+    // I-RAM is only writable once enabled
+    sa1.write_byte(&mut cartridge, 0x00_3000, 0x44);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_3000), Some(0x00));
+    sa1.write_byte(&mut cartridge, 0x00_2229, 0x01);
+    sa1.write_byte(&mut cartridge, 0x00_3000, 0x44);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_3000), Some(0x44));
+    // WRAM and the console's own registers aren't mapped to the SA-1
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x7E_0000), None);
+    assert_eq!(sa1.read_byte(&mut cartridge, 0x00_2100), None);
+}
+
+#[test]
+fn test_sa1_math() {
+    let sa1_code = [
+        // Multiply $1234 by -2, which is sign extended to 40 bits
+        write(0x2250, 0x00),
+        write(0x2251, 0x34),
+        write(0x2252, 0x12),
+        write(0x2253, 0xFE),
+        write(0x2254, 0xFF),
+        copy(0x2306, 0x3000),
+        copy(0x2307, 0x3001),
+        copy(0x2308, 0x3002),
+        copy(0x2309, 0x3003),
+        copy(0x230A, 0x3004),
+        // Divide -100 by 7, which gives a positive remainder
+        write(0x2250, 0x01),
+        write(0x2251, 0x9C),
+        write(0x2252, 0xFF),
+        write(0x2253, 0x07),
+        write(0x2254, 0x00),
+        copy(0x2306, 0x3010),
+        copy(0x2307, 0x3011),
+        copy(0x2308, 0x3012),
+        copy(0x2309, 0x3013),
+        // Cumulative sum of -2 * 3, which doesn't overflow
+        write(0x2250, 0x02),
+        write(0x2251, 0xFE),
+        write(0x2252, 0xFF),
+        write(0x2253, 0x03),
+        write(0x2254, 0x00),
+        copy(0x2306, 0x3020),
+        copy(0x2307, 0x3021),
+        copy(0x2308, 0x3022),
+        copy(0x2309, 0x3023),
+        copy(0x230A, 0x3024),
+        copy(0x230B, 0x3025),
+        // Add 4 * 3, which carries out of the 40 bit sum
+        write(0x2251, 0x04),
+        write(0x2252, 0x00),
+        write(0x2253, 0x03),
+        write(0x2254, 0x00),
+        copy(0x2306, 0x3030),
+        copy(0x2307, 0x3031),
+        copy(0x2308, 0x3032),
+        copy(0x2309, 0x3033),
+        copy(0x230A, 0x3034),
+        copy(0x230B, 0x3035),
+    ]
+    .concat();
+    let mut c = sa1_console(&sa1_code, &[]);
+    c.advance_instructions(1000);
+    let iram = iram(&c);
+    assert_eq!(iram[0x00..0x05], [0x98, 0xDB, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(iram[0x10..0x14], [0xF1, 0xFF, 0x05, 0x00]);
+    assert_eq!(iram[0x20..0x26], [0xFA, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+    assert_eq!(iram[0x30..0x36], [0x06, 0x00, 0x00, 0x00, 0x00, 0x80]);
+}
+
+#[test]
+fn test_sa1_dma() {
+    let sa1_code = [
+        // Copy 4 bytes from ROM at $00:8200 to I-RAM at $0010
+        write(0x2230, 0x80),
+        write(0x2232, 0x00),
+        write(0x2233, 0x82),
+        write(0x2234, 0x00),
+        write(0x2238, 0x04),
+        write(0x2239, 0x00),
+        write(0x2235, 0x10),
+        write(0x2236, 0x00),
+        // Then from I-RAM to BW-RAM at $20, which starts once the high byte of the destination is written
+        write(0x2230, 0x86),
+        write(0x2232, 0x10),
+        write(0x2233, 0x00),
+        write(0x2235, 0x20),
+        write(0x2236, 0x00),
+        copy(0x2301, 0x3000),
+        write(0x2237, 0x00),
+        copy(0x2301, 0x3001),
+    ]
+    .concat();
+    let mut c = sa1_console(&sa1_code, &[0x11, 0x22, 0x33, 0x44]);
+    c.advance_instructions(1000);
+    assert_eq!(iram(&c)[0x10..0x14], [0x11, 0x22, 0x33, 0x44]);
+    assert_eq!(c.sram()[0x20..0x24], [0x11, 0x22, 0x33, 0x44]);
+    // Each DMA raises the DMA IRQ flag
+    assert_eq!(iram(&c)[0] & 0x20, 0x20);
+    assert_eq!(iram(&c)[1] & 0x20, 0x20);
+}
+
+#[test]
+fn test_sa1_char_conversion() {
+    // Type 1, where the SNES CPU reads 2bpp characters converted from a bitmap in BW-RAM
+    let sa1_code = [
+        write(0x2230, 0xB0),
+        write(0x2231, 0x02),
+        write(0x2232, 0x00),
+        write(0x2233, 0x00),
+        write(0x2234, 0x00),
+        write(0x2235, 0x00),
+        write(0x2236, 0x00),
+    ]
+    .concat();
+    let mut c = sa1_console(&sa1_code, &[]);
+    // The first row of the bitmap is the pixels 1, 2, 3, 0, 1, 2, 3, 0
+    c.load_sram(&[0x39, 0x39]);
+    c.advance_instructions(1000);
+    let mut cartridge = c.cartridge().clone();
+    let Some(board) = c.board_mut() else {
+        panic!("Console should have a board");
+    };
+    assert_eq!(
+        board.read_byte(&mut cartridge, 0x00_2300).unwrap() & 0x20,
+        0x20
+    );
+    assert_eq!(board.read_byte(&mut cartridge, 0x40_0000), Some(0xAA));
+    assert_eq!(board.read_byte(&mut cartridge, 0x40_0001), Some(0x66));
+    // Ending the conversion shows BW-RAM again
+    board.write_byte(&mut cartridge, 0x00_2231, 0x80);
+    assert_eq!(board.read_byte(&mut cartridge, 0x40_0000), Some(0x39));
+
+    // Type 2, where the SA-1 CPU writes the bitmap a row at a time into the bitmap register file
+    let rows = [[1, 2, 3, 0, 1, 2, 3, 0], [3, 3, 3, 3, 0, 0, 0, 0]];
+    let mut sa1_code = [
+        write(0x2230, 0xA0),
+        write(0x2231, 0x02),
+        write(0x2235, 0x00),
+        write(0x2236, 0x01),
+    ]
+    .concat();
+    rows.iter().flatten().enumerate().for_each(|(i, pixel)| {
+        sa1_code.extend(write(0x2240 + i as u16, *pixel));
+    });
+    let mut c = sa1_console(&sa1_code, &[]);
+    c.advance_instructions(1000);
+    assert_eq!(iram(&c)[0x100..0x104], [0xAA, 0x66, 0xF0, 0xF0]);
+}
+
+#[test]
+fn test_sa1_timer() {
+    let sa1_code = [
+        // Trigger an IRQ at dot 16 of every line, counting from the start of this line
+        write(0x2212, 0x10),
+        write(0x2213, 0x00),
+        write(0x2210, 0x01),
+        write(0x220A, 0x40),
+        write(0x2211, 0x00),
+        vec![0x58], // CLI
+    ]
+    .concat();
+    let mut rom = sa1_rom(
+        0x10000,
+        &START_SA1,
+        &[
+            &[0x78],
+            write(0x222A, 0xFF).as_slice(),
+            &sa1_code,
+            &[0x80, 0xFE],
+        ]
+        .concat(),
+    );
+    // Count the IRQs, and keep the line the last one was on
+    let irq_handler = [
+        vec![0xEE, 0x00, 0x30], // INC $3000
+        copy(0x2304, 0x3001),
+        write(0x220B, 0x40),
+        vec![0x40], // RTI
+    ]
+    .concat();
+    rom[0x180..0x180 + irq_handler.len()].copy_from_slice(&irq_handler);
+    let mut c = Console::with_cartridge(&rom).unwrap();
+    c.advance_instructions(2000);
+    let iram = iram(&c);
+    assert!(iram[0] > 2);
+    assert_eq!(iram[0], iram[1] + 1);
+}
+
+#[test]
+fn test_sa1_variable_length_data() {
+    let sa1_code = [
+        // Start reading at $00:8200
+        write(0x2259, 0x00),
+        write(0x225A, 0x82),
+        write(0x225B, 0x00),
+        copy(0x230C, 0x3000),
+        copy(0x230D, 0x3001),
+        // In fixed mode, writing the length advances the data
+        write(0x2258, 0x04),
+        copy(0x230C, 0x3002),
+        copy(0x230D, 0x3003),
+        // In auto mode, reading the high byte advances the data
+        write(0x2258, 0x88),
+        copy(0x230C, 0x3004),
+        copy(0x230D, 0x3005),
+        copy(0x230C, 0x3006),
+        copy(0x230D, 0x3007),
+    ]
+    .concat();
+    let mut c = sa1_console(&sa1_code, &[0x34, 0x12, 0xCD, 0xAB]);
+    c.advance_instructions(1000);
+    assert_eq!(
+        iram(&c)[0..8],
+        [0x34, 0x12, 0x23, 0xD1, 0x23, 0xD1, 0xD1, 0xBC]
+    );
+}
@@ -0,0 +1,102 @@
+use super_yane::{
+    Console,
+    apu::{SPC_FILE_SIZE, SPC_MAGIC, SpcError, SpcFile},
+};
+
+/// Build a small SPC dump with a text ID666 tag
+fn test_spc() -> Vec<u8> {
+    let mut data = vec![0u8; SPC_FILE_SIZE];
+    data[0..SPC_MAGIC.len()].copy_from_slice(SPC_MAGIC);
+    data[0x21] = 26;
+    data[0x22] = 26;
+    data[0x23] = 26;
+    data[0x24] = 30;
+    // Registers
+    data[0x25..0x27].copy_from_slice(&0x1234u16.to_le_bytes());
+    data[0x27] = 0xAA;
+    data[0x28] = 0xBB;
+    data[0x29] = 0xCC;
+    data[0x2A] = 0x02;
+    data[0x2B] = 0xEF;
+    // ID666 tag
+    data[0x2E..0x2E + 10].copy_from_slice(b"Test Song ");
+    data[0x4E..0x4E + 9].copy_from_slice(b"Test Game");
+    data[0x9E..0xA9].copy_from_slice(b"10/18/2026\0");
+    data[0xA9..0xAC].copy_from_slice(b"120");
+    data[0xAC..0xB1].copy_from_slice(b"10000");
+    data[0xB1..0xB1 + 6].copy_from_slice(b"Artist");
+    // RAM and IO registers
+    data[0x100 + 0x0200] = 0x5A;
+    // Timer 0 enabled, IPL ROM hidden
+    data[0x100 + 0xF1] = 0x01;
+    data[0x100 + 0xF4..0x100 + 0xF8].copy_from_slice(&[1, 2, 3, 4]);
+    data[0x100 + 0xFA] = 0x40;
+    data[0x100 + 0xFFC0] = 0x77;
+    // DSP registers
+    data[0x10100 + 0x0C] = 0x7F;
+    data[0x10100 + 0x5D] = 0x12;
+    data
+}
+
+#[test]
+fn test_spc_parse() {
+    let spc = SpcFile::from_bytes(&test_spc()).unwrap();
+    assert_eq!(spc.core.pc, 0x1234);
+    assert_eq!(spc.core.a, 0xAA);
+    assert_eq!(spc.core.x, 0xBB);
+    assert_eq!(spc.core.y, 0xCC);
+    assert!(spc.core.psw.z);
+    assert_eq!(spc.core.sp, 0xEF);
+    let tag = spc.tag.unwrap();
+    assert_eq!(tag.song_title, "Test Song");
+    assert_eq!(tag.game_title, "Test Game");
+    assert_eq!(tag.date, "10/18/2026");
+    assert_eq!(tag.length_seconds, 120);
+    assert_eq!(tag.fade_ms, 10000);
+    assert_eq!(tag.artist, "Artist");
+}
+
+#[test]
+fn test_spc_load() {
+    let spc = SpcFile::from_bytes(&test_spc()).unwrap();
+    let mut c = Console::with_cartridge(include_bytes!("./roms/CPUADC.sfc")).unwrap();
+    c.load_spc(&spc);
+    let apu = c.apu();
+    assert_eq!(apu.core.pc, 0x1234);
+    assert_eq!(apu.read_ram(0x0200), 0x5A);
+    assert_eq!(apu.read_ram(0xFFC0), 0x77);
+    assert!(apu.rest.timers[0].enabled);
+    assert_eq!(apu.rest.timers[0].target, 0x40);
+    assert_eq!(*c.cpu_to_apu_reg(), [1, 2, 3, 4]);
+    assert_eq!(apu.dsp().volume[0], 0x7F);
+    assert_eq!(apu.dsp().sample_dir, 0x1200);
+}
+
+#[test]
+fn test_spc_invalid() {
+    assert!(matches!(
+        SpcFile::from_bytes(&[0; 0x100]),
+        Err(SpcError::TooSmall(0x100))
+    ));
+    assert!(matches!(
+        SpcFile::from_bytes(&vec![0; SPC_FILE_SIZE]),
+        Err(SpcError::InvalidHeader)
+    ));
+}
+
+#[test]
+fn test_spc_round_trip() {
+    let mut c = Console::with_cartridge(include_bytes!("./roms/CPUADC.sfc")).unwrap();
+    c.load_spc(&SpcFile::from_bytes(&test_spc()).unwrap());
+    let spc = SpcFile::from_bytes(&c.save_spc().to_bytes()).unwrap();
+    assert_eq!(spc.core.pc, 0x1234);
+    assert_eq!(spc.core.psw.to_byte(), 0x02);
+    assert_eq!(spc.ram[0x0200], 0x5A);
+    assert_eq!(spc.ram[0xF1], 0x01);
+    assert_eq!(spc.ram[0xF4..0xF8], [1, 2, 3, 4]);
+    assert_eq!(spc.ram[0xFA], 0x40);
+    assert_eq!(spc.extra_ram[0], 0x77);
+    assert_eq!(spc.dsp_registers[0x0C], 0x7F);
+    assert_eq!(spc.dsp_registers[0x5D], 0x12);
+    assert_eq!(spc.tag.unwrap().game_title, c.cartridge().title().trim());
+}
@@ -0,0 +1,287 @@
+use super_yane::{
+    Cartridge, Console,
+    cartridge::{Board, CoprocessorBoard, SuperFx},
+};
+
+mod common;
+use common::coprocessor_rom;
+
+/// Map mode, chipset (Super FX with RAM and battery), ROM size and SRAM size (128KB, which the GSU uses as its RAM)
+const HEADER: [u8; 4] = [0x20, 0x1A, 0x0A, 0x07];
+
+/// Build a cartridge with `gsu_code` at $00:8100 and `data` at $01:8000, and the GSU for it
+fn super_fx(gsu_code: &[u8], data: &[u8]) -> (SuperFx, Cartridge) {
+    let mut rom = coprocessor_rom(0x20000, "SUPER FX TEST", HEADER, &[], gsu_code);
+    rom[0x8000..0x8000 + data.len()].copy_from_slice(data);
+    let cartridge = Cartridge::from_data(&rom).unwrap();
+    (SuperFx::for_cartridge(&cartridge), cartridge)
+}
+
+/// Start the GSU at $8100 and run it until it stops, returning how many master clocks that took
+fn run(gsu: &mut SuperFx, cartridge: &mut Cartridge) -> u32 {
+    gsu.write_byte(cartridge, 0x00_301E, 0x00);
+    gsu.write_byte(cartridge, 0x00_301F, 0x81);
+    let mut clocks = 0;
+    while gsu.is_running() {
+        assert!(clocks < 100_000, "GSU should have stopped");
+        gsu.advance(cartridge, 1);
+        clocks += 1;
+    }
+    clocks
+}
+
+#[test]
+fn test_super_fx_console() {
+    let snes_code = [
+        0x78, // SEI
+        0xA9, 0x09, 0x8D, 0x3A, 0x30, // Give the GSU access to RAM, and plot in 4bpp
+        0xA9, 0x00, 0x8D, 0x1E, 0x30, // Start the GSU at $8100
+        0xA9, 0x81, 0x8D, 0x1F, 0x30, // LDA #$81, STA $301F
+        0x80, 0xFE, // BRA -2
+    ];
+    let gsu_code = [
+        0xA0, 0x12, // IBT R0, #$12
+        0xF1, 0x56, 0x34, // IWT R1, #$3456
+        0x51, // ADD R1
+        0x3E, 0xF0, 0x00, 0x01, // SM ($0100), R0
+        0xAC, 0x03, // IBT R12, #3
+        0xA2, 0x00, // IBT R2, #0
+        0x2F, 0x1D, // MOVE R13, R15
+        0xD2, // INC R2
+        0x3C, // LOOP
+        0x01, // NOP, which runs after every jump
+        0x3E, 0xF2, 0x02, 0x01, // SM ($0102), R2
+        0x05, 0x02, // BRA +2
+        0x01, // NOP, which runs before the branch is taken
+        0xD4, // INC R4, which is skipped
+        0xD5, // INC R5
+        0xA1, 0x00, // IBT R1, #0
+        0xA2, 0x00, // IBT R2, #0
+        0xA0, 0x05, // IBT R0, #5
+        0x4E, // COLOR
+        0x4C, // PLOT
+        0x4C, // PLOT
+        0xA1, 0x01, // IBT R1, #1
+        0x3D, 0x4C, // RPIX
+        0x00, // STOP
+        0x01, // NOP
+    ];
+    // Without SRAM, the GSU has its own RAM
+    let header = [0x20, 0x14, 0x0A, 0x00];
+    let rom = coprocessor_rom(0x10000, "SUPER FX TEST", header, &snes_code, &gsu_code);
+    let mut c = Console::with_cartridge(&rom).unwrap();
+    assert!(matches!(c.board(), Some(Board::SuperFx(_))));
+    c.advance_instructions(200);
+    let Some(Board::SuperFx(gsu)) = c.board() else {
+        panic!("Board should be a Super FX");
+    };
+    assert!(!gsu.is_running());
+    assert!(gsu.irq());
+    assert_eq!(gsu.ram()[0x100..0x104], [0x68, 0x34, 0x03, 0x00]);
+    assert_eq!(gsu.registers()[4], 0);
+    assert_eq!(gsu.registers()[5], 1);
+    // Both pixels are colour 5, so are in bitplanes 0 and 2
+    assert_eq!(gsu.ram()[0x00..0x02], [0xC0, 0x00]);
+    assert_eq!(gsu.ram()[0x10..0x12], [0xC0, 0x00]);
+    assert_eq!(gsu.registers()[0], 5);
+}
+
+#[test]
+fn test_super_fx_mapping() {
+    let (mut gsu, mut cartridge) = super_fx(&[0x00, 0x01], &[0x11]);
+    // ROM is LoROM in banks $00-$3F and HiROM in banks $40-$5F
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_8100), Some(0x00));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x01_8000), Some(0x11));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x40_8000), Some(0x11));
+    // Game Pak RAM is in banks $70-$71, and its start is mirrored into $6000-$7FFF
+    gsu.write_byte(&mut cartridge, 0x70_0002, 0x33);
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_6002), Some(0x33));
+    assert_eq!(cartridge.sram()[0x0002], 0x33);
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303B), Some(0x04));
+    // The cache can be written by the SNES CPU
+    gsu.write_byte(&mut cartridge, 0x00_3105, 0x44);
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_3105), Some(0x44));
+    // While the GSU is running with access to ROM and RAM, the SNES CPU can't see them
+    gsu.write_byte(&mut cartridge, 0x00_303A, 0x18);
+    gsu.write_byte(&mut cartridge, 0x00_301E, 0x00);
+    gsu.write_byte(&mut cartridge, 0x00_301F, 0x81);
+    assert!(gsu.is_running());
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x01_8000), Some(0x00));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x70_0002), Some(0x00));
+    gsu.advance(&mut cartridge, 100);
+    assert!(!gsu.is_running());
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x01_8000), Some(0x11));
+    // Reading the high byte of SFR acknowledges the IRQ
+    assert!(gsu.irq());
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_3031), Some(0x80));
+    assert!(!gsu.irq());
+    // WRAM and the console's own registers aren't mapped to the Super FX
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x7E_0000), None);
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_2100), None);
+}
+
+#[test]
+fn test_super_fx_alt_instructions() {
+    let gsu_code = [
+        0xF3, 0x78, 0x56, // IWT R3, #$5678
+        0x3E, 0xA3, 0x08, // SMS ($0010), R3
+        0x3D, 0xA4, 0x08, // LMS R4, ($0010)
+        0xA0, 0x01, // IBT R0, #1
+        0x3F, 0xDF, // ROMB, so R14 reads from bank 1
+        0x3E, 0xDF, // RAMB, so loads and stores use bank 1
+        0x3E, 0xA3, 0x08, // SMS ($0010), R3
+        0xFE, 0x00, 0x80, // IWT R14, #$8000, which reads $01:8000 into the ROM buffer
+        0x15, 0xEF, // GETB into R5
+        0xF6, 0x34, 0x12, // IWT R6, #$1234
+        0x26, 0x3D, 0xEF, // GETBH into R6
+        0xF7, 0x34, 0x12, // IWT R7, #$1234
+        0x27, 0x3E, 0xEF, // GETBL into R7
+        0x18, 0x3F, 0xEF, // GETBS into R8
+        0xA9, 0x01, // IBT R9, #1
+        0xFA, 0x10, 0x80, // IWT R10, #$8010
+        0xBA, 0x3D, 0x99, // LJMP R9 to $01:8010
+        0x01, // NOP, which runs before the jump
+        0xDB, // INC R11, which is skipped
+    ];
+    let data = [
+        &[0x80; 0x10][..],
+        &[
+            0xAB, 0x77, // IBT R11, #$77
+            0x00, // STOP
+            0x01, // NOP
+        ],
+    ]
+    .concat();
+    let (mut gsu, mut cartridge) = super_fx(&gsu_code, &data);
+    run(&mut gsu, &mut cartridge);
+    let r = gsu.registers();
+    assert_eq!(r[4], 0x5678);
+    assert_eq!(cartridge.sram()[0x0_0010..0x0_0012], [0x78, 0x56]);
+    assert_eq!(cartridge.sram()[0x1_0010..0x1_0012], [0x78, 0x56]);
+    assert_eq!(r[5..9], [0x0080, 0x8034, 0x1280, 0xFF80]);
+    assert_eq!(r[11], 0x77);
+    // PBR, ROMBR and RAMBR
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_3034), Some(0x01));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_3036), Some(0x01));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303C), Some(0x01));
+    // LJMP moves the cache to the address jumped to
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303E), Some(0x10));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303F), Some(0x80));
+}
+
+/// GSU code that increments R2 100 times in a loop and then stops
+fn count_loop(first: u8) -> [u8; 12] {
+    [
+        0x01, 0x01, 0x01,  // NOP
+        first, // CACHE, or NOP
+        0xAC, 100, // IBT R12, #100
+        0x2F, 0x1D, // MOVE R13, R15
+        0xD2, // INC R2
+        0x3C, // LOOP
+        0x01, // NOP
+        0x00, // STOP
+    ]
+}
+
+#[test]
+fn test_super_fx_cache() {
+    // Run the loop from ROM
+    let (mut gsu, mut cartridge) = super_fx(&count_loop(0x01), &[]);
+    let rom_clocks = run(&mut gsu, &mut cartridge);
+    assert_eq!(gsu.registers()[2], 100);
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303E), Some(0x00));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303F), Some(0x00));
+
+    // CACHE at $8103 caches from $8100, and the loop runs faster once the cache is filled
+    let (mut gsu, mut cartridge) = super_fx(&count_loop(0x02), &[]);
+    let cache_clocks = run(&mut gsu, &mut cartridge);
+    assert_eq!(gsu.registers()[2], 100);
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303E), Some(0x00));
+    assert_eq!(gsu.read_byte(&mut cartridge, 0x00_303F), Some(0x81));
+    assert!(cache_clocks * 2 < rom_clocks);
+}
+
+#[test]
+fn test_super_fx_clock_select() {
+    let (mut gsu, mut cartridge) = super_fx(&count_loop(0x01), &[]);
+    let slow_clocks = run(&mut gsu, &mut cartridge);
+    // CLSR runs the GSU at 21.48MHz instead of 10.74MHz
+    let (mut gsu, mut cartridge) = super_fx(&count_loop(0x01), &[]);
+    gsu.write_byte(&mut cartridge, 0x00_3039, 0x01);
+    let fast_clocks = run(&mut gsu, &mut cartridge);
+    assert_eq!(gsu.registers()[2], 100);
+    assert!(fast_clocks.abs_diff(slow_clocks / 2) <= 1);
+}
+
+#[test]
+fn test_super_fx_plot_modes() {
+    let gsu_code = [
+        0xA0, 0x02, // IBT R0, #2
+        0x3D, 0x4E, // CMODE, to dither
+        0xA0, 0x35, // IBT R0, #$35
+        0x4E, // COLOR
+        0xA1, 0x00, // IBT R1, #0
+        0xA2, 0x00, // IBT R2, #0
+        0x4C, // PLOT, with the low nibble of the colour
+        0x4C, // PLOT, with the high nibble of the colour
+        0xA1, 0x00, // IBT R1, #0
+        0x13, 0x3D, 0x4C, // RPIX into R3
+        0xA1, 0x01, // IBT R1, #1
+        0x14, 0x3D, 0x4C, // RPIX into R4
+        0xA0, 0x10, // IBT R0, #$10
+        0x3D, 0x4E, // CMODE, to plot in the OBJ layout
+        0xA0, 0x01, // IBT R0, #1
+        0x4E, // COLOR
+        0xA1, 0x08, // IBT R1, #8
+        0x4C, // PLOT
+        0xE1, // DEC R1
+        0x15, 0x3D, 0x4C, // RPIX into R5
+        0x00, // STOP
+        0x01, // NOP
+    ];
+    let (mut gsu, mut cartridge) = super_fx(&gsu_code, &[]);
+    // Plot in 4bpp
+    gsu.write_byte(&mut cartridge, 0x00_303A, 0x01);
+    run(&mut gsu, &mut cartridge);
+    assert_eq!(gsu.registers()[3..6], [0x05, 0x03, 0x01]);
+    // Colour 5 and then colour 3
+    assert_eq!(cartridge.sram()[0x00..0x02], [0xC0, 0x40]);
+    assert_eq!(cartridge.sram()[0x10..0x12], [0x80, 0x00]);
+    // In the OBJ layout, the character to the right is the next one rather than the one 16 characters on
+    assert_eq!(cartridge.sram()[0x20], 0x80);
+    assert_eq!(cartridge.sram()[0x200], 0x00);
+}
+
+#[test]
+fn test_super_fx_ram_write_buffer() {
+    // Run from the cache, so that each instruction is quicker than a RAM write
+    let code = |first: [u8; 2], second: [u8; 2]| {
+        [
+            &[0x02][..],   // CACHE
+            &[0xA0, 0x42], // IBT R0, #$42
+            &[0xA3, 0x10], // IBT R3, #$10
+            &first,
+            &second,
+            &[0x00, 0x01], // STOP, NOP
+        ]
+        .concat()
+    };
+    const STB: [u8; 2] = [0x3D, 0x33];
+    const LDB: [u8; 2] = [0x3D, 0x43];
+    const NOPS: [u8; 2] = [0x01, 0x01];
+    let run_code = |first, second| {
+        let (mut gsu, mut cartridge) = super_fx(&code(first, second), &[]);
+        let clocks = run(&mut gsu, &mut cartridge);
+        (clocks, gsu, cartridge)
+    };
+    // The GSU carries on while the byte is written
+    let (store, _, cartridge) = run_code(STB, NOPS);
+    let (nothing, _, _) = run_code(NOPS, NOPS);
+    assert_eq!(store, nothing);
+    assert_eq!(cartridge.sram()[0x10], 0x42);
+    // But has to wait for it to be written before it can access RAM again
+    let (store_load, gsu, _) = run_code(STB, LDB);
+    let (load, _, _) = run_code(NOPS, LDB);
+    assert!(store_load > load);
+    assert_eq!(gsu.registers()[0], 0x42);
+}
@@ -4,6 +4,24 @@ use serde::{Deserialize, Serialize};
 
 use crate::{ProgramStatusWord, opcodes::*};
 
+/// Why the processor has stopped executing instructions
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HaltState {
+    /// Halted by the SLEEP instruction
+    Sleep,
+    /// Halted by the STOP instruction
+    Stop,
+}
+
+impl Display for HaltState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltState::Sleep => write!(f, "SLEEP"),
+            HaltState::Stop => write!(f, "STOP"),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Processor {
     pub a: u8,
@@ -12,6 +30,10 @@ pub struct Processor {
     pub sp: u8,
     pub pc: u16,
     pub psw: ProgramStatusWord,
+    /// Set once the processor has executed SLEEP or STOP.
+    /// Only a reset will start it again, since nothing on the SNES can wake it.
+    #[serde(default)]
+    pub halted: Option<HaltState>,
 }
 
 pub trait HasAddressBus {
@@ -272,6 +294,11 @@ impl Processor {
         (value & (0x01 << bit)) != 0
     }
     pub fn step(&mut self, bus: &mut impl HasAddressBus) {
+        // While halted, just let time pass
+        if self.halted.is_some() {
+            bus.io();
+            return;
+        }
         // Read opcode
         let opcode = bus.read(self.pc as usize);
         self.pc = self.pc.wrapping_add(1);
@@ -820,12 +847,21 @@ impl Processor {
                 self.a = self.a.rotate_left(4);
                 self.set_nz(self.a);
             }
+            SLEEP => {
+                bus.io();
+                self.halted = Some(HaltState::Sleep);
+            }
+            STOP => {
+                bus.io();
+                self.halted = Some(HaltState::Stop);
+            }
             _ => panic!("Unimplemented SPC700 opcode: {:2X}", opcode),
         }
     }
     pub fn reset(&mut self) {
         self.pc = 0xFFC0;
-        self.psw = ProgramStatusWord::default()
+        self.psw = ProgramStatusWord::default();
+        self.halted = None;
     }
 }
 
@@ -838,6 +874,7 @@ impl Default for Processor {
             sp: 0,
             pc: 0xFFC0,
             psw: ProgramStatusWord::default(),
+            halted: None,
         }
     }
 }